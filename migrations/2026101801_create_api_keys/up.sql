@@ -0,0 +1,9 @@
+-- Create api_keys table (keys are stored as keccak256 hashes, never in plain text)
+CREATE TABLE api_keys (
+    key_hash TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    rate_limit_per_minute INTEGER NOT NULL DEFAULT 120,
+    max_connections INTEGER NOT NULL DEFAULT 5,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at DATETIME NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Create webhook_deliveries table (one row per alert, updated after every attempt)
+CREATE TABLE webhook_deliveries (
+    id TEXT PRIMARY KEY,
+    rule_name TEXT NOT NULL,
+    url TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    response_status INTEGER,
+    last_error TEXT,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+
+CREATE INDEX idx_webhook_deliveries_rule ON webhook_deliveries(rule_name);
+CREATE INDEX idx_webhook_deliveries_status ON webhook_deliveries(status);
+CREATE INDEX idx_webhook_deliveries_created_at ON webhook_deliveries(created_at);
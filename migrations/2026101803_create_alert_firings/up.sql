@@ -0,0 +1,14 @@
+-- Create alert_firings table (history of alerts sent, also used to restore cooldowns on restart)
+CREATE TABLE alert_firings (
+    id TEXT PRIMARY KEY,
+    rule_name TEXT NOT NULL,
+    dedup_key TEXT NOT NULL,
+    summary TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    transfer_id TEXT NOT NULL,
+    fired_at DATETIME NOT NULL
+);
+
+CREATE INDEX idx_alert_firings_rule ON alert_firings(rule_name);
+CREATE INDEX idx_alert_firings_dedup_key ON alert_firings(dedup_key);
+CREATE INDEX idx_alert_firings_fired_at ON alert_firings(fired_at);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_transfers_watched;
+ALTER TABLE transfers DROP COLUMN is_watched;
+DROP TABLE IF EXISTS watchlist_addresses;
+DROP TABLE IF EXISTS watchlists;
@@ -0,0 +1,22 @@
+-- Create watchlists of addresses followed by analysts
+CREATE TABLE watchlists (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+
+CREATE TABLE watchlist_addresses (
+    watchlist_id TEXT NOT NULL REFERENCES watchlists(id) ON DELETE CASCADE,
+    address TEXT NOT NULL,
+    label TEXT,
+    added_at DATETIME NOT NULL,
+    PRIMARY KEY (watchlist_id, address)
+);
+
+CREATE INDEX idx_watchlist_addresses_address ON watchlist_addresses(address);
+
+-- Flag transfers touching a watched address
+ALTER TABLE transfers ADD COLUMN is_watched BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE INDEX idx_transfers_watched ON transfers(is_watched);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_event_outbox_created_at;
+DROP TABLE IF EXISTS event_outbox;
+DROP TABLE IF EXISTS indexer_state;
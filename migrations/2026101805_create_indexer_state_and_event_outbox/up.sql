@@ -0,0 +1,19 @@
+-- Indexer progress per chain, written by the indexer process and read by API nodes
+CREATE TABLE indexer_state (
+    chain_id BIGINT PRIMARY KEY,
+    current_block BIGINT NOT NULL,
+    chain_head BIGINT NOT NULL,
+    is_running BOOLEAN NOT NULL,
+    last_block_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+
+-- Live events handed from the indexer to API nodes in other processes
+CREATE TABLE event_outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+CREATE INDEX idx_event_outbox_created_at ON event_outbox(created_at);
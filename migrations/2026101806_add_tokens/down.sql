@@ -0,0 +1,24 @@
+-- Only POL flows fit the single-token layout; other tokens' flows are dropped
+CREATE TABLE net_flows_single_token (
+    address TEXT PRIMARY KEY,
+    net_flow TEXT NOT NULL DEFAULT '0',
+    inflow TEXT NOT NULL DEFAULT '0',
+    outflow TEXT NOT NULL DEFAULT '0',
+    transfer_count BIGINT NOT NULL DEFAULT 0,
+    last_updated DATETIME NOT NULL
+);
+
+INSERT INTO net_flows_single_token (address, net_flow, inflow, outflow, transfer_count, last_updated)
+SELECT address, net_flow, inflow, outflow, transfer_count, last_updated
+FROM net_flows
+WHERE token = '0x0000000000000000000000000000000000001010';
+
+DROP TABLE net_flows;
+ALTER TABLE net_flows_single_token RENAME TO net_flows;
+CREATE INDEX idx_net_flows_last_updated ON net_flows(last_updated);
+
+DELETE FROM transfers WHERE token <> '0x0000000000000000000000000000000000001010';
+DROP INDEX IF EXISTS idx_transfers_token;
+ALTER TABLE transfers DROP COLUMN token;
+
+DROP TABLE IF EXISTS tokens;
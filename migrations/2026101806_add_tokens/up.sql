@@ -0,0 +1,35 @@
+-- Tokens the indexer has seen, with the metadata used to scale raw amounts
+CREATE TABLE tokens (
+    address TEXT PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    decimals INTEGER NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+
+-- Everything indexed so far is POL. Databases indexed with a different POL_CONTRACT are
+-- re-tagged with it in the same transaction by `run_migrations` (src/database.rs).
+INSERT INTO tokens (address, symbol, decimals, updated_at)
+VALUES ('0x0000000000000000000000000000000000001010', 'POL', 18, CURRENT_TIMESTAMP);
+
+ALTER TABLE transfers ADD COLUMN token TEXT NOT NULL DEFAULT '0x0000000000000000000000000000000000001010';
+CREATE INDEX idx_transfers_token ON transfers(token);
+
+-- Net flows are kept per (address, token); SQLite can't change a primary key in place
+CREATE TABLE net_flows_by_token (
+    address TEXT NOT NULL,
+    token TEXT NOT NULL,
+    net_flow TEXT NOT NULL DEFAULT '0',
+    inflow TEXT NOT NULL DEFAULT '0',
+    outflow TEXT NOT NULL DEFAULT '0',
+    transfer_count BIGINT NOT NULL DEFAULT 0,
+    last_updated DATETIME NOT NULL,
+    PRIMARY KEY (address, token)
+);
+
+INSERT INTO net_flows_by_token (address, token, net_flow, inflow, outflow, transfer_count, last_updated)
+SELECT address, '0x0000000000000000000000000000000000001010', net_flow, inflow, outflow, transfer_count, last_updated
+FROM net_flows;
+
+DROP TABLE net_flows;
+ALTER TABLE net_flows_by_token RENAME TO net_flows;
+CREATE INDEX idx_net_flows_last_updated ON net_flows(last_updated);
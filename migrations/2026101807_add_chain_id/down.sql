@@ -0,0 +1,46 @@
+-- Only Polygon PoS data fits the single-chain layout; other chains are dropped
+DELETE FROM indexer_state WHERE chain_id <> 137;
+
+CREATE TABLE tokens_single_chain (
+    address TEXT PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    decimals INTEGER NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+
+INSERT INTO tokens_single_chain (address, symbol, decimals, updated_at)
+SELECT address, symbol, decimals, updated_at
+FROM tokens
+WHERE chain_id = 137;
+
+DROP TABLE tokens;
+ALTER TABLE tokens_single_chain RENAME TO tokens;
+
+CREATE TABLE net_flows_single_chain (
+    address TEXT NOT NULL,
+    token TEXT NOT NULL,
+    net_flow TEXT NOT NULL DEFAULT '0',
+    inflow TEXT NOT NULL DEFAULT '0',
+    outflow TEXT NOT NULL DEFAULT '0',
+    transfer_count BIGINT NOT NULL DEFAULT 0,
+    last_updated DATETIME NOT NULL,
+    PRIMARY KEY (address, token)
+);
+
+INSERT INTO net_flows_single_chain (address, token, net_flow, inflow, outflow, transfer_count, last_updated)
+SELECT address, token, net_flow, inflow, outflow, transfer_count, last_updated
+FROM net_flows
+WHERE chain_id = 137;
+
+DROP TABLE net_flows;
+ALTER TABLE net_flows_single_chain RENAME TO net_flows;
+CREATE INDEX idx_net_flows_last_updated ON net_flows(last_updated);
+
+DELETE FROM alert_firings WHERE chain_id <> 137;
+UPDATE alert_firings SET transfer_id = substr(transfer_id, 5) WHERE transfer_id LIKE '137\_%' ESCAPE '\';
+ALTER TABLE alert_firings DROP COLUMN chain_id;
+
+DELETE FROM transfers WHERE chain_id <> 137;
+UPDATE transfers SET id = substr(id, 5) WHERE id LIKE '137\_%' ESCAPE '\';
+DROP INDEX IF EXISTS idx_transfers_chain_block;
+ALTER TABLE transfers DROP COLUMN chain_id;
@@ -0,0 +1,48 @@
+-- Everything indexed so far is from Polygon PoS (chain 137)
+ALTER TABLE transfers ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 137;
+CREATE INDEX idx_transfers_chain_block ON transfers(chain_id, block_number);
+
+-- Transfer ids are qualified with the chain, since a transaction hash alone
+-- doesn't identify a transaction across chains
+UPDATE transfers SET id = '137_' || id;
+
+ALTER TABLE alert_firings ADD COLUMN chain_id BIGINT NOT NULL DEFAULT 137;
+UPDATE alert_firings SET transfer_id = '137_' || transfer_id;
+
+-- Net flows are kept per (chain, address, token)
+CREATE TABLE net_flows_by_chain (
+    chain_id BIGINT NOT NULL,
+    address TEXT NOT NULL,
+    token TEXT NOT NULL,
+    net_flow TEXT NOT NULL DEFAULT '0',
+    inflow TEXT NOT NULL DEFAULT '0',
+    outflow TEXT NOT NULL DEFAULT '0',
+    transfer_count BIGINT NOT NULL DEFAULT 0,
+    last_updated DATETIME NOT NULL,
+    PRIMARY KEY (chain_id, address, token)
+);
+
+INSERT INTO net_flows_by_chain (chain_id, address, token, net_flow, inflow, outflow, transfer_count, last_updated)
+SELECT 137, address, token, net_flow, inflow, outflow, transfer_count, last_updated
+FROM net_flows;
+
+DROP TABLE net_flows;
+ALTER TABLE net_flows_by_chain RENAME TO net_flows;
+CREATE INDEX idx_net_flows_last_updated ON net_flows(last_updated);
+
+-- The same contract address can hold different tokens on different chains
+CREATE TABLE tokens_by_chain (
+    chain_id BIGINT NOT NULL,
+    address TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    decimals INTEGER NOT NULL,
+    updated_at DATETIME NOT NULL,
+    PRIMARY KEY (chain_id, address)
+);
+
+INSERT INTO tokens_by_chain (chain_id, address, symbol, decimals, updated_at)
+SELECT 137, address, symbol, decimals, updated_at
+FROM tokens;
+
+DROP TABLE tokens;
+ALTER TABLE tokens_by_chain RENAME TO tokens;
@@ -0,0 +1,17 @@
+-- Token balance per address, derived from indexed transfers. Transfers stored
+-- before this migration aren't replayed into it; seed the addresses that
+-- matter with `balances seed` to start them from on-chain balanceOf.
+CREATE TABLE balances (
+    chain_id BIGINT NOT NULL,
+    address TEXT NOT NULL,
+    token TEXT NOT NULL,
+    balance TEXT NOT NULL DEFAULT '0',
+    transfer_count BIGINT NOT NULL DEFAULT 0,
+    -- Block whose balanceOf the row was seeded from; transfers up to it are already included
+    seeded_block BIGINT,
+    last_block BIGINT NOT NULL,
+    last_updated DATETIME NOT NULL,
+    PRIMARY KEY (chain_id, address, token)
+);
+
+CREATE INDEX idx_balances_chain_token ON balances(chain_id, token);
@@ -0,0 +1 @@
+DROP TABLE net_flow_snapshots;
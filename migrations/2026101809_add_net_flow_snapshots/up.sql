@@ -0,0 +1,19 @@
+-- Net flow totals of every tracked address and token, computed from the transfer
+-- log every `snapshot_interval` blocks. As-of queries start from the latest
+-- snapshot before the requested point instead of summing all history.
+CREATE TABLE net_flow_snapshots (
+    chain_id BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    address TEXT NOT NULL,
+    token TEXT NOT NULL,
+    net_flow TEXT NOT NULL DEFAULT '0',
+    inflow TEXT NOT NULL DEFAULT '0',
+    outflow TEXT NOT NULL DEFAULT '0',
+    transfer_count BIGINT NOT NULL DEFAULT 0,
+    -- Time of the latest transfer counted; NULL while there is none
+    last_transfer_at DATETIME,
+    block_timestamp DATETIME NOT NULL,
+    PRIMARY KEY (chain_id, block_number, address, token)
+);
+
+CREATE INDEX idx_net_flow_snapshots_time ON net_flow_snapshots(chain_id, block_timestamp);
@@ -0,0 +1,9 @@
+-- Contiguous runs of blocks whose transfers are stored, live or backfilled;
+-- written in each block's transaction. Blocks indexed before this migration
+-- aren't known, so coverage starts with the next block stored.
+CREATE TABLE indexed_ranges (
+    chain_id BIGINT NOT NULL,
+    start_block BIGINT NOT NULL,
+    end_block BIGINT NOT NULL,
+    PRIMARY KEY (chain_id, start_block)
+);
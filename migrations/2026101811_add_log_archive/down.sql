@@ -0,0 +1,2 @@
+DROP TABLE raw_logs;
+DROP TABLE archived_blocks;
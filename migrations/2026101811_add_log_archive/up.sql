@@ -0,0 +1,24 @@
+-- Raw logs of the blocks indexed on chains with `archive_logs`, so they can be
+-- decoded again without the node. Hashes, addresses, topics and data are stored
+-- as raw bytes; `topics` is the topics' 32-byte words back to back.
+CREATE TABLE archived_blocks (
+    chain_id BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_hash BLOB NOT NULL,
+    block_timestamp DATETIME NOT NULL,
+    log_count BIGINT NOT NULL,
+    PRIMARY KEY (chain_id, block_number)
+) WITHOUT ROWID;
+
+CREATE TABLE raw_logs (
+    chain_id BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    -- Position of the log in the block
+    log_index BIGINT NOT NULL,
+    transaction_index BIGINT NOT NULL,
+    transaction_hash BLOB NOT NULL,
+    address BLOB NOT NULL,
+    topics BLOB NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (chain_id, block_number, log_index)
+) WITHOUT ROWID;
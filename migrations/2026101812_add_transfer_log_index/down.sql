@@ -0,0 +1,4 @@
+-- Ids already numbered by block log index are kept
+DROP INDEX IF EXISTS idx_transfers_chain_block_log;
+ALTER TABLE transfers DROP COLUMN transaction_index;
+ALTER TABLE transfers DROP COLUMN log_index;
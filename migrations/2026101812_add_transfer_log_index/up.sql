@@ -0,0 +1,8 @@
+-- Position of each transfer's log in its block and of its transaction in the
+-- block, as the node reports them. Transfers stored before this migration are
+-- left NULL and keep ids numbered within their transaction; indexing their
+-- blocks again (`backfill`, or `backfill --from-archive`) fills both in and
+-- moves them to ids numbered by block log index.
+ALTER TABLE transfers ADD COLUMN log_index BIGINT;
+ALTER TABLE transfers ADD COLUMN transaction_index BIGINT;
+CREATE UNIQUE INDEX idx_transfers_chain_block_log ON transfers(chain_id, block_number, log_index);
@@ -0,0 +1,2 @@
+DROP TABLE labelled_chains;
+DROP TABLE labels;
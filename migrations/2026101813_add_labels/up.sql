@@ -0,0 +1,15 @@
+-- Entity labels each indexer was started with, so API nodes, alerting and a running
+-- indexer pick up the labels published by `rebuild` or another indexer process
+CREATE TABLE labels (
+    chain_id BIGINT NOT NULL,
+    address TEXT NOT NULL,
+    entity TEXT NOT NULL,
+    updated_at DATETIME NOT NULL,
+    PRIMARY KEY (chain_id, address)
+) WITHOUT ROWID;
+
+-- Chains whose labels have been published; one listed here without rows in `labels` has none
+CREATE TABLE labelled_chains (
+    chain_id BIGINT PRIMARY KEY,
+    published_at DATETIME NOT NULL
+);
@@ -0,0 +1,1046 @@
+use axum::{extract::State, Json};
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::dsl::{count_star, max};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, Duration};
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    database::DbPool,
+    events::{Event, EventBus},
+    labels::{normalize_entity, Labels},
+    metrics::metrics,
+    models::{AlertFiring, Transfer, WebhookDelivery},
+    server::{ApiJson, ApiQuery, ServerState},
+    tokens::TokenSet,
+    utils::{current_utc_timestamp, string_to_bigdecimal},
+    webhooks::WebhookSender,
+    Config, IndexerError, Result,
+};
+
+/// Cooldowns apply per rule unless a rule sets its own key
+pub const DEFAULT_DEDUP_KEY: &str = "{rule}";
+
+/// Placeholders allowed in `dedup_key`
+const DEDUP_PLACEHOLDERS: &[&str] = &["rule", "chain", "entity", "from", "to", "counterparty", "tx", "block", "token"];
+
+/// `counterparty` value matching addresses without a known label
+const UNLABELED: &str = "unlabeled";
+
+/// Entry in `[[alerts.rules]]` of the config file
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Every condition set here must hold for the rule to match
+    pub when: Conditions,
+    /// Matches with the same dedup key within this long of an alert, by block time, are suppressed
+    #[serde(default)]
+    pub cooldown_secs: u64,
+    /// Template over `{rule}`, `{chain}`, `{entity}`, `{from}`, `{to}`, `{counterparty}`, `{tx}`, `{block}` and `{token}`
+    #[serde(default = "default_dedup_key")]
+    pub dedup_key: String,
+    pub webhook_url: String,
+    /// Overrides `WEBHOOK_SECRET` for this rule; never returned by the API
+    #[serde(default, skip_serializing)]
+    pub secret: Option<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_dedup_key() -> String {
+    DEFAULT_DEDUP_KEY.to_string()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Conditions {
+    /// Chain id; any configured chain when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<i64>,
+    /// Token address or symbol; any indexed token when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Transfer value bounds in token units, inclusive. Set `token` too when several tokens are indexed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub min_value: Option<BigDecimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub max_value: Option<BigDecimal>,
+    /// Label that one side of the transfer must carry, e.g. `binance`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity: Option<String>,
+    /// Which side `entity` must be on
+    #[serde(default)]
+    pub direction: Direction,
+    /// Label of the other side of an `entity` transfer, or `unlabeled`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    /// Aggregate over the transfers matching the conditions above
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<WindowCondition>,
+}
+
+/// Which side of a transfer the rule's entity must be on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    #[default]
+    Any,
+    Inflow,
+    Outflow,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WindowCondition {
+    pub secs: i64,
+    pub aggregate: Aggregate,
+    /// Inclusive bounds on the aggregate; set at least one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub gte: Option<BigDecimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub lte: Option<BigDecimal>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregate {
+    /// Inflow minus outflow of `entity`
+    NetFlow,
+    Inflow,
+    Outflow,
+    /// Total value of matching transfers
+    Volume,
+    /// Number of matching transfers
+    Count,
+}
+
+impl Aggregate {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Aggregate::NetFlow => "net_flow",
+            Aggregate::Inflow => "inflow",
+            Aggregate::Outflow => "outflow",
+            Aggregate::Volume => "volume",
+            Aggregate::Count => "count",
+        }
+    }
+}
+
+/// JSON body of an alert webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    /// Also the delivery id, sent as `X-Webhook-Id`
+    pub id: String,
+    pub rule: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub triggered_at: DateTime<Utc>,
+    pub summary: String,
+    pub dedup_key: String,
+    pub transfer: Transfer,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<WindowSummary>,
+}
+
+/// Aggregate value that satisfied a window condition
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WindowSummary {
+    pub secs: i64,
+    pub aggregate: Aggregate,
+    #[schema(value_type = String)]
+    pub value: BigDecimal,
+    pub transfer_count: usize,
+}
+
+/// Result of checking one rule against one transfer
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Evaluation {
+    pub rule: String,
+    /// All conditions hold
+    pub matched: bool,
+    /// Matched and not held back by the rule being disabled or in cooldown
+    pub fires: bool,
+    pub reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dedup_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<WindowSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown_remaining_secs: Option<i64>,
+}
+
+/// A transfer's contribution to a rule's window
+#[derive(Debug, Clone)]
+struct WindowEntry {
+    at: NaiveDateTime,
+    value: BigDecimal,
+    inflow: BigDecimal,
+    outflow: BigDecimal,
+}
+
+struct CompiledRule {
+    rule: AlertRule,
+    secret: String,
+}
+
+/// Mutable evaluation state, shared by the event loop and the API
+struct EngineState {
+    /// Recent matching transfers per rule, in rule order
+    windows: Vec<VecDeque<WindowEntry>>,
+    /// Block time of the latest transfer each dedup key produced an alert for
+    last_fired: HashMap<String, NaiveDateTime>,
+}
+
+/// Evaluates alert rules against transfers published by the indexer and sends webhooks.
+///
+/// Windows and cooldowns are measured in block time, so replaying or backfilling blocks
+/// alerts the same way whenever it runs.
+pub struct AlertEngine {
+    rules: Vec<CompiledRule>,
+    state: Mutex<EngineState>,
+    /// Whether this node follows the indexer's transfers, see `run`
+    evaluating: AtomicBool,
+    sender: WebhookSender,
+    pool: DbPool,
+    events: EventBus,
+    tokens: TokenSet,
+    labels: Labels,
+    /// Chain id -> configured name, for summaries
+    chain_names: HashMap<i64, String>,
+}
+
+impl AlertEngine {
+    /// Compiles `[[alerts.rules]]`; returns `None` when alerting isn't configured
+    pub fn from_config(config: &Config, pool: DbPool, events: EventBus, tokens: TokenSet, labels: Labels) -> Result<Option<Self>> {
+        if config.alert_rules.is_empty() {
+            return Ok(None);
+        }
+        let mut problems = Vec::new();
+        check_rules(&mut problems, config);
+        if !problems.is_empty() {
+            return Err(IndexerError::Config(format!("Invalid alert rules:\n  - {}", problems.join("\n  - "))));
+        }
+
+        let rules: Vec<CompiledRule> = config
+            .alert_rules
+            .iter()
+            .cloned()
+            .map(|mut rule| {
+                // Compared with labels, which are normalized the same way
+                rule.when.entity = rule.when.entity.as_deref().map(normalize_entity);
+                rule.when.counterparty = rule.when.counterparty.as_deref().map(normalize_entity);
+                let secret = rule.secret.clone().or_else(|| config.webhook_secret.clone()).unwrap_or_default();
+                CompiledRule { rule, secret }
+            })
+            .collect();
+
+        let any_cooldown = rules.iter().any(|compiled| compiled.rule.cooldown_secs > 0);
+        let last_fired = if any_cooldown { restore_cooldowns(&pool)? } else { HashMap::new() };
+
+        info!("🚨 Loaded {} alert rules", rules.len());
+        Ok(Some(Self {
+            state: Mutex::new(EngineState {
+                windows: vec![VecDeque::new(); rules.len()],
+                last_fired,
+            }),
+            evaluating: AtomicBool::new(false),
+            rules,
+            sender: WebhookSender::new(config, pool.clone())?,
+            pool,
+            events,
+            tokens,
+            labels,
+            chain_names: config
+                .chains
+                .iter()
+                .map(|chain| (chain.chain_id as i64, chain.name.clone()))
+                .collect(),
+        }))
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &AlertRule> {
+        self.rules.iter().map(|compiled| &compiled.rule)
+    }
+
+    /// Whether `run` follows transfers here. Only nodes running the indexer do, so elsewhere
+    /// windows and cooldowns stay empty.
+    pub fn is_evaluating(&self) -> bool {
+        self.evaluating.load(Ordering::Relaxed)
+    }
+
+    /// Follows the event bus until it closes, refilling from the replay buffer after lag
+    pub async fn run(self: Arc<Self>) {
+        self.evaluating.store(true, Ordering::Relaxed);
+        let mut rx = self.events.subscribe();
+        let mut last_id = self.events.last_id();
+
+        loop {
+            match rx.recv().await {
+                Ok(sequenced) => {
+                    last_id = sequenced.id;
+                    self.handle(&sequenced.event);
+                }
+                Err(RecvError::Lagged(_)) => {
+                    let resume = self.events.resume(last_id);
+                    if let Some(gap) = resume.gap {
+                        warn!("⚠️ Alert engine missed events {}-{}", gap.from, gap.to);
+                    }
+                    rx = resume.receiver;
+                    for sequenced in resume.backlog {
+                        last_id = sequenced.id;
+                        self.handle(&sequenced.event);
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Restarts webhook deliveries left pending by a previous run or another process, on a timer
+    pub async fn resume_deliveries(self: Arc<Self>, period: Duration) {
+        let mut ticker = interval(period);
+        loop {
+            ticker.tick().await;
+            let secret_for = |name: &str| {
+                self.rules
+                    .iter()
+                    .find(|compiled| compiled.rule.name == name)
+                    .map(|compiled| compiled.secret.clone())
+            };
+            if let Err(e) = self.sender.resume(secret_for) {
+                warn!("⚠️ Failed to resume pending webhook deliveries: {}", e);
+            }
+        }
+    }
+
+    /// Waits for webhook requests in flight; retries still due stay pending for the next run
+    pub async fn drain_deliveries(&self) {
+        self.sender.drain().await;
+    }
+
+    fn handle(&self, event: &Event) {
+        let Event::Transfer(transfer) = event else {
+            return;
+        };
+
+        for (alert, secret, url) in self.evaluate(transfer) {
+            info!("🚨 Alert '{}': {}", alert.rule, alert.summary);
+            if let Err(e) = self.record(&alert).and_then(|_| self.sender.dispatch(url, secret, &alert)) {
+                warn!("⚠️ Failed to send alert '{}': {}", alert.rule, e);
+            }
+        }
+    }
+
+    /// Updates windows and cooldowns with a live transfer and returns the alerts to send
+    fn evaluate(&self, transfer: &Transfer) -> Vec<(Alert, &str, &str)> {
+        let now = current_utc_timestamp();
+        let at = transfer.timestamp;
+        let mut state = self.state.lock().unwrap();
+        let EngineState { windows, last_fired } = &mut *state;
+
+        let mut alerts = Vec::new();
+        for (compiled, window) in self.rules.iter().zip(windows.iter_mut()) {
+            let rule = &compiled.rule;
+            if !rule.enabled {
+                continue;
+            }
+            let Ok(window_summary) = check(rule, transfer, window, &self.tokens, &self.labels) else {
+                continue;
+            };
+
+            let dedup_key = render_dedup_key(rule, transfer, &self.labels);
+            if cooldown_remaining(rule, last_fired.get(&dedup_key), at).is_some() {
+                metrics().alerts.with_label_values(&[rule.name.as_str(), "suppressed"]).inc();
+                continue;
+            }
+            let latest = last_fired.entry(dedup_key.clone()).or_insert(at);
+            *latest = (*latest).max(at);
+            metrics().alerts.with_label_values(&[rule.name.as_str(), "fired"]).inc();
+
+            let alert = Alert {
+                id: uuid::Uuid::new_v4().to_string(),
+                rule: rule.name.clone(),
+                description: rule.description.clone(),
+                triggered_at: now,
+                summary: self.summarize(transfer, window_summary.as_ref()),
+                dedup_key,
+                transfer: transfer.clone(),
+                window: window_summary,
+            };
+            alerts.push((alert, compiled.secret.as_str(), rule.webhook_url.as_str()));
+        }
+        alerts
+    }
+
+    /// Evaluates a hypothetical transfer without touching windows or cooldowns
+    pub fn dry_run(&self, transfer: &Transfer, only: Option<&str>) -> Vec<Evaluation> {
+        let state = self.state.lock().unwrap();
+
+        self.rules
+            .iter()
+            .zip(state.windows.iter())
+            .filter(|(compiled, _)| only.is_none_or(|name| compiled.rule.name == name))
+            .map(|(compiled, window)| {
+                let rule = &compiled.rule;
+                let mut window = window.clone();
+                match check(rule, transfer, &mut window, &self.tokens, &self.labels) {
+                    Err(reason) => Evaluation {
+                        rule: rule.name.clone(),
+                        matched: false,
+                        fires: false,
+                        reason,
+                        dedup_key: None,
+                        window: None,
+                        cooldown_remaining_secs: None,
+                    },
+                    Ok(window_summary) => {
+                        let dedup_key = render_dedup_key(rule, transfer, &self.labels);
+                        let remaining = cooldown_remaining(rule, state.last_fired.get(&dedup_key), transfer.timestamp);
+                        let (fires, reason) = match (rule.enabled, remaining) {
+                            (false, _) => (false, "Rule is disabled".to_string()),
+                            (true, Some(secs)) => (false, format!("In cooldown for another {}s", secs)),
+                            (true, None) => (true, self.summarize(transfer, window_summary.as_ref())),
+                        };
+                        Evaluation {
+                            rule: rule.name.clone(),
+                            matched: true,
+                            fires,
+                            reason,
+                            dedup_key: Some(dedup_key),
+                            window: window_summary,
+                            cooldown_remaining_secs: remaining,
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn record(&self, alert: &Alert) -> Result<()> {
+        let firing = AlertFiring {
+            id: alert.id.clone(),
+            rule_name: alert.rule.clone(),
+            dedup_key: alert.dedup_key.clone(),
+            summary: alert.summary.clone(),
+            payload: serde_json::to_string(alert)?,
+            transfer_id: alert.transfer.id.clone(),
+            fired_at: alert.triggered_at.naive_utc(),
+            chain_id: alert.transfer.chain_id,
+        };
+
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(crate::schema::alert_firings::table)
+            .values(&firing)
+            .execute(&mut conn)?;
+        Ok(())
+    }
+
+    fn summarize(&self, transfer: &Transfer, window: Option<&WindowSummary>) -> String {
+        let chain = self
+            .chain_names
+            .get(&transfer.chain_id)
+            .cloned()
+            .unwrap_or_else(|| transfer.chain_id.to_string());
+        let mut summary = format!(
+            "{} {} from {} to {} on {}",
+            transfer.value,
+            self.tokens.symbol_of(transfer.chain_id, &transfer.token),
+            transfer.from_address,
+            transfer.to_address,
+            chain
+        );
+        if let Some(window) = window {
+            summary.push_str(&format!("; {} over {}s is {}", window.aggregate.as_str(), window.secs, window.value));
+        }
+        summary
+    }
+}
+
+/// Checks every condition of a rule, recording the transfer in the rule's window when
+/// the per-transfer conditions hold. Returns the window aggregate, or why it didn't match.
+///
+/// The window holds the matching transfers by block time. A transfer counts those in the
+/// `secs` up to its own block time, so blocks backfilled out of order only see entries
+/// still within the window of the newest transfer.
+fn check(
+    rule: &AlertRule,
+    transfer: &Transfer,
+    window: &mut VecDeque<WindowEntry>,
+    tokens: &TokenSet,
+    labels: &Labels,
+) -> std::result::Result<Option<WindowSummary>, String> {
+    let when = &rule.when;
+    let value = string_to_bigdecimal(&transfer.value);
+
+    if when.chain_id.is_some_and(|chain_id| chain_id != transfer.chain_id) {
+        return Err(format!("Not a transfer on chain {}", when.chain_id.unwrap()));
+    }
+
+    if let Some(wanted) = &when.token {
+        // Resolved per transfer: API nodes learn new tokens after the rules are loaded
+        let addresses = tokens.resolve(wanted, when.chain_id).map_err(|e| e.to_string())?;
+        if !addresses.contains(&transfer.token) {
+            return Err(format!("Not a {} transfer", wanted));
+        }
+    }
+
+    if when.min_value.as_ref().is_some_and(|min| value < *min) {
+        return Err(format!("Value {} is below {}", value, when.min_value.as_ref().unwrap()));
+    }
+    if when.max_value.as_ref().is_some_and(|max| value > *max) {
+        return Err(format!("Value {} is above {}", value, when.max_value.as_ref().unwrap()));
+    }
+
+    let (mut inflow, mut outflow) = (BigDecimal::zero(), BigDecimal::zero());
+    if let Some(entity) = &when.entity {
+        let to_entity = labels.entity_of(transfer.chain_id, &transfer.to_address).as_ref() == Some(entity);
+        let from_entity = labels.entity_of(transfer.chain_id, &transfer.from_address).as_ref() == Some(entity);
+        let direction_ok = match when.direction {
+            Direction::Any => to_entity || from_entity,
+            Direction::Inflow => to_entity,
+            Direction::Outflow => from_entity,
+        };
+        if !direction_ok {
+            return Err(format!("Not a matching {} transfer", entity));
+        }
+
+        if let Some(expected) = &when.counterparty {
+            let counterparty = if to_entity { &transfer.from_address } else { &transfer.to_address };
+            let label = labels.entity_of(transfer.chain_id, counterparty);
+            let matches = match &label {
+                None => expected == UNLABELED,
+                Some(label) => label == expected,
+            };
+            if !matches {
+                return Err(format!("Counterparty is {}, not {}", label.as_deref().unwrap_or(UNLABELED), expected));
+            }
+        }
+
+        if to_entity {
+            inflow = value.clone();
+        }
+        if from_entity {
+            outflow = value.clone();
+        }
+    }
+
+    let Some(condition) = &when.window else {
+        return Ok(None);
+    };
+
+    let at = transfer.timestamp;
+    let span = chrono::Duration::seconds(condition.secs);
+    window.insert(window.partition_point(|entry| entry.at <= at), WindowEntry { at, value, inflow, outflow });
+    let newest = window.back().map_or(at, |entry| entry.at);
+
+    let entries: Vec<&WindowEntry> = window.iter().filter(|entry| entry.at >= at - span && entry.at <= at).collect();
+    let aggregate = match condition.aggregate {
+        Aggregate::NetFlow => entries.iter().map(|entry| &entry.inflow - &entry.outflow).sum(),
+        Aggregate::Inflow => entries.iter().map(|entry| entry.inflow.clone()).sum(),
+        Aggregate::Outflow => entries.iter().map(|entry| entry.outflow.clone()).sum(),
+        Aggregate::Volume => entries.iter().map(|entry| entry.value.clone()).sum(),
+        Aggregate::Count => BigDecimal::from(entries.len() as u64),
+    };
+    let summary = WindowSummary {
+        secs: condition.secs,
+        aggregate: condition.aggregate,
+        value: aggregate,
+        transfer_count: entries.len(),
+    };
+    while window.front().is_some_and(|entry| entry.at < newest - span) {
+        window.pop_front();
+    }
+
+    if condition.gte.as_ref().is_some_and(|gte| summary.value < *gte)
+        || condition.lte.as_ref().is_some_and(|lte| summary.value > *lte)
+    {
+        return Err(format!(
+            "Window {} over {}s is {}, outside the configured bounds",
+            condition.aggregate.as_str(), condition.secs, summary.value
+        ));
+    }
+    Ok(Some(summary))
+}
+
+/// Seconds of cooldown left at block time `at`. Blocks replayed from before the
+/// latest alert count their distance from it too.
+fn cooldown_remaining(rule: &AlertRule, last_fired: Option<&NaiveDateTime>, at: NaiveDateTime) -> Option<i64> {
+    let elapsed = (at - *last_fired?).num_seconds().abs();
+    let remaining = rule.cooldown_secs as i64 - elapsed;
+    (remaining > 0).then_some(remaining)
+}
+
+fn render_dedup_key(rule: &AlertRule, transfer: &Transfer, labels: &Labels) -> String {
+    let entity = rule.when.entity.as_deref().unwrap_or_default();
+    let counterparty = match &rule.when.entity {
+        Some(entity) if labels.entity_of(transfer.chain_id, &transfer.to_address).as_ref() == Some(entity) => {
+            &transfer.from_address
+        }
+        Some(_) => &transfer.to_address,
+        None => &transfer.from_address,
+    };
+
+    rule.dedup_key
+        .replace("{rule}", &rule.name)
+        .replace("{chain}", &transfer.chain_id.to_string())
+        .replace("{entity}", entity)
+        .replace("{from}", &transfer.from_address)
+        .replace("{to}", &transfer.to_address)
+        .replace("{counterparty}", counterparty)
+        .replace("{tx}", &transfer.transaction_hash)
+        .replace("{block}", &transfer.block_number.to_string())
+        .replace("{token}", &transfer.token)
+}
+
+/// Reports every problem with `[[alerts.rules]]`, for `Config::validate`
+pub fn check_rules(problems: &mut Vec<String>, config: &Config) {
+    let mut names = HashSet::new();
+    for rule in &config.alert_rules {
+        check_rule(problems, rule);
+        if !names.insert(&rule.name) {
+            problems.push(format!("alerts.rules: {} is listed more than once", rule.name));
+        }
+        if let Some(chain_id) = rule.when.chain_id {
+            if config.chain(chain_id).is_none() {
+                problems.push(format!("alerts.rules.{}.when.chain_id: chain {} is not configured", rule.name, chain_id));
+            }
+        }
+        if rule.secret.is_none() && config.webhook_secret.is_none() {
+            problems.push(format!("alerts.rules.{}: no secret, and alerts.webhook_secret is not set", rule.name));
+        }
+    }
+}
+
+fn check_rule(problems: &mut Vec<String>, rule: &AlertRule) {
+    let mut invalid = |reason: &str| problems.push(format!("alerts.rules.{}: {}", rule.name, reason));
+    let when = &rule.when;
+
+    if rule.name.trim().is_empty() {
+        invalid("name must not be empty");
+    }
+    if when.entity.is_none() && when.direction != Direction::Any {
+        invalid("direction requires an entity");
+    }
+    if when.entity.is_none() && when.counterparty.is_some() {
+        invalid("counterparty requires an entity");
+    }
+    if let Some(window) = &when.window {
+        if window.secs <= 0 {
+            invalid("window.secs must be positive");
+        }
+        if window.gte.is_none() && window.lte.is_none() {
+            invalid("window needs gte and/or lte");
+        }
+        let needs_entity = matches!(window.aggregate, Aggregate::NetFlow | Aggregate::Inflow | Aggregate::Outflow);
+        if needs_entity && when.entity.is_none() {
+            invalid("net_flow, inflow and outflow windows require an entity");
+        }
+    }
+
+    let mut rest = rule.dedup_key.as_str();
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            invalid("unclosed placeholder in dedup_key");
+            return;
+        };
+        let placeholder = &rest[start + 1..start + end];
+        if !DEDUP_PLACEHOLDERS.contains(&placeholder) {
+            invalid(&format!("unknown dedup_key placeholder {{{}}}", placeholder));
+        }
+        rest = &rest[start + end + 1..];
+    }
+}
+
+/// Block time of the latest alerted transfer per dedup key, so restarts don't re-alert
+fn restore_cooldowns(pool: &DbPool) -> Result<HashMap<String, NaiveDateTime>> {
+    use crate::schema::{alert_firings, transfers};
+
+    let mut conn = pool.get()?;
+    let rows: Vec<(String, Option<NaiveDateTime>)> = alert_firings::table
+        .inner_join(transfers::table)
+        .group_by(alert_firings::dedup_key)
+        .select((alert_firings::dedup_key, max(transfers::timestamp)))
+        .load(&mut conn)?;
+
+    Ok(rows.into_iter().filter_map(|(key, at)| Some((key, at?))).collect())
+}
+
+// API
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AlertRuleView {
+    pub rule: AlertRule,
+    pub fire_count: i64,
+    #[schema(value_type = Option<String>)]
+    pub last_fired_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AlertTestRequest {
+    /// Only evaluate this rule
+    pub rule: Option<String>,
+    /// Defaults to the first configured chain
+    pub chain_id: Option<i64>,
+    pub from_address: String,
+    pub to_address: String,
+    /// Token address or symbol; defaults to the chain's first configured token
+    pub token: Option<String>,
+    /// Value in token units
+    pub value: String,
+    /// Defaults to now
+    #[schema(value_type = Option<String>)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AlertHistoryQuery {
+    /// Only firings of this rule
+    rule: Option<String>,
+    /// Only firings for transfers on this chain
+    chain_id: Option<i64>,
+    /// Max rows to return (default 100, max 1000)
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AlertFiringView {
+    pub id: String,
+    pub rule: String,
+    pub chain_id: i64,
+    pub dedup_key: String,
+    pub summary: String,
+    pub transfer_id: String,
+    #[schema(value_type = String)]
+    pub fired_at: NaiveDateTime,
+    /// pending, delivered or failed
+    pub delivery_status: Option<String>,
+    pub delivery_attempts: Option<i32>,
+}
+
+/// Configured alert rules with how often they fired
+#[utoipa::path(
+    get,
+    path = "/api/alerts",
+    tag = "alerts",
+    responses(
+        (status = 200, description = "Alert rules", body = Vec<AlertRuleView>),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    )
+)]
+pub async fn list_alert_rules(State(state): State<ServerState>) -> Result<Json<Vec<AlertRuleView>>> {
+    let Some(engine) = &state.alerts else {
+        return Ok(Json(Vec::new()));
+    };
+
+    use crate::schema::alert_firings::dsl::*;
+    let mut conn = state.pool.get()?;
+    let stats: HashMap<String, (i64, Option<NaiveDateTime>)> = alert_firings
+        .group_by(rule_name)
+        .select((rule_name, count_star(), max(fired_at)))
+        .load::<(String, i64, Option<NaiveDateTime>)>(&mut conn)?
+        .into_iter()
+        .map(|(name, count, last)| (name, (count, last)))
+        .collect();
+
+    let views = engine
+        .rules()
+        .map(|rule| {
+            let (count, last) = stats.get(&rule.name).cloned().unwrap_or((0, None));
+            AlertRuleView {
+                rule: rule.clone(),
+                fire_count: count,
+                last_fired_at: last,
+            }
+        })
+        .collect();
+    Ok(Json(views))
+}
+
+/// Evaluates rules against a hypothetical transfer without sending anything
+#[utoipa::path(
+    post,
+    path = "/api/alerts/test",
+    tag = "alerts",
+    request_body = AlertTestRequest,
+    responses(
+        (status = 200, description = "Per-rule evaluation", body = Vec<Evaluation>),
+        (status = 400, description = "Invalid transfer, or alerting disabled or not evaluated on this node", body = ErrorResponse),
+        (status = 404, description = "Unknown rule", body = ErrorResponse),
+    )
+)]
+pub async fn test_alert_rules(
+    State(state): State<ServerState>,
+    ApiJson(request): ApiJson<AlertTestRequest>,
+) -> Result<Json<Vec<Evaluation>>> {
+    let engine = state
+        .alerts
+        .as_ref()
+        .ok_or_else(|| IndexerError::BadRequest("No alert rules are configured".to_string()))?;
+    if !engine.is_evaluating() {
+        // Its windows and cooldowns would be empty, so the answer could be wrong
+        return Err(IndexerError::BadRequest(
+            "Alerts are evaluated by the indexer process; send dry runs to a node running it".to_string(),
+        ));
+    }
+
+    if let Some(name) = &request.rule {
+        if !engine.rules().any(|rule| &rule.name == name) {
+            return Err(IndexerError::NotFound(format!("Alert rule '{}'", name)));
+        }
+    }
+    BigDecimal::from_str(&request.value)
+        .map_err(|e| IndexerError::BadRequest(format!("Invalid value: {}", e)))?;
+    let chain = match request.chain_id {
+        Some(chain_id) => state
+            .config
+            .chain(chain_id)
+            .ok_or_else(|| IndexerError::BadRequest(format!("Chain {} is not configured", chain_id)))?,
+        None => state
+            .config
+            .chains
+            .first()
+            .ok_or_else(|| IndexerError::BadRequest("No chains are configured".to_string()))?,
+    };
+    let chain_id = chain.chain_id as i64;
+    let token = match &request.token {
+        Some(token) => {
+            let addresses = state.tokens.resolve(token, Some(chain_id))?;
+            addresses.into_iter().next().unwrap_or_default()
+        }
+        None => chain.tokens.first().map(|token| token.address.clone()).unwrap_or_default(),
+    };
+
+    let timestamp = request.timestamp.unwrap_or_else(current_utc_timestamp).naive_utc();
+    let transfer = Transfer::new(
+        "test".to_string(),
+        chain_id,
+        state.current_block(Some(chain_id))? as i64,
+        "0x0".to_string(),
+        request.from_address.to_lowercase(),
+        request.to_address.to_lowercase(),
+        token,
+        request.value,
+        timestamp,
+        false,
+        false,
+        None,
+        None,
+    );
+
+    Ok(Json(engine.dry_run(&transfer, request.rule.as_deref())))
+}
+
+/// Alerts that fired, newest first, with their webhook delivery status
+#[utoipa::path(
+    get,
+    path = "/api/alerts/history",
+    tag = "alerts",
+    params(AlertHistoryQuery),
+    responses(
+        (status = 200, description = "Alert firings", body = Vec<AlertFiringView>),
+        (status = 400, description = "Invalid query parameters", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    )
+)]
+pub async fn alert_history(
+    State(state): State<ServerState>,
+    ApiQuery(params): ApiQuery<AlertHistoryQuery>,
+) -> Result<Json<Vec<AlertFiringView>>> {
+    use crate::schema::{alert_firings, webhook_deliveries};
+    let mut conn = state.pool.get()?;
+
+    let mut query = alert_firings::table.into_boxed();
+    if let Some(name) = &params.rule {
+        query = query.filter(alert_firings::rule_name.eq(name));
+    }
+    if let Some(chain_id) = params.chain_id {
+        query = query.filter(alert_firings::chain_id.eq(chain_id));
+    }
+    let firings: Vec<AlertFiring> = query
+        .order(alert_firings::fired_at.desc())
+        .limit(params.limit.unwrap_or(100).clamp(1, 1000))
+        .offset(params.offset.unwrap_or(0).max(0))
+        .load(&mut conn)?;
+
+    let ids: Vec<&str> = firings.iter().map(|firing| firing.id.as_str()).collect();
+    let deliveries: HashMap<String, WebhookDelivery> = webhook_deliveries::table
+        .filter(webhook_deliveries::id.eq_any(&ids))
+        .load::<WebhookDelivery>(&mut conn)?
+        .into_iter()
+        .map(|delivery| (delivery.id.clone(), delivery))
+        .collect();
+
+    let views = firings
+        .into_iter()
+        .map(|firing| {
+            let delivery = deliveries.get(&firing.id);
+            AlertFiringView {
+                delivery_status: delivery.map(|d| d.status.clone()),
+                delivery_attempts: delivery.map(|d| d.attempts),
+                id: firing.id,
+                rule: firing.rule_name,
+                chain_id: firing.chain_id,
+                dedup_key: firing.dedup_key,
+                summary: firing.summary,
+                transfer_id: firing.transfer_id,
+                fired_at: firing.fired_at,
+            }
+        })
+        .collect();
+    Ok(Json(views))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_pool;
+
+    fn transfer(chain: &crate::config::ChainConfig, from: &str, to: &str) -> Transfer {
+        Transfer {
+            id: "t1".to_string(),
+            chain_id: chain.chain_id as i64,
+            block_number: 1,
+            transaction_hash: "0x01".to_string(),
+            from_address: from.to_string(),
+            to_address: to.to_string(),
+            token: chain.tokens[0].address.to_lowercase(),
+            value: "1000".to_string(),
+            timestamp: chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc(),
+            is_binance_related: true,
+            is_watched: false,
+            log_index: Some(0),
+            transaction_index: Some(0),
+        }
+    }
+
+    const EXCHANGE: &str = "0xaa00000000000000000000000000000000000001";
+    const UNKNOWN: &str = "0xbb00000000000000000000000000000000000002";
+
+    /// An engine for `[[alerts.rules]]` in TOML, with `EXCHANGE` labelled Binance
+    fn engine(pool: DbPool, rules: &str) -> (Config, AlertEngine) {
+        #[derive(Deserialize)]
+        struct Rules {
+            rules: Vec<AlertRule>,
+        }
+        let mut config = Config::default();
+        config.chains[0].labels = [(EXCHANGE.to_string(), "Binance".to_string())].into();
+        config.alert_rules = toml::from_str::<Rules>(rules).unwrap().rules;
+        config.webhook_secret = Some("secret".to_string());
+
+        let labels = Labels::from_config(&config);
+        let engine = AlertEngine::from_config(&config, pool, EventBus::new(), TokenSet::default(), labels).unwrap().unwrap();
+        (config, engine)
+    }
+
+    /// A transfer into `EXCHANGE`, `secs` after the block time of `transfer`
+    fn deposit_at(config: &Config, secs: i64) -> Transfer {
+        let mut deposit = transfer(&config.chains[0], UNKNOWN, EXCHANGE);
+        deposit.id = format!("t{}", secs);
+        deposit.timestamp += chrono::Duration::seconds(secs);
+        deposit
+    }
+
+    fn fired(engine: &AlertEngine, transfer: &Transfer) -> Vec<String> {
+        engine.evaluate(transfer).into_iter().map(|(alert, _, _)| alert.rule).collect()
+    }
+
+    #[test]
+    fn rule_entities_match_labels_regardless_of_case() {
+        let (_dir, pool) = test_pool();
+        let (config, engine) = engine(
+            pool,
+            r#"
+[[rules]]
+name = "deposit"
+when = { entity = "Binance", direction = "inflow", counterparty = "Unlabeled" }
+webhook_url = "http://localhost/hook"
+
+[[rules]]
+name = "withdrawal"
+when = { entity = "BINANCE", direction = "outflow" }
+webhook_url = "http://localhost/hook"
+"#,
+        );
+        let (exchange, unknown) = (EXCHANGE, UNKNOWN);
+
+        let deposit = engine.dry_run(&transfer(&config.chains[0], unknown, exchange), None);
+        assert_eq!(deposit.iter().map(|e| e.fires).collect::<Vec<_>>(), vec![true, false]);
+        assert_eq!(deposit[0].dedup_key.as_deref(), Some("deposit"));
+
+        let withdrawal = engine.dry_run(&transfer(&config.chains[0], exchange, unknown), None);
+        assert_eq!(withdrawal.iter().map(|e| e.fires).collect::<Vec<_>>(), vec![false, true]);
+    }
+
+    #[test]
+    fn windows_and_cooldowns_follow_block_time() {
+        let (_dir, pool) = test_pool();
+        let (config, engine) = engine(
+            pool,
+            r#"
+[[rules]]
+name = "busy"
+when = { entity = "binance", window = { secs = 60, aggregate = "count", gte = 2 } }
+cooldown_secs = 120
+webhook_url = "http://localhost/hook"
+"#,
+        );
+
+        assert!(fired(&engine, &deposit_at(&config, 0)).is_empty());
+        assert_eq!(fired(&engine, &deposit_at(&config, 30)), vec!["busy"]);
+        // Two in a minute again, but within two minutes of the alert
+        assert!(fired(&engine, &deposit_at(&config, 80)).is_empty());
+        // Out of the window of the first two, however late it is evaluated
+        assert!(fired(&engine, &deposit_at(&config, 1000)).is_empty());
+        assert_eq!(fired(&engine, &deposit_at(&config, 1030)), vec!["busy"]);
+
+        // A backfilled block from between the last two counts the window up to its own time
+        let backfilled = engine.dry_run(&deposit_at(&config, 1010), None);
+        assert_eq!(backfilled[0].window.as_ref().unwrap().transfer_count, 2);
+        assert_eq!(backfilled[0].cooldown_remaining_secs, Some(100));
+    }
+
+    #[test]
+    fn invalid_rules_are_all_reported() {
+        let rules: Vec<AlertRule> = serde_json::from_value(serde_json::json!([
+            {"name": "sided", "when": {"direction": "inflow"}, "webhook_url": "ftp://localhost/hook"},
+            {"name": "sided", "when": {"chain_id": 1, "window": {"secs": 0, "aggregate": "volume"}}, "dedup_key": "{wallet}", "webhook_url": "http://localhost/hook"},
+        ]))
+        .unwrap();
+        let config = Config { alert_rules: rules, ..Config::default() };
+
+        let error = config.validate().unwrap_err().to_string();
+        for problem in [
+            "alerts.rules.sided: direction requires an entity",
+            "alerts.rules.sided.webhook_url 'ftp://localhost/hook' must use http or https, not ftp",
+            "alerts.rules: sided is listed more than once",
+            "alerts.rules.sided.when.chain_id: chain 1 is not configured",
+            "alerts.rules.sided: window.secs must be positive",
+            "alerts.rules.sided: window needs gte and/or lte",
+            "alerts.rules.sided: unknown dedup_key placeholder {wallet}",
+            "alerts.rules.sided: no secret, and alerts.webhook_secret is not set",
+        ] {
+            assert!(error.contains(problem), "missing {:?} in {}", problem, error);
+        }
+    }
+}
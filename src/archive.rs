@@ -0,0 +1,202 @@
+use chrono::NaiveDateTime;
+use diesel::dsl::max;
+use diesel::prelude::*;
+use ethers::types::{Address, Bytes, Log, H256, U256, U64};
+use std::collections::BTreeMap;
+
+use crate::{
+    database::DbConnection,
+    models::{ArchivedBlock, RawLog},
+    schema::{archived_blocks, raw_logs},
+    Result,
+};
+
+/// Rows per raw log insert, well under SQLite's bound parameter limit
+const LOG_CHUNK: usize = 100;
+
+/// A block's raw logs, as kept in the archive
+pub struct BlockLogs {
+    pub block: ArchivedBlock,
+    pub logs: Vec<RawLog>,
+}
+
+impl BlockLogs {
+    /// `logs` are every log of the block, in block order
+    pub fn new(chain_id: i64, block_number: i64, block_hash: H256, block_time: NaiveDateTime, logs: &[Log]) -> Self {
+        let logs: Vec<RawLog> = logs
+            .iter()
+            .enumerate()
+            .map(|(position, log)| RawLog {
+                chain_id,
+                block_number,
+                log_index: log.log_index.map_or(position as i64, |index| index.as_u64() as i64),
+                transaction_index: log.transaction_index.map_or(0, |index| index.as_u64() as i64),
+                transaction_hash: log.transaction_hash.unwrap_or_default().as_bytes().to_vec(),
+                address: log.address.as_bytes().to_vec(),
+                topics: log.topics.iter().flat_map(|topic| topic.as_bytes().to_vec()).collect(),
+                data: log.data.to_vec(),
+            })
+            .collect();
+        Self {
+            block: ArchivedBlock {
+                chain_id,
+                block_number,
+                block_hash: block_hash.as_bytes().to_vec(),
+                block_timestamp: block_time,
+                log_count: logs.len() as i64,
+            },
+            logs,
+        }
+    }
+
+    /// Writes the block over any earlier copy of it; runs inside the block's transaction
+    pub fn store(&self, conn: &mut SqliteConnection) -> Result<()> {
+        let block = &self.block;
+        diesel::delete(
+            raw_logs::table
+                .filter(raw_logs::chain_id.eq(block.chain_id))
+                .filter(raw_logs::block_number.eq(block.block_number)),
+        )
+        .execute(conn)?;
+        diesel::replace_into(archived_blocks::table).values(block).execute(conn)?;
+        for chunk in self.logs.chunks(LOG_CHUNK) {
+            diesel::insert_into(raw_logs::table).values(chunk).execute(conn)?;
+        }
+        Ok(())
+    }
+}
+
+impl RawLog {
+    /// The log as the node returned it, minus fields the archive doesn't keep
+    pub fn to_log(&self, block_hash: H256) -> Log {
+        Log {
+            address: Address::from_slice(&self.address),
+            topics: self.topics.chunks(32).map(H256::from_slice).collect(),
+            data: Bytes::from(self.data.clone()),
+            block_hash: Some(block_hash),
+            block_number: Some(U64::from(self.block_number)),
+            transaction_hash: Some(H256::from_slice(&self.transaction_hash)),
+            transaction_index: Some(U64::from(self.transaction_index)),
+            log_index: Some(U256::from(self.log_index)),
+            removed: Some(false),
+            ..Log::default()
+        }
+    }
+}
+
+/// Archived blocks of a chain from `from` to `to`, lowest first, with their logs in block order
+pub fn load_blocks(conn: &mut DbConnection, chain_id: i64, from: i64, to: i64) -> Result<Vec<(ArchivedBlock, Vec<Log>)>> {
+    let blocks: Vec<ArchivedBlock> = archived_blocks::table
+        .filter(archived_blocks::chain_id.eq(chain_id))
+        .filter(archived_blocks::block_number.between(from, to))
+        .order(archived_blocks::block_number.asc())
+        .load(conn)?;
+    let logs: Vec<RawLog> = raw_logs::table
+        .filter(raw_logs::chain_id.eq(chain_id))
+        .filter(raw_logs::block_number.between(from, to))
+        .order((raw_logs::block_number.asc(), raw_logs::log_index.asc()))
+        .load(conn)?;
+
+    let mut by_block: BTreeMap<i64, Vec<RawLog>> = BTreeMap::new();
+    for log in logs {
+        by_block.entry(log.block_number).or_default().push(log);
+    }
+    Ok(blocks
+        .into_iter()
+        .map(|block| {
+            let block_hash = H256::from_slice(&block.block_hash);
+            let logs = by_block
+                .remove(&block.block_number)
+                .unwrap_or_default()
+                .iter()
+                .map(|log| log.to_log(block_hash))
+                .collect();
+            (block, logs)
+        })
+        .collect())
+}
+
+/// Highest archived block of a chain
+pub fn last_block(conn: &mut DbConnection, chain_id: i64) -> Result<Option<i64>> {
+    Ok(archived_blocks::table
+        .filter(archived_blocks::chain_id.eq(chain_id))
+        .select(max(archived_blocks::block_number))
+        .first(conn)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{test_pool, DbPool};
+
+    fn block_time(block: i64) -> NaiveDateTime {
+        chrono::DateTime::from_timestamp(1_790_000_000 + block * 2, 0).unwrap().naive_utc()
+    }
+
+    /// `count` logs of transaction `block`, each with one more topic than the last, up to four
+    fn logs(block: i64, count: usize) -> Vec<Log> {
+        (0..count)
+            .map(|i| Log {
+                address: Address::from_low_u64_be(i as u64 + 1),
+                topics: (0..=i % 4).map(|topic| H256::from_low_u64_be(topic as u64)).collect(),
+                data: Bytes::from(vec![i as u8; i % 3]),
+                transaction_hash: Some(H256::from_low_u64_be(block as u64)),
+                transaction_index: Some(U64::from(i / 2)),
+                log_index: Some(U256::from(i)),
+                ..Log::default()
+            })
+            .collect()
+    }
+
+    fn store(pool: &DbPool, chain_id: i64, block: i64, logs: &[Log]) {
+        let hash = H256::from_low_u64_be(block as u64 + 1000);
+        BlockLogs::new(chain_id, block, hash, block_time(block), logs)
+            .store(&mut pool.get().unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn archived_logs_read_back_in_block_order() {
+        let (_dir, pool) = test_pool();
+        // More than one insert's worth of logs, in one block
+        let written = logs(10, LOG_CHUNK * 2 + 5);
+        store(&pool, 137, 10, &written);
+        store(&pool, 137, 11, &[]);
+        store(&pool, 137, 12, &logs(12, 1));
+        store(&pool, 1, 10, &logs(10, 3));
+
+        let mut conn = pool.get().unwrap();
+        let blocks = load_blocks(&mut conn, 137, 10, 11).unwrap();
+        assert_eq!(blocks.iter().map(|(block, _)| block.block_number).collect::<Vec<_>>(), vec![10, 11]);
+
+        let (block, read) = &blocks[0];
+        assert_eq!(block.log_count, written.len() as i64);
+        assert_eq!(block.block_timestamp, block_time(10));
+        assert_eq!(read.len(), written.len());
+        for (read, written) in read.iter().zip(&written) {
+            assert_eq!(read.address, written.address);
+            assert_eq!(read.topics, written.topics);
+            assert_eq!(read.data, written.data);
+            assert_eq!(read.transaction_hash, written.transaction_hash);
+            assert_eq!(read.transaction_index, written.transaction_index);
+            assert_eq!(read.log_index, written.log_index);
+            assert_eq!(read.block_hash, Some(H256::from_low_u64_be(1010)));
+        }
+        assert!(blocks[1].1.is_empty());
+
+        assert_eq!(last_block(&mut conn, 137).unwrap(), Some(12));
+        assert_eq!(last_block(&mut conn, 56).unwrap(), None);
+    }
+
+    #[test]
+    fn storing_a_block_again_replaces_its_logs() {
+        let (_dir, pool) = test_pool();
+        store(&pool, 137, 10, &logs(10, 5));
+        store(&pool, 137, 10, &logs(10, 2));
+
+        let blocks = load_blocks(&mut pool.get().unwrap(), 137, 0, 100).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].0.log_count, 2);
+        assert_eq!(blocks[0].1.len(), 2);
+    }
+}
@@ -0,0 +1,326 @@
+use axum::{
+    extract::{Query, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use diesel::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::{
+    database::DbPool,
+    models::ApiKey,
+    server::ServerState,
+    utils::current_timestamp,
+    Config, IndexerError, Result,
+};
+
+/// Limits applied to file-based keys that don't set their own
+pub const DEFAULT_RATE_LIMIT_PER_MINUTE: i32 = 120;
+pub const DEFAULT_MAX_CONNECTIONS: i32 = 5;
+
+/// Entry in the `API_KEYS_FILE` JSON array
+#[derive(Debug, Deserialize)]
+struct KeyFileEntry {
+    key: String,
+    name: String,
+    rate_limit_per_minute: Option<i32>,
+    max_connections: Option<i32>,
+}
+
+/// The key a request was authenticated with, stored in request extensions
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub key_hash: String,
+    pub name: String,
+    pub rate_limit_per_minute: i32,
+    pub max_connections: i32,
+}
+
+/// Token bucket refilled continuously at `rate_limit_per_minute / 60` tokens per second
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// API key verification, per-key request rate limiting and streaming connection caps
+pub struct Auth {
+    required: bool,
+    pool: DbPool,
+    file_keys: HashMap<String, Caller>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    connections: Arc<Mutex<HashMap<String, i32>>>,
+}
+
+impl Auth {
+    pub fn new(config: &Config, pool: DbPool) -> Result<Self> {
+        let file_keys = match &config.api_keys_file {
+            Some(path) => load_key_file(path)?,
+            None => HashMap::new(),
+        };
+        let required = config.require_api_key || !file_keys.is_empty();
+        if required {
+            info!("🔑 API key authentication enabled ({} keys from file)", file_keys.len());
+        }
+
+        Ok(Self {
+            required,
+            pool,
+            file_keys,
+            buckets: Mutex::new(HashMap::new()),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// Resolves a presented key against the key file first, then the `api_keys` table
+    pub fn authenticate(&self, presented: &str) -> Result<Caller> {
+        let hash = hash_key(presented);
+        if let Some(caller) = self.file_keys.get(&hash) {
+            return Ok(caller.clone());
+        }
+
+        use crate::schema::api_keys::dsl::*;
+        let mut conn = self.pool.get()?;
+        let row: Option<ApiKey> = api_keys
+            .filter(key_hash.eq(&hash))
+            .filter(enabled.eq(true))
+            .first(&mut conn)
+            .optional()?;
+
+        row.map(|row| Caller {
+            key_hash: row.key_hash,
+            name: row.name,
+            rate_limit_per_minute: row.rate_limit_per_minute,
+            max_connections: row.max_connections,
+        })
+        .ok_or_else(|| IndexerError::Unauthorized("Invalid API key".to_string()))
+    }
+
+    /// Takes one token from the caller's bucket
+    pub fn check_rate(&self, caller: &Caller) -> Result<()> {
+        let capacity = f64::from(caller.rate_limit_per_minute.max(1));
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(caller.key_hash.clone()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * capacity / 60.0).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(IndexerError::RateLimited(format!(
+                "Key '{}' exceeded {} requests per minute",
+                caller.name, caller.rate_limit_per_minute
+            )));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Reserves one streaming connection slot; released when the guard is dropped
+    pub fn acquire_connection(&self, caller: &Caller) -> Result<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap();
+        let open = connections.entry(caller.key_hash.clone()).or_insert(0);
+        if *open >= caller.max_connections {
+            return Err(IndexerError::RateLimited(format!(
+                "Key '{}' already has {} open connections",
+                caller.name, caller.max_connections
+            )));
+        }
+        *open += 1;
+
+        Ok(ConnectionGuard {
+            key_hash: caller.key_hash.clone(),
+            connections: self.connections.clone(),
+        })
+    }
+}
+
+/// Holds a streaming connection slot for as long as the connection lives
+pub struct ConnectionGuard {
+    key_hash: String,
+    connections: Arc<Mutex<HashMap<String, i32>>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(open) = connections.get_mut(&self.key_hash) {
+            *open -= 1;
+            if *open <= 0 {
+                connections.remove(&self.key_hash);
+            }
+        }
+    }
+}
+
+/// Streaming handlers call this to count the connection against the caller's cap.
+/// Returns `None` when authentication is disabled.
+pub fn connection_guard(state: &ServerState, caller: Option<&Caller>) -> Result<Option<ConnectionGuard>> {
+    caller
+        .map(|caller| state.auth.acquire_connection(caller))
+        .transpose()
+}
+
+/// Middleware for /api and /ws: requires a valid key when authentication is enabled
+/// and enforces the key's request rate.
+///
+/// Keys are read from `X-API-Key`, `Authorization: Bearer`, or the `api_key` query
+/// parameter (browsers can't set headers on WebSocket or EventSource requests).
+pub async fn require_api_key(
+    State(state): State<ServerState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response> {
+    if !state.auth.is_required() {
+        return Ok(next.run(request).await);
+    }
+
+    let presented = presented_key(&request)
+        .ok_or_else(|| IndexerError::Unauthorized("Missing API key".to_string()))?;
+    let caller = state.auth.authenticate(&presented)?;
+    state.auth.check_rate(&caller)?;
+
+    request.extensions_mut().insert(caller);
+    Ok(next.run(request).await)
+}
+
+fn presented_key(request: &Request) -> Option<String> {
+    let headers = request.headers();
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.trim().to_string());
+    }
+    if let Some(bearer) = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some(bearer.trim().to_string());
+    }
+
+    Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(mut params)| params.remove("api_key"))
+}
+
+pub fn hash_key(key: &str) -> String {
+    hex::encode(ethers::utils::keccak256(key.as_bytes()))
+}
+
+/// Creates a new random key in the `api_keys` table and returns it in plain text
+pub fn create_key(pool: &DbPool, name: &str, rate_limit_per_minute: i32, max_connections: i32) -> Result<String> {
+    let key = format!("pk_{}", uuid::Uuid::new_v4().simple());
+    let row = ApiKey {
+        key_hash: hash_key(&key),
+        name: name.to_string(),
+        rate_limit_per_minute,
+        max_connections,
+        enabled: true,
+        created_at: current_timestamp(),
+    };
+
+    let mut conn = pool.get()?;
+    diesel::insert_into(crate::schema::api_keys::table)
+        .values(&row)
+        .execute(&mut conn)?;
+
+    Ok(key)
+}
+
+fn load_key_file(path: &str) -> Result<HashMap<String, Caller>> {
+    let contents = std::fs::read_to_string(path)?;
+    let entries: Vec<KeyFileEntry> = serde_json::from_str(&contents)
+        .map_err(|e| IndexerError::Config(format!("Invalid API_KEYS_FILE {}: {}", path, e)))?;
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let key_hash = hash_key(&entry.key);
+            let caller = Caller {
+                key_hash: key_hash.clone(),
+                name: entry.name,
+                rate_limit_per_minute: entry.rate_limit_per_minute.unwrap_or(DEFAULT_RATE_LIMIT_PER_MINUTE),
+                max_connections: entry.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS),
+            };
+            (key_hash, caller)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::test_pool, schema::api_keys};
+
+    fn caller(name: &str, rate_limit_per_minute: i32, max_connections: i32) -> Caller {
+        Caller {
+            key_hash: hash_key(name),
+            name: name.to_string(),
+            rate_limit_per_minute,
+            max_connections,
+        }
+    }
+
+    #[test]
+    fn keys_come_from_the_file_or_the_table() {
+        let (dir, pool) = test_pool();
+        let file = dir.path().join("api_keys.json");
+        std::fs::write(&file, r#"[{"key": "from_file", "name": "dashboard"}]"#).unwrap();
+        let config = Config { api_keys_file: Some(file.to_str().unwrap().to_string()), ..Config::default() };
+        let auth = Auth::new(&config, pool.clone()).unwrap();
+        assert!(auth.is_required());
+
+        let from_file = auth.authenticate("from_file").unwrap();
+        assert_eq!(from_file.name, "dashboard");
+        assert_eq!(from_file.rate_limit_per_minute, DEFAULT_RATE_LIMIT_PER_MINUTE);
+        assert_eq!(from_file.max_connections, DEFAULT_MAX_CONNECTIONS);
+
+        let key = create_key(&pool, "backend", 600, 2).unwrap();
+        assert_eq!(auth.authenticate(&key).unwrap().rate_limit_per_minute, 600);
+
+        diesel::update(api_keys::table).set(api_keys::enabled.eq(false)).execute(&mut pool.get().unwrap()).unwrap();
+        for rejected in [key.as_str(), "unknown", ""] {
+            assert!(matches!(auth.authenticate(rejected), Err(IndexerError::Unauthorized(_))), "{:?}", rejected);
+        }
+    }
+
+    #[test]
+    fn requests_beyond_the_rate_are_limited_per_key() {
+        let (_dir, pool) = test_pool();
+        let auth = Auth::new(&Config::default(), pool).unwrap();
+        assert!(!auth.is_required());
+        let (busy, quiet) = (caller("busy", 3, 1), caller("quiet", 3, 1));
+
+        for _ in 0..3 {
+            auth.check_rate(&busy).unwrap();
+        }
+        assert!(matches!(auth.check_rate(&busy), Err(IndexerError::RateLimited(_))));
+        auth.check_rate(&quiet).unwrap();
+    }
+
+    #[test]
+    fn connection_slots_are_capped_and_released() {
+        let (_dir, pool) = test_pool();
+        let auth = Auth::new(&Config::default(), pool).unwrap();
+        let (streamer, other) = (caller("streamer", 60, 2), caller("other", 60, 1));
+
+        let first = auth.acquire_connection(&streamer).unwrap();
+        let _second = auth.acquire_connection(&streamer).unwrap();
+        assert!(matches!(auth.acquire_connection(&streamer), Err(IndexerError::RateLimited(_))));
+        let _elsewhere = auth.acquire_connection(&other).unwrap();
+
+        drop(first);
+        auth.acquire_connection(&streamer).unwrap();
+    }
+}
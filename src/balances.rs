@@ -0,0 +1,614 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use bigdecimal::{BigDecimal, Zero};
+use diesel::connection::DefaultLoadingMode;
+use diesel::prelude::*;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::str::FromStr;
+use tracing::info;
+use utoipa::IntoParams;
+
+use crate::{
+    database::DbPool,
+    metrics::observe_rpc,
+    models::{Balance, Token, Transfer},
+    schema::{balances, indexer_state, transfers},
+    history::{balances_as_of, AsOf, AsOfQuery},
+    server::{ApiQuery, ServerState},
+    utils::{add_bigdecimal_strings, bigdecimal_to_string, current_timestamp, string_to_bigdecimal, subtract_bigdecimal_strings, to_token_units},
+    IndexerError, Result,
+};
+
+/// Mints come from and burns go to this address; its "balance" means nothing
+pub const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+/// `balanceOf(address)` selector
+const BALANCE_OF_SELECTOR: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+
+/// Default and maximum number of top holders returned
+const DEFAULT_HOLDERS: i64 = 100;
+const MAX_HOLDERS: i64 = 1000;
+
+/// On-chain balances, as `balanceOf` at a block reports them.
+///
+/// Seeding and `verify --balances` go through this so they can run against a stub
+/// instead of a node.
+#[async_trait]
+pub trait BalanceSource {
+    /// Balance of `holder` in token units at the end of `block`
+    async fn balance_of(&self, token: &Token, holder: &str, block: u64) -> Result<BigDecimal>;
+}
+
+/// Reads balances with `eth_call` on a node
+pub struct RpcBalances {
+    provider: Provider<Ws>,
+}
+
+impl RpcBalances {
+    pub async fn connect(ws_url: &str) -> Result<Self> {
+        let provider = Provider::<Ws>::connect(ws_url)
+            .await
+            .map_err(|e| IndexerError::Web3(format!("Failed to connect to {}: {}", ws_url, e)))?;
+        Ok(Self { provider })
+    }
+
+    /// Chain id the node serves
+    pub async fn chain_id(&self) -> Result<u64> {
+        let id = observe_rpc("eth_chainId", self.provider.get_chainid())
+            .await
+            .map_err(IndexerError::Ethereum)?;
+        Ok(id.as_u64())
+    }
+
+    pub async fn head(&self) -> Result<u64> {
+        let head = observe_rpc("eth_blockNumber", self.provider.get_block_number())
+            .await
+            .map_err(IndexerError::Ethereum)?;
+        Ok(head.as_u64())
+    }
+}
+
+#[async_trait]
+impl BalanceSource for RpcBalances {
+    async fn balance_of(&self, token: &Token, holder: &str, block: u64) -> Result<BigDecimal> {
+        let contract = Address::from_str(&token.address)
+            .map_err(|_| IndexerError::Config(format!("Invalid token address '{}'", token.address)))?;
+        let holder = Address::from_str(holder)
+            .map_err(|_| IndexerError::BadRequest(format!("Invalid address '{}'", holder)))?;
+
+        let mut data = BALANCE_OF_SELECTOR.to_vec();
+        data.extend_from_slice(H256::from(holder).as_bytes());
+        let request: TypedTransaction = TransactionRequest::new().to(contract).data(data).into();
+        let output = observe_rpc("eth_call", self.provider.call(&request, Some(BlockId::from(block))))
+            .await
+            .map_err(IndexerError::Ethereum)?;
+        if output.len() < 32 {
+            return Err(IndexerError::Web3(format!("{} returned no balanceOf; is it an ERC-20 contract?", token.address)));
+        }
+        Ok(to_token_units(U256::from_big_endian(&output[..32]), token.decimals as u32))
+    }
+}
+
+/// Applies a stored transfer to the sender's and receiver's balances; runs inside the block's transaction.
+///
+/// Rows seeded at or after the transfer's block already include it.
+pub fn apply_transfer(transfer: &Transfer, conn: &mut SqliteConnection) -> Result<()> {
+    let value = string_to_bigdecimal(&transfer.value);
+    if transfer.from_address != ZERO_ADDRESS {
+        apply_change(transfer, &transfer.from_address, &value, true, conn)?;
+    }
+    if transfer.to_address != ZERO_ADDRESS {
+        apply_change(transfer, &transfer.to_address, &value, false, conn)?;
+    }
+    Ok(())
+}
+
+fn apply_change(transfer: &Transfer, holder: &str, value: &BigDecimal, is_outflow: bool, conn: &mut SqliteConnection) -> Result<()> {
+    let key = (transfer.chain_id, holder, transfer.token.as_str());
+    let existing: Option<Balance> = balances::table.find(key).first(conn).optional()?;
+
+    match existing {
+        Some(row) if row.seeded_block.is_some_and(|seeded| seeded >= transfer.block_number) => {}
+        Some(row) => {
+            let balance = if is_outflow {
+                subtract_bigdecimal_strings(&row.balance, value)
+            } else {
+                add_bigdecimal_strings(&row.balance, value)
+            };
+            diesel::update(balances::table.find(key))
+                .set((
+                    balances::balance.eq(balance),
+                    balances::transfer_count.eq(row.transfer_count + 1),
+                    balances::last_block.eq(row.last_block.max(transfer.block_number)),
+                    balances::last_updated.eq(current_timestamp()),
+                ))
+                .execute(conn)?;
+        }
+        None => {
+            let balance = if is_outflow { -value.clone() } else { value.clone() };
+            let row = Balance {
+                chain_id: transfer.chain_id,
+                address: holder.to_string(),
+                token: transfer.token.clone(),
+                balance: bigdecimal_to_string(&balance),
+                transfer_count: 1,
+                seeded_block: None,
+                last_block: transfer.block_number,
+                last_updated: current_timestamp(),
+            };
+            diesel::insert_into(balances::table).values(&row).execute(conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets each holder's balance of each token to `balanceOf` at `block`, plus the
+/// transfers already indexed after it. Returns the number of rows written.
+pub async fn seed_balances<S: BalanceSource>(
+    source: &S,
+    pool: &DbPool,
+    tokens: &[Token],
+    holders: &[String],
+    block: u64,
+) -> Result<usize> {
+    let mut seeded = 0;
+    for token in tokens {
+        for holder in holders {
+            let on_chain = source.balance_of(token, holder, block).await?;
+
+            let mut conn = pool.get()?;
+            conn.immediate_transaction(|conn| -> Result<()> {
+                // Transfers after the seed block were indexed against the old row; replay them on top
+                let later: Vec<(String, String, String)> = transfers::table
+                    .filter(transfers::chain_id.eq(token.chain_id))
+                    .filter(transfers::token.eq(&token.address))
+                    .filter(transfers::block_number.gt(block as i64))
+                    .filter(transfers::from_address.eq(holder).or(transfers::to_address.eq(holder)))
+                    .select((transfers::from_address, transfers::to_address, transfers::value))
+                    .load(conn)?;
+                let last_block: Option<i64> = transfers::table
+                    .filter(transfers::chain_id.eq(token.chain_id))
+                    .filter(transfers::token.eq(&token.address))
+                    .filter(transfers::from_address.eq(holder).or(transfers::to_address.eq(holder)))
+                    .select(diesel::dsl::max(transfers::block_number))
+                    .first(conn)?;
+
+                let mut balance = on_chain.clone();
+                for (from, to, value) in &later {
+                    let value = string_to_bigdecimal(value);
+                    if from == holder {
+                        balance -= &value;
+                    }
+                    if to == holder {
+                        balance += &value;
+                    }
+                }
+
+                let row = Balance {
+                    chain_id: token.chain_id,
+                    address: holder.clone(),
+                    token: token.address.clone(),
+                    balance: bigdecimal_to_string(&balance),
+                    transfer_count: later.len() as i64,
+                    seeded_block: Some(block as i64),
+                    last_block: last_block.unwrap_or_default().max(block as i64),
+                    last_updated: current_timestamp(),
+                };
+                diesel::replace_into(balances::table).values(&row).execute(conn)?;
+                Ok(())
+            })?;
+
+            info!("🌱 Seeded {} {} balance of {} at block {}", on_chain, token.symbol, holder, block);
+            seeded += 1;
+        }
+    }
+    Ok(seeded)
+}
+
+/// A derived balance that disagrees with `balanceOf`
+#[derive(Debug, Clone, Serialize)]
+pub struct BalanceMismatch {
+    pub chain_id: i64,
+    pub address: String,
+    pub token: String,
+    pub derived: BigDecimal,
+    pub on_chain: BigDecimal,
+    /// Whether the row started from `balanceOf`; unseeded rows only cover indexed history
+    pub seeded: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BalanceReport {
+    pub block: u64,
+    pub checked: usize,
+    /// Rows that changed after `block` while checking, so couldn't be compared
+    pub skipped: usize,
+    pub mismatches: Vec<BalanceMismatch>,
+}
+
+/// Compares the stored balances of `holders` (every row on the chain when `None`)
+/// with `balanceOf` at `block`, which should be the last block the chain's indexer processed.
+pub async fn verify_balances<S: BalanceSource>(
+    source: &S,
+    pool: &DbPool,
+    tokens: &[Token],
+    holders: Option<&[String]>,
+    block: u64,
+) -> Result<BalanceReport> {
+    let mut report = BalanceReport {
+        block,
+        checked: 0,
+        skipped: 0,
+        mismatches: Vec::new(),
+    };
+
+    for token in tokens {
+        let rows: Vec<Balance> = {
+            let mut conn = pool.get()?;
+            let mut query = balances::table
+                .filter(balances::chain_id.eq(token.chain_id))
+                .filter(balances::token.eq(&token.address))
+                .into_boxed();
+            if let Some(holders) = holders {
+                query = query.filter(balances::address.eq_any(holders));
+            }
+            query.order(balances::address.asc()).load(&mut conn)?
+        };
+
+        for row in rows {
+            if row.last_block > block as i64 {
+                report.skipped += 1;
+                continue;
+            }
+            let on_chain = source.balance_of(token, &row.address, block).await?;
+            let derived = string_to_bigdecimal(&row.balance);
+            report.checked += 1;
+            if derived != on_chain {
+                report.mismatches.push(BalanceMismatch {
+                    chain_id: row.chain_id,
+                    address: row.address,
+                    token: row.token,
+                    derived,
+                    on_chain,
+                    seeded: row.seeded_block.is_some(),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Last block the chain's indexer processed; seeding and verification default to it
+pub fn indexed_block(pool: &DbPool, chain_id: i64) -> Result<Option<u64>> {
+    let mut conn = pool.get()?;
+    let block: Option<i64> = indexer_state::table
+        .find(chain_id)
+        .select(indexer_state::current_block)
+        .first(&mut conn)
+        .optional()?;
+    Ok(block.map(|block| block as u64))
+}
+
+/// Holders on a chain with a balance seeded from `balanceOf`
+pub fn seeded_addresses(pool: &DbPool, chain_id: i64) -> Result<Vec<String>> {
+    let mut conn = pool.get()?;
+    let addresses = balances::table
+        .filter(balances::chain_id.eq(chain_id))
+        .filter(balances::seeded_block.is_not_null())
+        .select(balances::address)
+        .distinct()
+        .load(&mut conn)?;
+    Ok(addresses)
+}
+
+/// Stored balances of an address, per chain and token
+pub fn balances_of(pool: &DbPool, address: &str, chain_id: Option<i64>, token_addresses: Option<&[String]>) -> Result<Vec<Balance>> {
+    let mut conn = pool.get()?;
+    let mut query = balances::table.filter(balances::address.eq(address.to_lowercase())).into_boxed();
+    if let Some(chain_id) = chain_id {
+        query = query.filter(balances::chain_id.eq(chain_id));
+    }
+    if let Some(addrs) = token_addresses {
+        query = query.filter(balances::token.eq_any(addrs));
+    }
+    Ok(query.order((balances::chain_id.asc(), balances::token.asc())).load(&mut conn)?)
+}
+
+/// Balance row ranked by amount; ties go to the lower chain id, address and token
+struct Ranked {
+    amount: BigDecimal,
+    row: Balance,
+}
+
+impl Ranked {
+    fn key(&self) -> (&BigDecimal, Reverse<(i64, &str, &str)>) {
+        (&self.amount, Reverse((self.row.chain_id, &self.row.address, &self.row.token)))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Largest positive balances of a token, largest first.
+///
+/// Balances are decimal strings, some in exponent form, beyond the precision of
+/// SQLite's numbers, so they are ranked here, keeping only the best `limit` rows.
+pub fn top_holders(pool: &DbPool, chain_id: Option<i64>, token_addresses: &[String], limit: i64) -> Result<Vec<Balance>> {
+    let limit = limit.clamp(1, MAX_HOLDERS) as usize;
+    let mut conn = pool.get()?;
+    let mut query = balances::table
+        .filter(balances::token.eq_any(token_addresses))
+        .filter(balances::balance.not_like("-%"))
+        .into_boxed();
+    if let Some(chain_id) = chain_id {
+        query = query.filter(balances::chain_id.eq(chain_id));
+    }
+
+    // Min-heap of the best rows so far
+    let mut best = BinaryHeap::with_capacity(limit + 1);
+    for row in query.load_iter::<Balance, DefaultLoadingMode>(&mut conn)? {
+        let row = row?;
+        let amount = string_to_bigdecimal(&row.balance);
+        if amount <= BigDecimal::zero() {
+            continue;
+        }
+        best.push(Reverse(Ranked { amount, row }));
+        if best.len() > limit {
+            best.pop();
+        }
+    }
+    // Ascending order of `Reverse` is largest first
+    Ok(best.into_sorted_vec().into_iter().map(|Reverse(ranked)| ranked.row).collect())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct HoldersQuery {
+    /// Chain id; all chains when omitted
+    pub chain_id: Option<i64>,
+    /// Token address or symbol
+    pub token: String,
+    /// Max holders to return (default 100, max 1000)
+    pub limit: Option<i64>,
+}
+
+/// Token balances of an address, derived from indexed transfers
+#[utoipa::path(
+    get,
+    path = "/api/balances/{address}",
+    tag = "balances",
+    params(("address" = String, Path, description = "Holder address"), AsOfQuery),
+    responses(
+        (status = 200, description = "Balance per chain and token; empty when the address never moved an indexed token", body = Vec<Balance>),
+        (status = 400, description = "Unknown token, or at_block without chain_id on a multi-chain indexer", body = ErrorResponse),
+    )
+)]
+pub async fn get_balances(
+    State(state): State<ServerState>,
+    Path(address): Path<String>,
+    ApiQuery(query): ApiQuery<AsOfQuery>,
+) -> Result<Json<Vec<Balance>>> {
+    let at = AsOf::from_params(query.at_block, query.at_time)?;
+    let token_addresses = state.tokens.resolve_opt(query.token.as_deref(), query.chain_id)?;
+    let mut rows = balances_of(&state.pool, &address, query.chain_id, token_addresses.as_deref())?;
+    if let Some(at) = at {
+        let chain_ids = at.chains(&state.config.chains, query.chain_id)?;
+        rows.retain(|row| chain_ids.contains(&row.chain_id));
+        rows = balances_as_of(rows, &mut state.pool.get()?, at)?;
+    }
+    Ok(Json(rows))
+}
+
+/// Addresses holding the most of a token
+#[utoipa::path(
+    get,
+    path = "/api/holders",
+    tag = "balances",
+    params(HoldersQuery),
+    responses(
+        (status = 200, description = "Holders by balance, largest first", body = Vec<Balance>),
+        (status = 400, description = "Missing or unknown token", body = ErrorResponse),
+    )
+)]
+pub async fn get_top_holders(
+    State(state): State<ServerState>,
+    ApiQuery(query): ApiQuery<HoldersQuery>,
+) -> Result<Json<Vec<Balance>>> {
+    let token_addresses = state.tokens.resolve(&query.token, query.chain_id)?;
+    let limit = query.limit.unwrap_or(DEFAULT_HOLDERS);
+    Ok(Json(top_holders(&state.pool, query.chain_id, &token_addresses, limit)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_pool;
+    use std::collections::HashMap;
+
+    const A: &str = "0x000000000000000000000000000000000000000a";
+    const B: &str = "0x000000000000000000000000000000000000000b";
+    const C: &str = "0x000000000000000000000000000000000000000c";
+
+    /// `balanceOf` answered from a table; unknown holders have nothing
+    #[derive(Default)]
+    struct StubBalances {
+        balances: HashMap<(String, u64), BigDecimal>,
+    }
+
+    impl StubBalances {
+        fn with(mut self, holder: &str, block: u64, balance: &str) -> Self {
+            self.balances.insert((holder.to_string(), block), string_to_bigdecimal(balance));
+            self
+        }
+    }
+
+    #[async_trait]
+    impl BalanceSource for StubBalances {
+        async fn balance_of(&self, _token: &Token, holder: &str, block: u64) -> Result<BigDecimal> {
+            Ok(self.balances.get(&(holder.to_string(), block)).cloned().unwrap_or_default())
+        }
+    }
+
+    fn token() -> Token {
+        Token {
+            chain_id: 137,
+            address: "0x0000000000000000000000000000000000001010".to_string(),
+            symbol: "POL".to_string(),
+            decimals: 18,
+            updated_at: current_timestamp(),
+        }
+    }
+
+    /// Stores a transfer and applies it to balances, as the indexer does
+    fn index(pool: &DbPool, block: i64, from: &str, to: &str, value: &str) {
+        let mut conn = pool.get().unwrap();
+        let log_index: i64 = transfers::table
+            .filter(transfers::block_number.eq(block))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        let transfer = Transfer {
+            id: format!("137_0x{:02x}_{}", block, log_index),
+            chain_id: 137,
+            block_number: block,
+            transaction_hash: format!("0x{:02x}", block),
+            from_address: from.to_string(),
+            to_address: to.to_string(),
+            token: token().address,
+            value: value.to_string(),
+            timestamp: current_timestamp(),
+            is_binance_related: false,
+            is_watched: false,
+            log_index: Some(log_index),
+            transaction_index: Some(0),
+        };
+        diesel::insert_into(transfers::table).values(&transfer).execute(&mut conn).unwrap();
+        apply_transfer(&transfer, &mut conn).unwrap();
+    }
+
+    fn balance(pool: &DbPool, holder: &str) -> Balance {
+        balances::table
+            .find((137, holder, token().address))
+            .first(&mut pool.get().unwrap())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn seeding_replays_later_transfers_and_skips_earlier_ones() {
+        let (_dir, pool) = test_pool();
+        index(&pool, 10, A, B, "5");
+        index(&pool, 20, B, C, "2");
+        index(&pool, 30, C, B, "1");
+
+        // balanceOf at block 20 already includes blocks 10 and 20
+        let source = StubBalances::default().with(B, 20, "100");
+        assert_eq!(seed_balances(&source, &pool, &[token()], &[B.to_string()], 20).await.unwrap(), 1);
+        let seeded = balance(&pool, B);
+        assert_eq!(string_to_bigdecimal(&seeded.balance), string_to_bigdecimal("101"));
+        assert_eq!((seeded.seeded_block, seeded.last_block, seeded.transfer_count), (Some(20), 30, 1));
+
+        // A late transfer at or before the seed block is part of balanceOf already
+        index(&pool, 20, A, B, "7");
+        index(&pool, 15, A, B, "7");
+        assert_eq!(string_to_bigdecimal(&balance(&pool, B).balance), string_to_bigdecimal("101"));
+
+        // Later ones apply as usual
+        index(&pool, 40, B, A, "3");
+        let updated = balance(&pool, B);
+        assert_eq!(string_to_bigdecimal(&updated.balance), string_to_bigdecimal("98"));
+        assert_eq!(updated.last_block, 40);
+
+        // Unseeded rows still take every transfer
+        assert_eq!(string_to_bigdecimal(&balance(&pool, A).balance), string_to_bigdecimal("-16"));
+    }
+
+    #[tokio::test]
+    async fn verification_reports_mismatches_and_skips_newer_rows() {
+        let (_dir, pool) = test_pool();
+        index(&pool, 10, A, B, "5");
+        index(&pool, 20, B, C, "2");
+        index(&pool, 30, C, B, "1");
+        let source = StubBalances::default().with(B, 20, "100");
+        seed_balances(&source, &pool, &[token()], &[B.to_string()], 20).await.unwrap();
+
+        // A's row only covers indexed history, so it disagrees with the chain
+        let source = StubBalances::default().with(A, 30, "50").with(B, 30, "101").with(C, 30, "1");
+        let report = verify_balances(&source, &pool, &[token()], None, 30).await.unwrap();
+        assert_eq!((report.checked, report.skipped), (3, 0));
+        assert_eq!(report.mismatches.len(), 1);
+        let mismatch = &report.mismatches[0];
+        assert_eq!(mismatch.address, A);
+        assert_eq!((mismatch.derived.clone(), mismatch.on_chain.clone()), (string_to_bigdecimal("-5"), string_to_bigdecimal("50")));
+        assert!(!mismatch.seeded);
+
+        // Rows changed after the block can't be compared with it
+        let report = verify_balances(&source, &pool, &[token()], None, 20).await.unwrap();
+        assert_eq!((report.checked, report.skipped), (1, 2));
+
+        let only_b = [B.to_string()];
+        let report = verify_balances(&source, &pool, &[token()], Some(&only_b), 30).await.unwrap();
+        assert_eq!((report.checked, report.mismatches.len()), (1, 0));
+    }
+
+    #[test]
+    fn top_holders_rank_balances_exactly() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        let rows = [
+            ("0x01", "9007199254740992"),
+            ("0x02", "9007199254740993"),
+            ("0x03", "1e+30"),
+            ("0x04", "0.5"),
+            ("0x05", "-3"),
+            ("0x06", "0"),
+            ("0x07", "9007199254740993"),
+        ];
+        for (address, amount) in rows {
+            let row = Balance {
+                chain_id: 137,
+                address: address.to_string(),
+                token: token().address,
+                balance: amount.to_string(),
+                transfer_count: 1,
+                seeded_block: None,
+                last_block: 1,
+                last_updated: current_timestamp(),
+            };
+            diesel::insert_into(balances::table).values(&row).execute(&mut conn).unwrap();
+        }
+
+        let ranked = |limit| -> Vec<String> {
+            top_holders(&pool, Some(137), &[token().address], limit)
+                .unwrap()
+                .into_iter()
+                .map(|row| row.address)
+                .collect()
+        };
+        // 2^53 and 2^53 + 1 are the same REAL
+        assert_eq!(ranked(100), vec!["0x03", "0x02", "0x07", "0x01", "0x04"]);
+        assert_eq!(ranked(2), vec!["0x03", "0x02"]);
+    }
+}
@@ -0,0 +1,69 @@
+use async_graphql::SimpleObject;
+use axum::{extract::State, Json};
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::{
+    config::ChainConfig,
+    database::DbPool,
+    models::IndexerState,
+    schema::indexer_state,
+    server::ServerState,
+    Result,
+};
+
+/// A configured chain and the progress its indexer last recorded
+#[derive(Debug, Clone, Serialize, ToSchema, SimpleObject)]
+pub struct ChainStatus {
+    pub chain_id: i64,
+    pub name: String,
+    pub confirmations: i64,
+    /// `None` until the chain's indexer first starts
+    pub current_block: Option<i64>,
+    pub chain_head: Option<i64>,
+    pub is_running: bool,
+    #[schema(value_type = Option<String>)]
+    pub last_block_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Progress of every configured chain, in configuration order
+pub fn chain_statuses(pool: &DbPool, chains: &[ChainConfig]) -> Result<Vec<ChainStatus>> {
+    let mut conn = pool.get()?;
+    let states: HashMap<i64, IndexerState> = indexer_state::table
+        .load::<IndexerState>(&mut conn)?
+        .into_iter()
+        .map(|state| (state.chain_id, state))
+        .collect();
+
+    Ok(chains
+        .iter()
+        .map(|chain| {
+            let state = states.get(&(chain.chain_id as i64));
+            ChainStatus {
+                chain_id: chain.chain_id as i64,
+                name: chain.name.clone(),
+                confirmations: chain.confirmations as i64,
+                current_block: state.map(|state| state.current_block),
+                chain_head: state.map(|state| state.chain_head),
+                is_running: state.is_some_and(|state| state.is_running),
+                last_block_at: state.map(|state| state.last_block_at),
+            }
+        })
+        .collect())
+}
+
+/// Configured chains with their indexing progress
+#[utoipa::path(
+    get,
+    path = "/api/chains",
+    tag = "indexer",
+    responses(
+        (status = 200, description = "Configured chains", body = Vec<ChainStatus>),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    )
+)]
+pub async fn list_chains(State(state): State<ServerState>) -> Result<Json<Vec<ChainStatus>>> {
+    Ok(Json(chain_statuses(&state.pool, &state.config.chains)?))
+}
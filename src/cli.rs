@@ -0,0 +1,1137 @@
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use crate::{
+    alerts::AlertEngine,
+    archive,
+    auth::{create_key, DEFAULT_MAX_CONNECTIONS, DEFAULT_RATE_LIMIT_PER_MINUTE},
+    balances::{indexed_block, seed_balances, seeded_addresses, verify_balances, RpcBalances},
+    database::{create_pool, run_migrations, DbPool},
+    events::EventBus,
+    export::{Dataset, ExportFilter, ExportFormat, ExportSource, Interval},
+    history::AsOf,
+    indexer::ChainIndexer,
+    labels::Labels,
+    outbox::Outbox,
+    rebuild::rebuild_chain,
+    server::Server,
+    shutdown::{self, Shutdown},
+    tokens::TokenSet,
+    verify::{repair_net_flows, verify_net_flows},
+    watchlists::WatchSet,
+    config::{ChainConfig, TokenConfig},
+    coverage,
+    Config, IndexerError, Result,
+};
+
+/// Real-time token transfer indexer for Polygon and other EVM chains.
+///
+/// Settings come from the environment (and `.env`); flags override them.
+#[derive(Debug, Parser)]
+#[command(name = "polygon-indexer", version, about)]
+pub struct Cli {
+    /// TOML config file [env: CONFIG_FILE; default: ./config.toml when present]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Defaults to `run`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Index new blocks and serve the API in one process
+    Run {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        node: NodeArgs,
+        #[command(flatten)]
+        http: HttpArgs,
+    },
+    /// Serve the API without indexing
+    Serve {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        http: HttpArgs,
+    },
+    /// Index new blocks and evaluate alerts without serving the API
+    Index {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        node: NodeArgs,
+    },
+    /// Index a past block range of one chain, then exit
+    Backfill {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        node: NodeArgs,
+        /// First block to index
+        #[arg(long)]
+        from: u64,
+        /// Last block to index, inclusive; defaults to the chain head, or the last archived block
+        #[arg(long)]
+        to: Option<u64>,
+        /// Decode the blocks' logs from the local archive instead of fetching them from the node
+        #[arg(long)]
+        from_archive: bool,
+    },
+    /// Apply pending database migrations
+    Migrate {
+        #[command(flatten)]
+        db: DbArgs,
+    },
+    /// Delete the database and recreate it from migrations
+    Reset {
+        #[command(flatten)]
+        db: DbArgs,
+        /// Confirm deleting all indexed data
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Recompute net flows from stored transfers, report mismatches and unindexed block ranges
+    Verify {
+        #[command(flatten)]
+        db: DbArgs,
+        /// Overwrite mismatched net flows with the recomputed ones
+        #[arg(long, conflicts_with = "balances")]
+        repair: bool,
+        /// Compare one chain's derived balances with on-chain balanceOf at its last indexed block instead
+        #[arg(long)]
+        balances: bool,
+        /// With --balances: holders to check; labelled, watched and seeded addresses when omitted
+        #[arg(long = "address", value_name = "ADDRESS", requires = "balances")]
+        addresses: Vec<String>,
+        /// With --balances: check every stored balance of the chain
+        #[arg(long, requires = "balances", conflicts_with = "addresses")]
+        all: bool,
+        #[command(flatten)]
+        node: NodeArgs,
+    },
+    /// Reclassify stored transfers against the current labels and watchlists, then rebuild net flows and snapshots
+    Rebuild {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        node: NodeArgs,
+    },
+    /// Export transfers, net flows or hourly/daily aggregates as CSV or Parquet
+    Export {
+        #[command(flatten)]
+        db: DbArgs,
+        #[arg(value_enum, default_value_t)]
+        dataset: Dataset,
+        #[command(flatten)]
+        filter: ExportArgs,
+        /// Defaults to Parquet for a .parquet output file, CSV otherwise
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Output file; stdout when omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Manage balances derived from transfers
+    Balances {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(subcommand)]
+        command: BalancesCommand,
+    },
+    /// Inspect address labels
+    Labels {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(subcommand)]
+        command: LabelsCommand,
+    },
+    /// Inspect the effective configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage API keys
+    ApiKey {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(subcommand)]
+        command: ApiKeyCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BalancesCommand {
+    /// Start holders' balances on one chain from balanceOf at a block, replaying later indexed transfers on top
+    Seed {
+        #[command(flatten)]
+        node: NodeArgs,
+        /// Block to read balanceOf at; defaults to the chain's last indexed block
+        #[arg(long)]
+        block: Option<u64>,
+        /// Holder to seed, repeatable; labelled and watched addresses when omitted
+        #[arg(long = "address", value_name = "ADDRESS")]
+        addresses: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LabelsCommand {
+    /// List every labelled and watched address
+    List,
+    /// Show the labels of one address
+    Lookup { address: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and print it with secrets redacted
+    Check {
+        #[command(flatten)]
+        db: DbArgs,
+        #[command(flatten)]
+        node: NodeArgs,
+        #[command(flatten)]
+        http: HttpArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ApiKeyCommand {
+    /// Create a key and print it; it is not shown again
+    Create {
+        name: String,
+        #[arg(long, default_value_t = DEFAULT_RATE_LIMIT_PER_MINUTE)]
+        rate_limit: i32,
+        #[arg(long, default_value_t = DEFAULT_MAX_CONNECTIONS)]
+        max_connections: i32,
+    },
+}
+
+#[derive(Debug, Default, Args)]
+pub struct DbArgs {
+    /// SQLite database path [env: DATABASE_URL]
+    #[arg(long)]
+    pub database_url: Option<String>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct NodeArgs {
+    /// Configured chain to index, repeatable; all configured chains when omitted
+    #[arg(long = "chain", value_name = "NAME")]
+    pub chains: Vec<String>,
+    /// WebSocket endpoint of the selected chain [env: <CHAIN>_WS_URL]
+    #[arg(long)]
+    pub ws_url: Option<String>,
+    /// HTTP endpoint of the selected chain [env: <CHAIN>_RPC_URL]
+    #[arg(long)]
+    pub rpc_url: Option<String>,
+    /// Token contract to index on the selected chain, repeatable; symbol and decimals are read from the contract [env: <CHAIN>_TOKENS]
+    #[arg(long = "token", value_name = "ADDRESS")]
+    pub tokens: Vec<String>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct HttpArgs {
+    /// Address to bind the API to [env: HOST]
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Port to bind the API to [env: PORT]
+    #[arg(long)]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ExportArgs {
+    /// Only transfers on this chain
+    #[arg(long)]
+    pub chain_id: Option<i64>,
+    #[arg(long)]
+    pub from_block: Option<i64>,
+    #[arg(long)]
+    pub to_block: Option<i64>,
+    /// Only transfers at or after this RFC 3339 block time
+    #[arg(long)]
+    pub from_time: Option<DateTime<Utc>>,
+    /// Only transfers before this RFC 3339 block time
+    #[arg(long)]
+    pub to_time: Option<DateTime<Utc>>,
+    /// Only transfers sent or received by this address, or only its flows
+    #[arg(long)]
+    pub address: Option<String>,
+    /// Only transfers of this token (address or symbol)
+    #[arg(long)]
+    pub token: Option<String>,
+    #[arg(long)]
+    pub binance_only: bool,
+    #[arg(long)]
+    pub watched_only: bool,
+    /// Bucket width of aggregates
+    #[arg(long, value_enum, default_value_t)]
+    pub interval: Interval,
+    /// Net flows at the end of this block instead of now
+    #[arg(long, conflicts_with = "at_time")]
+    pub at_block: Option<i64>,
+    /// Net flows at this RFC 3339 block time instead of now
+    #[arg(long)]
+    pub at_time: Option<DateTime<Utc>>,
+}
+
+impl DbArgs {
+    fn apply(&self, config: &mut Config) {
+        if let Some(url) = &self.database_url {
+            config.database_url = url.clone();
+        }
+    }
+}
+
+impl NodeArgs {
+    /// Endpoint and token flags describe one chain, so they need exactly one selected
+    fn apply(&self, config: &mut Config) -> Result<()> {
+        if self.ws_url.is_none() && self.rpc_url.is_none() && self.tokens.is_empty() {
+            return Ok(());
+        }
+        let selected = config.select_chains(&self.chains)?;
+        let [target] = selected.as_slice() else {
+            return Err(IndexerError::Config(
+                "--ws-url, --rpc-url and --token apply to one chain; select it with --chain".to_string(),
+            ));
+        };
+        let chain = config
+            .chains
+            .iter_mut()
+            .find(|chain| chain.name == target.name)
+            .expect("selected from the configured chains");
+
+        if let Some(url) = &self.ws_url {
+            chain.ws_url = url.clone();
+        }
+        if let Some(url) = &self.rpc_url {
+            chain.rpc_url = Some(url.clone());
+        }
+        if !self.tokens.is_empty() {
+            chain.tokens = self.tokens.iter().map(|address| TokenConfig::from_address(address)).collect();
+        }
+        Ok(())
+    }
+}
+
+impl HttpArgs {
+    fn apply(&self, config: &mut Config) {
+        if let Some(host) = &self.host {
+            config.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+    }
+}
+
+/// Everything but the token, which is resolved against the database, and the as-of point
+impl From<ExportArgs> for ExportFilter {
+    fn from(args: ExportArgs) -> Self {
+        Self {
+            chain_id: args.chain_id,
+            from_block: args.from_block,
+            to_block: args.to_block,
+            from_time: args.from_time.map(|time| time.naive_utc()),
+            to_time: args.to_time.map(|time| time.naive_utc()),
+            address: args.address,
+            token: None,
+            binance_only: args.binance_only,
+            watched_only: args.watched_only,
+            interval: args.interval,
+            at: None,
+        }
+    }
+}
+
+impl Command {
+    /// Applies this command's flags on top of the file and environment settings
+    fn apply_overrides(&self, config: &mut Config) -> Result<()> {
+        let (db, node, http) = match self {
+            Command::Run { db, node, http } | Command::Config { command: ConfigCommand::Check { db, node, http } } => {
+                (Some(db), Some(node), Some(http))
+            }
+            Command::Serve { db, http } => (Some(db), None, Some(http)),
+            Command::Index { db, node }
+            | Command::Backfill { db, node, .. }
+            | Command::Verify { db, node, .. }
+            | Command::Rebuild { db, node }
+            | Command::Balances { db, command: BalancesCommand::Seed { node, .. } } => (Some(db), Some(node), None),
+            Command::Migrate { db }
+            | Command::Reset { db, .. }
+            | Command::Export { db, .. }
+            | Command::Labels { db, .. }
+            | Command::ApiKey { db, .. } => (Some(db), None, None),
+        };
+
+        if let Some(db) = db {
+            db.apply(config);
+        }
+        if let Some(node) = node {
+            node.apply(config)?;
+        }
+        if let Some(http) = http {
+            http.apply(config);
+        }
+        Ok(())
+    }
+
+    /// Names passed with `--chain`; empty selects every configured chain
+    fn chain_names(&self) -> &[String] {
+        match self {
+            Command::Run { node, .. }
+            | Command::Index { node, .. }
+            | Command::Backfill { node, .. }
+            | Command::Verify { node, .. }
+            | Command::Rebuild { node, .. }
+            | Command::Balances { command: BalancesCommand::Seed { node, .. }, .. } => &node.chains,
+            _ => &[],
+        }
+    }
+
+    fn connects_to_node(&self) -> bool {
+        matches!(
+            self,
+            Command::Run { .. } | Command::Index { .. } | Command::Backfill { .. } | Command::Balances { .. }
+        ) || matches!(self, Command::Verify { balances: true, .. })
+    }
+}
+
+impl Cli {
+    pub async fn execute(self) -> Result<()> {
+        let command = self.command.unwrap_or(Command::Run {
+            db: DbArgs::default(),
+            node: NodeArgs::default(),
+            http: HttpArgs::default(),
+        });
+
+        let file = Config::locate_file(self.config.as_deref());
+        let mut config = Config::load(file.as_deref())?;
+        command.apply_overrides(&mut config)?;
+
+        if let Command::Config { command: ConfigCommand::Check { .. } } = command {
+            return check_config(&config, file.as_deref());
+        }
+
+        config.validate()?;
+        let chains = config.select_chains(command.chain_names())?;
+        if command.connects_to_node() {
+            Config::require_node(&chains)?;
+        }
+        if let Some(path) = &file {
+            info!("⚙️ Loaded configuration from {}", path.display());
+        }
+
+        match command {
+            Command::Run { .. } => run(config, chains, true, true).await,
+            Command::Serve { .. } => run(config, Vec::new(), false, true).await,
+            Command::Index { .. } => run(config, chains, true, false).await,
+            Command::Backfill { from, to, from_archive, .. } => backfill(config, chains, from, to, from_archive).await,
+            Command::Migrate { .. } => {
+                info!("🔄 Running migrations on {}", config.database_url);
+                open_database(&config)?;
+                info!("✅ Database is up to date");
+                Ok(())
+            }
+            Command::Reset { yes, .. } => reset(&config, yes),
+            Command::Verify { balances: false, repair, .. } => verify(&config, &chains, repair),
+            Command::Verify { balances: true, addresses, all, .. } => {
+                verify_chain_balances(&config, single_chain(chains, "verify --balances")?, addresses, all).await
+            }
+            Command::Balances { command: BalancesCommand::Seed { block, addresses, .. }, .. } => {
+                seed(&config, single_chain(chains, "balances seed")?, block, addresses).await
+            }
+            Command::Rebuild { .. } => rebuild(&config, &chains),
+            Command::Export { dataset, filter, format, output, .. } => {
+                export(&config, dataset, filter, format, output.as_deref())
+            }
+            Command::Labels { command, .. } => labels(&config, command),
+            Command::Config { .. } => unreachable!("handled before validation"),
+            Command::ApiKey { command, .. } => api_key(&config, command),
+        }
+    }
+}
+
+/// Prints the effective configuration, then fails if it doesn't validate
+fn check_config(config: &Config, file: Option<&Path>) -> Result<()> {
+    match file {
+        Some(path) => println!("# Config file: {}", path.display()),
+        None => println!("# No config file; defaults and environment only"),
+    }
+    let rendered = toml::to_string(&config.to_redacted_file())
+        .map_err(|e| IndexerError::Config(format!("Cannot render configuration: {}", e)))?;
+    print!("{}", rendered);
+
+    config.validate()?;
+    if let Err(e) = Config::require_node(&config.chains) {
+        warn!("⚠️ {}; until then those chains can't be indexed", e);
+    }
+    info!("✅ Configuration is valid");
+    Ok(())
+}
+
+fn open_database(config: &Config) -> Result<DbPool> {
+    let pool = create_pool(&config.database_url)?;
+    run_migrations(&mut pool.get()?, config.legacy_pol_contract.as_deref())?;
+    Ok(pool)
+}
+
+/// How often a node picks up watchlist changes made through the API of another process
+const WATCHLIST_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often a node picks up labels published by `rebuild` or an indexer in another process
+const LABEL_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often an API node picks up tokens registered by an indexer in another process
+const TOKEN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often an indexer looks for webhook deliveries abandoned by a crash or restart
+const WEBHOOK_RESUME_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the indexer and open connections get to wind down before they are aborted
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs an indexer per selected chain, the API or both until Ctrl-C or SIGTERM.
+///
+/// The indexers record their progress and events in the database, so any number
+/// of `serve` processes can run next to them without a node connection.
+async fn run(config: Config, chains: Vec<ChainConfig>, with_indexer: bool, with_server: bool) -> Result<()> {
+    info!("🌌 Starting Polygon Token Indexer");
+
+    let pool = open_database(&config)?;
+    let outbox = Outbox::new(pool.clone());
+    let events = if with_indexer {
+        EventBus::new().with_outbox()
+    } else {
+        EventBus::new()
+    };
+    outbox.restore(&events)?;
+    let watchlists = WatchSet::load(&pool)?;
+    let labels = Labels::load(&config, &pool)?;
+
+    let mut indexers = Vec::new();
+    if with_indexer {
+        for chain in chains {
+            let indexer = ChainIndexer::new(chain, pool.clone(), events.clone(), watchlists.clone(), labels.clone()).await?;
+            indexers.push(Arc::new(indexer));
+        }
+    }
+    // Loaded after the indexers have registered their tokens
+    let tokens = TokenSet::load(&pool)?;
+    let alert_engine =
+        AlertEngine::from_config(&config, pool.clone(), events.clone(), tokens.clone(), labels.clone())?.map(Arc::new);
+
+    let shutdown = Shutdown::new();
+    // Loops with nothing to finish; aborted on shutdown
+    let mut background = Vec::new();
+
+    // Every node, so watchlist edits made on one API node show up on the others
+    background.push(tokio::spawn(watchlists.clone().refresh_every(pool.clone(), WATCHLIST_REFRESH_INTERVAL)));
+    // Indexers check for published labels per block; this keeps API and alert lookups current
+    background.push(tokio::spawn(labels.clone().refresh_every(pool.clone(), LABEL_REFRESH_INTERVAL)));
+    if with_indexer {
+        background.push(tokio::spawn(outbox.clone().prune(Duration::from_secs(config.event_retention_secs))));
+    } else {
+        let poll_interval = Duration::from_millis(config.event_poll_interval_ms.max(1));
+        background.push(tokio::spawn(outbox.follow(events.clone(), poll_interval)));
+        background.push(tokio::spawn(tokens.clone().refresh_every(pool.clone(), TOKEN_REFRESH_INTERVAL)));
+    }
+
+    // Alerts are evaluated, and their webhooks sent, where the transfers are indexed
+    let alerting = alert_engine.clone().filter(|_| with_indexer);
+    if let Some(engine) = &alerting {
+        background.push(tokio::spawn(engine.clone().run()));
+        background.push(tokio::spawn(engine.clone().resume_deliveries(WEBHOOK_RESUME_INTERVAL)));
+    }
+
+    // Tasks that are allowed to finish their current work on shutdown
+    let mut draining = Vec::new();
+
+    if with_server {
+        let server = Server::new(config.clone(), pool, events, alert_engine, watchlists, tokens, labels, shutdown.clone())?;
+        draining.push(tokio::spawn(async move {
+            if let Err(e) = server.start().await {
+                warn!("❌ Server error: {}", e);
+            }
+        }));
+        info!("🚀 Polygon Indexer API running at http://{}:{}", config.host, config.port);
+    }
+
+    for indexer in &indexers {
+        let indexer = indexer.clone();
+        draining.push(tokio::spawn(async move {
+            if let Err(e) = indexer.start().await {
+                warn!("❌ Indexer error: {}", e);
+            }
+        }));
+    }
+
+    shutdown::signal().await;
+    info!("🛑 Shutting down: finishing the current block and closing client connections");
+
+    shutdown.trigger();
+    for indexer in &indexers {
+        indexer.stop().await?;
+    }
+    // No new alerts once the indexers have stopped; let sent webhooks finish
+    if let Some(engine) = alerting {
+        draining.push(tokio::spawn(async move { engine.drain_deliveries().await }));
+    }
+
+    let drained = tokio::time::timeout(SHUTDOWN_TIMEOUT, futures::future::join_all(draining.iter_mut())).await;
+    if drained.is_err() {
+        warn!("⚠️ Shutdown took longer than {:?}; aborting remaining tasks", SHUTDOWN_TIMEOUT);
+        draining.iter().for_each(|handle| handle.abort());
+    }
+    background.iter().for_each(|handle| handle.abort());
+
+    info!("👋 Polygon Indexer stopped gracefully");
+    Ok(())
+}
+
+/// The one chain a command works on, for commands that can't span several
+fn single_chain(chains: Vec<ChainConfig>, command: &str) -> Result<ChainConfig> {
+    let [chain] = <[ChainConfig; 1]>::try_from(chains).map_err(|_| {
+        IndexerError::BadRequest(format!("`{}` works on one chain at a time; select it with --chain", command))
+    })?;
+    Ok(chain)
+}
+
+async fn backfill(config: Config, chains: Vec<ChainConfig>, from: u64, to: Option<u64>, from_archive: bool) -> Result<()> {
+    let chain = single_chain(chains, "backfill")?;
+    let chain_id = chain.chain_id as i64;
+    let pool = open_database(&config)?;
+    let watchlists = WatchSet::load(&pool)?;
+    // The node is still needed once, to register tokens added since the blocks were archived
+    let labels = Labels::from_config(&config);
+    let indexer = ChainIndexer::new(chain, pool.clone(), EventBus::new(), watchlists, labels).await?;
+
+    let to = match to {
+        Some(to) => to,
+        None if from_archive => archive::last_block(&mut pool.get()?, chain_id)?
+            .ok_or_else(|| IndexerError::NotFound("No blocks of this chain are archived".to_string()))?
+            as u64,
+        None => indexer.get_chain_head().await,
+    };
+    if from > to {
+        return Err(IndexerError::BadRequest(format!("--from {} is after --to {}", from, to)));
+    }
+
+    // Each block is committed atomically, so stopping between awaits loses at most the block in progress
+    tokio::select! {
+        result = async {
+            if from_archive {
+                indexer.replay(from, to).await
+            } else {
+                indexer.backfill(from, to).await
+            }
+        } => result,
+        _ = shutdown::signal() => {
+            warn!("⚠️ Backfill interrupted; rerun it to index the remaining blocks");
+            Ok(())
+        }
+    }
+}
+
+fn reset(config: &Config, confirmed: bool) -> Result<()> {
+    if !confirmed {
+        return Err(IndexerError::BadRequest(format!(
+            "This deletes {}; pass --yes to confirm",
+            config.database_url
+        )));
+    }
+
+    if Path::new(&config.database_url).exists() {
+        info!("🔥 Removing existing database: {}", config.database_url);
+        std::fs::remove_file(&config.database_url)?;
+    } else {
+        warn!("⚠️  Database file not found: {}", config.database_url);
+    }
+    // WAL side files would otherwise be replayed into the new database
+    for suffix in ["-wal", "-shm"] {
+        let path = format!("{}{}", config.database_url, suffix);
+        if Path::new(&path).exists() {
+            std::fs::remove_file(&path)?;
+        }
+    }
+
+    open_database(config)?;
+    info!("✅ Database recreated");
+    Ok(())
+}
+
+fn verify(config: &Config, chains: &[ChainConfig], repair: bool) -> Result<()> {
+    let pool = open_database(config)?;
+    let watchlists = WatchSet::load(&pool)?;
+    let tokens = TokenSet::load(&pool)?;
+    let chain_ids: Vec<i64> = chains.iter().map(|chain| chain.chain_id as i64).collect();
+    let report = verify_net_flows(&pool, &chain_ids, &Labels::load(config, &pool)?, &watchlists)?;
+
+    for mismatch in &report.mismatches {
+        let computed = &mismatch.computed;
+        let chain = config.chain_name(mismatch.chain_id);
+        let symbol = tokens.symbol_of(mismatch.chain_id, &mismatch.token);
+        match &mismatch.stored {
+            Some(stored) => println!(
+                "{}  {}  {}  stored in {} out {} net {} ({} transfers), computed in {} out {} net {} ({} transfers)",
+                chain,
+                mismatch.address,
+                symbol,
+                stored.inflow,
+                stored.outflow,
+                stored.net_flow,
+                stored.transfer_count,
+                computed.inflow,
+                computed.outflow,
+                computed.net_flow,
+                computed.transfer_count
+            ),
+            None => println!(
+                "{}  {}  {}  no stored net flow, computed in {} out {} net {} ({} transfers)",
+                chain, mismatch.address, symbol, computed.inflow, computed.outflow, computed.net_flow, computed.transfer_count
+            ),
+        }
+    }
+
+    let mut conn = pool.get()?;
+    let mut gaps = Vec::new();
+    for chain in chains {
+        let ranges = coverage::ranges(&mut conn, chain.chain_id as i64)?;
+        match (ranges.first(), ranges.last()) {
+            (Some(first), Some(last)) => info!(
+                "🧱 {} blocks {} to {} indexed in {} ranges",
+                chain.name,
+                first.start_block,
+                last.end_block,
+                ranges.len()
+            ),
+            _ => info!("🧱 No {} blocks recorded as indexed yet", chain.name),
+        }
+        for gap in coverage::gaps(&mut conn, chain.chain_id as i64)? {
+            println!(
+                "{}  blocks {} to {} not indexed ({} blocks); backfill --chain {} --from {} --to {}",
+                chain.name,
+                gap.from_block,
+                gap.to_block,
+                gap.blocks(),
+                chain.name,
+                gap.from_block,
+                gap.to_block
+            );
+            gaps.push(gap);
+        }
+    }
+
+    let mut problems = Vec::new();
+    if report.mismatches.is_empty() {
+        info!("✅ Net flows match for all {} tracked addresses", report.addresses_checked);
+    } else if repair {
+        let repaired = repair_net_flows(&pool, &chain_ids, &report.mismatches)?;
+        info!("🔧 Repaired {} of {} mismatched net flows", repaired, report.mismatches.len());
+    } else {
+        problems.push(format!(
+            "{} of {} tracked addresses have mismatched net flows",
+            report.mismatches.len(),
+            report.addresses_checked
+        ));
+    }
+    if gaps.is_empty() {
+        info!("✅ No gaps between indexed blocks");
+    } else {
+        let missing: i64 = gaps.iter().map(|gap| gap.blocks()).sum();
+        problems.push(format!("{} blocks in {} ranges are not indexed", missing, gaps.len()));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(IndexerError::Generic(problems.join("; ")))
+    }
+}
+
+fn rebuild(config: &Config, chains: &[ChainConfig]) -> Result<()> {
+    let pool = open_database(config)?;
+    let labels = Labels::from_config(config);
+    let watchlists = WatchSet::load(&pool)?;
+    let tokens = TokenSet::load(&pool)?;
+
+    // A running indexer checks for published labels in each block's transaction, so the
+    // blocks it stores from here on are classified like the rebuilt history
+    for chain in chains {
+        Labels::publish(&pool, chain)?;
+    }
+
+    for chain in chains {
+        info!("🔁 Rebuilding {} net flows and snapshots from the transfer log", chain.name);
+        let report = rebuild_chain(&pool, chain, &labels, &watchlists, &tokens)?;
+        info!(
+            "✅ Rebuilt {}: {} transfers reclassified, {} net flows changed for {} tracked addresses, {} dropped, {} snapshots retaken",
+            chain.name,
+            report.transfers_reclassified,
+            report.net_flows_changed,
+            report.addresses_tracked,
+            report.net_flows_dropped,
+            report.snapshots_retaken
+        );
+    }
+    Ok(())
+}
+
+/// Connects to a chain's node, checking it serves that chain
+async fn connect_balances(chain: &ChainConfig) -> Result<RpcBalances> {
+    let source = RpcBalances::connect(&chain.ws_url).await?;
+    let node_chain_id = source.chain_id().await?;
+    if node_chain_id != chain.chain_id {
+        return Err(IndexerError::Config(format!(
+            "The {} node reports chain id {}, expected {}",
+            chain.name, node_chain_id, chain.chain_id
+        )));
+    }
+    Ok(source)
+}
+
+/// Tokens the chain's indexer has recorded, with their decimals
+fn chain_tokens(pool: &DbPool, chain: &ChainConfig) -> Result<Vec<crate::models::Token>> {
+    let tokens = TokenSet::load(pool)?.all(Some(chain.chain_id as i64));
+    if tokens.is_empty() {
+        return Err(IndexerError::BadRequest(format!(
+            "No tokens recorded for {}; run the indexer for it first",
+            chain.name
+        )));
+    }
+    Ok(tokens)
+}
+
+/// Labelled addresses on the chain and watched addresses
+fn tracked_addresses(pool: &DbPool, chain: &ChainConfig) -> Result<Vec<String>> {
+    let mut addresses: Vec<String> = chain.labels.keys().cloned().chain(WatchSet::load(pool)?.addresses()).collect();
+    addresses.sort();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+async fn seed(config: &Config, chain: ChainConfig, block: Option<u64>, addresses: Vec<String>) -> Result<()> {
+    let pool = open_database(config)?;
+    let tokens = chain_tokens(&pool, &chain)?;
+    let source = connect_balances(&chain).await?;
+    let block = match block.or(indexed_block(&pool, chain.chain_id as i64)?) {
+        Some(block) => block,
+        None => source.head().await?.saturating_sub(chain.confirmations),
+    };
+    let holders = if addresses.is_empty() {
+        tracked_addresses(&pool, &chain)?
+    } else {
+        addresses.iter().map(|address| address.to_lowercase()).collect()
+    };
+
+    let seeded = seed_balances(&source, &pool, &tokens, &holders, block).await?;
+    info!("✅ Seeded {} balances on {} at block {}", seeded, chain.name, block);
+    Ok(())
+}
+
+async fn verify_chain_balances(config: &Config, chain: ChainConfig, addresses: Vec<String>, all: bool) -> Result<()> {
+    let pool = open_database(config)?;
+    let tokens = chain_tokens(&pool, &chain)?;
+    let block = indexed_block(&pool, chain.chain_id as i64)?.ok_or_else(|| {
+        IndexerError::BadRequest(format!("The {} indexer hasn't run yet; nothing to verify", chain.name))
+    })?;
+    let source = connect_balances(&chain).await?;
+
+    let holders = if all {
+        None
+    } else if addresses.is_empty() {
+        let mut holders = tracked_addresses(&pool, &chain)?;
+        holders.extend(seeded_addresses(&pool, chain.chain_id as i64)?);
+        holders.sort();
+        holders.dedup();
+        Some(holders)
+    } else {
+        Some(addresses.iter().map(|address| address.to_lowercase()).collect())
+    };
+
+    let report = verify_balances(&source, &pool, &tokens, holders.as_deref(), block).await?;
+    let symbols = TokenSet::load(&pool)?;
+    for mismatch in &report.mismatches {
+        println!(
+            "{}  {}  {}  derived {}, balanceOf {} (diff {}){}",
+            chain.name,
+            mismatch.address,
+            symbols.symbol_of(mismatch.chain_id, &mismatch.token),
+            mismatch.derived,
+            mismatch.on_chain,
+            &mismatch.derived - &mismatch.on_chain,
+            if mismatch.seeded { "" } else { "; not seeded, so only indexed history is counted" }
+        );
+    }
+    if report.skipped > 0 {
+        warn!("⚠️ {} balances changed after block {} while verifying and were skipped", report.skipped, block);
+    }
+
+    if report.mismatches.is_empty() {
+        info!("✅ {} balances on {} match balanceOf at block {}", report.checked, chain.name, block);
+        Ok(())
+    } else {
+        Err(IndexerError::Generic(format!(
+            "{} of {} balances on {} differ from balanceOf at block {}",
+            report.mismatches.len(),
+            report.checked,
+            chain.name,
+            block
+        )))
+    }
+}
+
+fn export(
+    config: &Config,
+    dataset: Dataset,
+    args: ExportArgs,
+    format: Option<ExportFormat>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let pool = open_database(config)?;
+    let token = TokenSet::load(&pool)?.resolve_opt(args.token.as_deref(), args.chain_id)?;
+    let at = AsOf::from_params(args.at_block, args.at_time)?;
+    let filter = ExportFilter { token, at, ..args.into() };
+    let format = format.unwrap_or_else(|| ExportFormat::for_path(output));
+
+    let labels = Labels::load(config, &pool)?;
+    let watchlists = WatchSet::load(&pool)?;
+    let source = ExportSource {
+        pool: &pool,
+        labels: &labels,
+        watchlists: &watchlists,
+        chains: &config.chains,
+    };
+    let written = match output {
+        Some(path) => source.write(dataset, format, &filter, BufWriter::new(File::create(path)?))?,
+        None => source.write(dataset, format, &filter, BufWriter::new(io::stdout()))?,
+    };
+
+    info!("📤 Exported {} {} rows", written, dataset.name());
+    Ok(())
+}
+
+fn labels(config: &Config, command: LabelsCommand) -> Result<()> {
+    let pool = open_database(config)?;
+    let watchlists = WatchSet::load(&pool)?;
+    // The published labels, which the indexer applies, where there are any
+    let known = Labels::load(config, &pool)?;
+
+    match command {
+        LabelsCommand::List => {
+            for chain in &config.chains {
+                for address in known.addresses(Some(chain.chain_id as i64)) {
+                    let entity = known.entity_of(chain.chain_id as i64, &address).unwrap_or_default();
+                    println!("{}  {}  {}", address, entity, chain.name);
+                }
+            }
+            let mut watched = watchlists.addresses();
+            watched.sort();
+            for address in watched {
+                println!("{}  watchlist: {}", address, watchlists.lists_for(&[&address]).join(", "));
+            }
+        }
+        LabelsCommand::Lookup { address } => {
+            let address = address.to_lowercase();
+            let mut labels: Vec<String> = config
+                .chains
+                .iter()
+                .filter_map(|chain| {
+                    let entity = known.entity_of(chain.chain_id as i64, &address)?;
+                    Some(format!("{} on {}", entity, chain.name))
+                })
+                .collect();
+            labels.extend(watchlists.lists_for(&[&address]).into_iter().map(|name| format!("watchlist: {}", name)));
+
+            if labels.is_empty() {
+                println!("{}  unlabeled", address);
+            } else {
+                println!("{}  {}", address, labels.join("; "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn api_key(config: &Config, command: ApiKeyCommand) -> Result<()> {
+    let pool = open_database(config)?;
+
+    match command {
+        ApiKeyCommand::Create { name, rate_limit, max_connections } => {
+            let key = create_key(&pool, &name, rate_limit, max_connections)?;
+            info!("🔑 Created API key '{}' ({} req/min, {} connections)", name, rate_limit, max_connections);
+            println!("{}", key);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_the_environment_which_overrides_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("config.toml");
+        std::fs::write(
+            &file,
+            r#"
+[database]
+url = "file.db"
+
+[server]
+host = "10.0.0.1"
+port = 1000
+
+[[chains]]
+name = "polygon"
+ws_url = "wss://file.example/polygon"
+confirmations = 5
+
+[[chains]]
+name = "ethereum"
+ws_url = "wss://file.example/ethereum"
+confirmations = 12
+tokens = [{ address = "0xdac17f958d2ee523a2206206994597c13d831ec7" }]
+"#,
+        )
+        .unwrap();
+
+        // No other test reads these variables
+        let vars = [
+            ("DATABASE_URL", "env.db"),
+            ("PORT", "2000"),
+            ("POLYGON_WS_URL", "wss://env.example/polygon"),
+            ("BLOCK_CONFIRMATIONS", "3"),
+            ("ETHEREUM_CONFIRMATIONS", "20"),
+        ];
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+        let loaded = Config::load(Some(&file));
+        for (name, _) in vars {
+            std::env::remove_var(name);
+        }
+        let mut config = loaded.unwrap();
+
+        let cli = Cli::try_parse_from([
+            "polygon-indexer",
+            "run",
+            "--database-url",
+            "flag.db",
+            "--chain",
+            "polygon",
+            "--ws-url",
+            "wss://flag.example/polygon",
+        ])
+        .unwrap();
+        cli.command.unwrap().apply_overrides(&mut config).unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.database_url, "flag.db");
+        assert_eq!((config.host.as_str(), config.port), ("10.0.0.1", 2000));
+        let chains: Vec<_> = config
+            .chains
+            .iter()
+            .map(|chain| (chain.name.as_str(), chain.ws_url.as_str(), chain.confirmations))
+            .collect();
+        assert_eq!(
+            chains,
+            [("polygon", "wss://flag.example/polygon", 3), ("ethereum", "wss://file.example/ethereum", 20)]
+        );
+    }
+
+    fn parse(args: &[&str]) -> std::result::Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("polygon-indexer").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn subcommands_parse_their_own_flags() {
+        assert!(parse(&[]).unwrap().command.is_none());
+
+        let cli = parse(&["--config", "prod.toml", "serve", "--port", "8080"]).unwrap();
+        assert_eq!(cli.config.as_deref(), Some(Path::new("prod.toml")));
+        assert!(matches!(cli.command, Some(Command::Serve { http: HttpArgs { port: Some(8080), .. }, .. })));
+
+        let cli = parse(&["backfill", "--chain", "ethereum", "--from", "100", "--from-archive"]).unwrap();
+        let Some(Command::Backfill { node, from: 100, to: None, from_archive: true, .. }) = cli.command else {
+            panic!("{:?}", cli.command);
+        };
+        assert_eq!(node.chains, ["ethereum"]);
+
+        let cli = parse(&["verify", "--balances", "--address", "0xa", "--address", "0xb"]).unwrap();
+        let Some(Command::Verify { balances: true, repair: false, addresses, .. }) = cli.command else {
+            panic!("{:?}", cli.command);
+        };
+        assert_eq!(addresses, ["0xa", "0xb"]);
+
+        let cli = parse(&["export", "net-flows", "--at-block", "500", "-o", "flows.parquet"]).unwrap();
+        let Some(Command::Export { dataset: Dataset::NetFlows, filter, format: None, output, .. }) = cli.command else {
+            panic!("{:?}", cli.command);
+        };
+        assert_eq!((filter.at_block, output), (Some(500), Some(PathBuf::from("flows.parquet"))));
+
+        let cli = parse(&["labels", "lookup", "0xa"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Labels { command: LabelsCommand::Lookup { .. }, .. })));
+        let cli = parse(&["config", "check", "--database-url", "other.db"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Config { command: ConfigCommand::Check { .. } })));
+    }
+
+    #[test]
+    fn flags_outside_their_subcommand_or_in_conflict_are_rejected() {
+        for args in [
+            &["serve", "--ws-url", "wss://node.example"][..],
+            &["migrate", "--chain", "polygon"],
+            &["backfill", "--to", "200"],
+            &["verify", "--repair", "--balances"],
+            &["verify", "--address", "0xa"],
+            &["verify", "--balances", "--all", "--address", "0xa"],
+            &["export", "--at-block", "1", "--at-time", "2024-01-01T00:00:00Z"],
+            &["export", "--from-time", "yesterday"],
+            &["labels"],
+        ] {
+            assert!(parse(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn node_flags_apply_to_exactly_one_selected_chain() {
+        let mut config = Config::default();
+        config.chains.push(ChainConfig::new("ethereum", 1));
+        let token = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+
+        let command = parse(&["index", "--ws-url", "wss://node.example"]).unwrap().command.unwrap();
+        let error = command.apply_overrides(&mut config).unwrap_err().to_string();
+        assert!(error.contains("--chain"), "{}", error);
+        let command = parse(&["index", "--chain", "nowhere", "--token", token]).unwrap().command.unwrap();
+        let error = command.apply_overrides(&mut config).unwrap_err().to_string();
+        assert!(error.contains("Unknown chain 'nowhere'"), "{}", error);
+
+        let command = parse(&["index", "--chain", "Ethereum", "--ws-url", "wss://eth.example", "--token", token])
+            .unwrap()
+            .command
+            .unwrap();
+        command.apply_overrides(&mut config).unwrap();
+        assert!(config.chains[0].ws_url.is_empty());
+        assert_eq!(config.chains[1].ws_url, "wss://eth.example");
+        let tokens: Vec<_> = config.chains[1].tokens.iter().map(|token| token.address.as_str()).collect();
+        assert_eq!(tokens, [token]);
+        assert_eq!(command.chain_names(), ["Ethereum"]);
+        assert!(command.connects_to_node());
+
+        let command = parse(&["verify", "--repair"]).unwrap().command.unwrap();
+        assert!(!command.connects_to_node());
+        assert!(parse(&["verify", "--balances"]).unwrap().command.unwrap().connects_to_node());
+    }
+}
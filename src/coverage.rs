@@ -0,0 +1,147 @@
+use diesel::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    database::DbConnection,
+    models::IndexedRange,
+    schema::indexed_ranges::dsl::*,
+    Result,
+};
+
+/// Blocks between two indexed ranges of a chain that were never stored
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockGap {
+    pub chain_id: i64,
+    pub from_block: i64,
+    pub to_block: i64,
+}
+
+impl BlockGap {
+    pub fn blocks(&self) -> i64 {
+        self.to_block - self.from_block + 1
+    }
+}
+
+/// Marks a block as stored, merging it into the ranges next to it; runs inside the block's transaction
+pub fn record_block(conn: &mut SqliteConnection, chain: i64, block_number: i64) -> Result<()> {
+    let covered: i64 = indexed_ranges
+        .filter(chain_id.eq(chain))
+        .filter(start_block.le(block_number))
+        .filter(end_block.ge(block_number))
+        .count()
+        .get_result(conn)?;
+    if covered > 0 {
+        return Ok(());
+    }
+
+    let before: Option<IndexedRange> = indexed_ranges
+        .filter(chain_id.eq(chain))
+        .filter(end_block.eq(block_number - 1))
+        .first(conn)
+        .optional()?;
+    let after: Option<IndexedRange> = indexed_ranges
+        .find((chain, block_number + 1))
+        .first(conn)
+        .optional()?;
+
+    match (before, after) {
+        (Some(before), Some(after)) => {
+            diesel::delete(indexed_ranges.find((chain, after.start_block))).execute(conn)?;
+            diesel::update(indexed_ranges.find((chain, before.start_block)))
+                .set(end_block.eq(after.end_block))
+                .execute(conn)?;
+        }
+        (Some(before), None) => {
+            diesel::update(indexed_ranges.find((chain, before.start_block)))
+                .set(end_block.eq(block_number))
+                .execute(conn)?;
+        }
+        (None, Some(after)) => {
+            diesel::update(indexed_ranges.find((chain, after.start_block)))
+                .set(start_block.eq(block_number))
+                .execute(conn)?;
+        }
+        (None, None) => {
+            diesel::insert_into(indexed_ranges)
+                .values(IndexedRange {
+                    chain_id: chain,
+                    start_block: block_number,
+                    end_block: block_number,
+                })
+                .execute(conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// A chain's indexed ranges, lowest first
+pub fn ranges(conn: &mut DbConnection, chain: i64) -> Result<Vec<IndexedRange>> {
+    Ok(indexed_ranges
+        .filter(chain_id.eq(chain))
+        .order(start_block.asc())
+        .load(conn)?)
+}
+
+/// Block ranges between a chain's first and last stored block that no indexed range covers
+pub fn gaps(conn: &mut DbConnection, chain: i64) -> Result<Vec<BlockGap>> {
+    Ok(ranges(conn, chain)?
+        .windows(2)
+        .filter(|pair| pair[1].start_block > pair[0].end_block + 1)
+        .map(|pair| BlockGap {
+            chain_id: chain,
+            from_block: pair[0].end_block + 1,
+            to_block: pair[1].start_block - 1,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_pool;
+
+    fn spans(conn: &mut DbConnection, chain: i64) -> Vec<(i64, i64)> {
+        ranges(conn, chain).unwrap().into_iter().map(|range| (range.start_block, range.end_block)).collect()
+    }
+
+    #[test]
+    fn blocks_merge_into_neighbouring_ranges() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        for block in [5, 7, 9] {
+            record_block(&mut conn, 137, block).unwrap();
+        }
+        assert_eq!(spans(&mut conn, 137), [(5, 5), (7, 7), (9, 9)]);
+
+        // Joins both sides, then extends either end
+        record_block(&mut conn, 137, 6).unwrap();
+        assert_eq!(spans(&mut conn, 137), [(5, 7), (9, 9)]);
+        record_block(&mut conn, 137, 4).unwrap();
+        record_block(&mut conn, 137, 10).unwrap();
+        assert_eq!(spans(&mut conn, 137), [(4, 7), (9, 10)]);
+
+        // Replays are no-ops, and chains don't merge with each other
+        record_block(&mut conn, 137, 6).unwrap();
+        record_block(&mut conn, 1, 8).unwrap();
+        assert_eq!(spans(&mut conn, 137), [(4, 7), (9, 10)]);
+        assert_eq!(spans(&mut conn, 1), [(8, 8)]);
+    }
+
+    #[test]
+    fn gaps_lie_between_ranges() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        for block in [4, 5, 6, 7, 8, 10, 15, 16] {
+            record_block(&mut conn, 137, block).unwrap();
+        }
+        record_block(&mut conn, 1, 100).unwrap();
+
+        let found: Vec<_> = gaps(&mut conn, 137)
+            .unwrap()
+            .into_iter()
+            .map(|gap| (gap.chain_id, gap.from_block, gap.to_block, gap.blocks()))
+            .collect();
+        assert_eq!(found, [(137, 9, 9, 1), (137, 11, 14, 4)]);
+        assert!(gaps(&mut conn, 1).unwrap().is_empty());
+    }
+}
@@ -1,7 +1,10 @@
-﻿use diesel::r2d2::{ConnectionManager, Pool};
+﻿use diesel::connection::SimpleConnection;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
 use diesel::sqlite::SqliteConnection;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-use crate::Result;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use crate::{tokens::POL_ADDRESS, Result};
 use std::path::Path;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
@@ -9,6 +12,18 @@ pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
 pub type DbConnection = diesel::r2d2::PooledConnection<ConnectionManager<SqliteConnection>>;
 
+/// Lets the indexer and API processes share one database file: WAL keeps readers
+/// from blocking the writer, and writers wait for each other instead of failing
+#[derive(Debug)]
+struct ConnectionOptions;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> std::result::Result<(), diesel::r2d2::Error> {
+        conn.batch_execute("PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000;")
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
 pub fn create_pool(database_url: &str) -> Result<DbPool> {
     // Ensure the directory exists
     if let Some(parent) = Path::new(database_url).parent() {
@@ -18,16 +33,142 @@ pub fn create_pool(database_url: &str) -> Result<DbPool> {
     let manager = ConnectionManager::<SqliteConnection>::new(database_url);
     let pool = Pool::builder()
         .max_size(10)
+        .connection_customizer(Box::new(ConnectionOptions))
         .build(manager)?;
     
     Ok(pool)
 }
 
-pub fn run_migrations(conn: &mut DbConnection) -> Result<()> {
-    conn.run_pending_migrations(MIGRATIONS)
-        .map_err(|e| crate::IndexerError::Database(diesel::result::Error::DatabaseError(
-            diesel::result::DatabaseErrorKind::Unknown,
-            Box::new(e.to_string())
-        )))?;
+/// Migration that introduced the `token` column, tagging every existing row as POL
+const ADD_TOKENS_VERSION: &str = "2026101806";
+
+/// Runs pending migrations one at a time.
+///
+/// When the tokens migration runs, the rows it tags with the POL address are
+/// re-tagged with `legacy_pol_contract` (the old `POL_CONTRACT`) in the same
+/// transaction, for databases that were indexed with a different contract.
+pub fn run_migrations(conn: &mut DbConnection, legacy_pol_contract: Option<&str>) -> Result<()> {
+    let pending = conn.pending_migrations(MIGRATIONS).map_err(migration_error)?;
+    for migration in pending {
+        conn.transaction(|conn| {
+            conn.run_migration(&migration)?;
+            if migration.name().version().to_string() == ADD_TOKENS_VERSION {
+                if let Some(contract) = legacy_pol_contract.filter(|contract| *contract != POL_ADDRESS) {
+                    retag_legacy_token(conn, contract)?;
+                }
+            }
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        })
+        .map_err(migration_error)?;
+    }
     Ok(())
 }
+
+fn retag_legacy_token(conn: &mut DbConnection, contract: &str) -> QueryResult<()> {
+    tracing::info!("🪙 Tagging transfers indexed before token support with {}", contract);
+    for table in ["transfers", "net_flows"] {
+        diesel::sql_query(format!("UPDATE {} SET token = ? WHERE token = ?", table))
+            .bind::<Text, _>(contract)
+            .bind::<Text, _>(POL_ADDRESS)
+            .execute(conn)?;
+    }
+    // Symbol and decimals are rewritten from the configured tokens when the indexer starts
+    diesel::sql_query("UPDATE tokens SET address = ? WHERE address = ?")
+        .bind::<Text, _>(contract)
+        .bind::<Text, _>(POL_ADDRESS)
+        .execute(conn)?;
+    Ok(())
+}
+
+fn migration_error(e: impl std::fmt::Display) -> crate::IndexerError {
+    crate::IndexerError::Database(diesel::result::Error::DatabaseError(
+        diesel::result::DatabaseErrorKind::Unknown,
+        Box::new(e.to_string())
+    ))
+}
+
+/// A migrated database in a temporary directory, removed when the directory is dropped
+#[cfg(test)]
+pub(crate) fn test_pool() -> (tempfile::TempDir, DbPool) {
+    let dir = tempfile::tempdir().expect("temporary directory");
+    let path = dir.path().join("indexer.db");
+    let pool = create_pool(path.to_str().expect("UTF-8 path")).expect("pool");
+    run_migrations(&mut pool.get().expect("connection"), None).expect("migrations");
+    (dir, pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{indexer_state, net_flows, tokens, transfers};
+
+    const LEGACY: &str = "0x00000000000000000000000000000000000000aa";
+
+    /// A database at the schema before tokens, holding one transfer and its net flows
+    fn legacy_database() -> (tempfile::TempDir, DbPool) {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = create_pool(dir.path().join("indexer.db").to_str().unwrap()).unwrap();
+        let mut conn = pool.get().unwrap();
+        while conn.pending_migrations(MIGRATIONS).unwrap()[0].name().version().to_string() != ADD_TOKENS_VERSION {
+            conn.run_next_migration(MIGRATIONS).unwrap();
+        }
+        conn.batch_execute(
+            "INSERT INTO transfers (id, block_number, transaction_hash, from_address, to_address, value, timestamp)
+             VALUES ('0x01_0', 1, '0x01', '0xa', '0xb', '5', '2024-01-01 00:00:00');
+             INSERT INTO net_flows (address, net_flow, inflow, outflow, transfer_count, last_updated)
+             VALUES ('0xa', '-5', '0', '5', 1, '2024-01-01 00:00:00'), ('0xb', '5', '5', '0', 1, '2024-01-01 00:00:00');",
+        )
+        .unwrap();
+        drop(conn);
+        (dir, pool)
+    }
+
+    #[test]
+    fn tokens_migration_tags_old_rows_with_the_legacy_contract() {
+        let (_dir, pool) = legacy_database();
+        let mut conn = pool.get().unwrap();
+        run_migrations(&mut conn, Some(LEGACY)).unwrap();
+
+        let transfer_tokens: Vec<String> = transfers::table.select(transfers::token).load(&mut conn).unwrap();
+        assert_eq!(transfer_tokens, vec![LEGACY]);
+        let flow_tokens: Vec<String> = net_flows::table.select(net_flows::token).load(&mut conn).unwrap();
+        assert_eq!(flow_tokens, vec![LEGACY, LEGACY]);
+        let known: Vec<String> = tokens::table.select(tokens::address).load(&mut conn).unwrap();
+        assert_eq!(known, vec![LEGACY]);
+    }
+
+    #[test]
+    fn tokens_migration_defaults_to_pol() {
+        let (_dir, pool) = legacy_database();
+        let mut conn = pool.get().unwrap();
+        run_migrations(&mut conn, None).unwrap();
+
+        let transfer_tokens: Vec<String> = transfers::table.select(transfers::token).load(&mut conn).unwrap();
+        assert_eq!(transfer_tokens, vec![POL_ADDRESS]);
+
+        // Already migrated databases are left alone
+        run_migrations(&mut conn, Some(LEGACY)).unwrap();
+        let known: Vec<String> = tokens::table.select(tokens::address).load(&mut conn).unwrap();
+        assert_eq!(known, vec![POL_ADDRESS]);
+    }
+
+    #[test]
+    fn indexer_state_is_kept_per_chain_through_reverts() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        conn.batch_execute(
+            "INSERT INTO indexer_state (chain_id, current_block, chain_head, is_running, last_block_at, updated_at)
+             VALUES (137, 10, 12, 0, '2026-10-01 00:00:00', '2026-10-01 00:00:00'),
+                    (1, 20, 22, 0, '2026-10-01 00:00:00', '2026-10-01 00:00:00');",
+        )
+        .unwrap();
+
+        // Before chain ids, only Polygon PoS progress is kept, in the same table
+        while conn.revert_last_migration(MIGRATIONS).unwrap().to_string() != "2026101807" {}
+        let chains: Vec<i64> = indexer_state::table.select(indexer_state::chain_id).load(&mut conn).unwrap();
+        assert_eq!(chains, vec![137]);
+
+        while conn.revert_last_migration(MIGRATIONS).unwrap().to_string() != "2026101805" {}
+        run_migrations(&mut conn, None).unwrap();
+    }
+}
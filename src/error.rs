@@ -1,4 +1,11 @@
-﻿use thiserror::Error;
+﻿use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use thiserror::Error;
+use utoipa::ToSchema;
 
 #[derive(Error, Debug)]
 pub enum IndexerError {
@@ -15,7 +22,7 @@ pub enum IndexerError {
     Web3(String),
     
     #[error("WebSocket error: {0}")]
-    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+    WebSocket(#[from] Box<tokio_tungstenite::tungstenite::Error>),
     
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
@@ -26,14 +33,135 @@ pub enum IndexerError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    
+    #[error("Not found: {0}")]
+    NotFound(String),
+    
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    
     #[error("Generic error: {0}")]
     Generic(String),
 }
 
 pub type Result<T> = std::result::Result<T, IndexerError>;
 
+impl From<tokio_tungstenite::tungstenite::Error> for IndexerError {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        IndexerError::WebSocket(Box::new(err))
+    }
+}
+
 impl From<anyhow::Error> for IndexerError {
     fn from(err: anyhow::Error) -> Self {
         IndexerError::Generic(err.to_string())
     }
 }
+
+/// JSON body returned by every failing API request
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Stable machine-readable error kind, e.g. `bad_request`
+    pub code: &'static str,
+    /// Human-readable description
+    pub error: String,
+}
+
+impl IndexerError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            IndexerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            IndexerError::Conflict(_) => StatusCode::CONFLICT,
+            IndexerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            IndexerError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            IndexerError::NotFound(_) | IndexerError::Database(diesel::result::Error::NotFound) => {
+                StatusCode::NOT_FOUND
+            }
+            IndexerError::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
+            IndexerError::Ethereum(_) | IndexerError::Web3(_) => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            IndexerError::Database(diesel::result::Error::NotFound) | IndexerError::NotFound(_) => "not_found",
+            IndexerError::Database(_) => "database_error",
+            IndexerError::Pool(_) => "database_unavailable",
+            IndexerError::Ethereum(_) | IndexerError::Web3(_) => "upstream_error",
+            IndexerError::WebSocket(_) => "websocket_error",
+            IndexerError::Serialization(_) => "serialization_error",
+            IndexerError::Config(_) => "config_error",
+            IndexerError::Io(_) => "io_error",
+            IndexerError::BadRequest(_) => "bad_request",
+            IndexerError::Conflict(_) => "conflict",
+            IndexerError::Unauthorized(_) => "unauthorized",
+            IndexerError::RateLimited(_) => "rate_limited",
+            IndexerError::Generic(_) => "internal_error",
+        }
+    }
+}
+
+impl IntoResponse for IndexerError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        // Database, pool and node errors stay in the log; clients only learn what kind of failure it was
+        let error = if status.is_server_error() {
+            tracing::error!("❌ API error: {}", self);
+            status.canonical_reason().unwrap_or("Internal Server Error").to_string()
+        } else {
+            self.to_string()
+        };
+
+        let body = ErrorResponse {
+            code: self.code(),
+            error,
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(error: IndexerError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn server_errors_hide_their_cause() {
+        let database = IndexerError::Database(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::Unknown,
+            Box::new("no such table: transfers".to_string()),
+        ));
+        let (status, json) = body(database).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(json["code"], "database_error");
+        assert_eq!(json["error"], "Internal Server Error");
+
+        let (status, json) = body(IndexerError::Web3("node at ws://10.0.0.5:8546 refused".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(json["code"], "upstream_error");
+        assert_eq!(json["error"], "Bad Gateway");
+    }
+
+    #[tokio::test]
+    async fn client_errors_keep_their_message() {
+        let (status, json) = body(IndexerError::BadRequest("limit must be at most 1000".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json["code"], "bad_request");
+        assert!(json["error"].as_str().unwrap().contains("limit must be at most 1000"));
+    }
+}
@@ -0,0 +1,352 @@
+use diesel::SqliteConnection;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::{
+    models::Transfer,
+    outbox::Outbox,
+    subscriptions::Ack,
+    types::{BlockSummary, NetFlowData, SystemStats},
+    watchlists::WatchedTransfer,
+    Result,
+};
+
+/// Capacity of the in-process event channel shared by the indexer and server
+pub const EVENT_CHANNEL_CAPACITY: usize = 1000;
+
+/// Number of recent events kept for clients resuming after a disconnect
+pub const REPLAY_BUFFER_SIZE: usize = 1000;
+
+/// Envelope for everything pushed to live clients.
+///
+/// Serializes as `{"type": "...", "data": ...}` so the UI can switch on `type`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    Hello(String),
+    Transfer(Transfer),
+    WatchedTransfer(WatchedTransfer),
+    NetFlowUpdate(NetFlowData),
+    NewBlock(BlockSummary),
+    StatsUpdate(SystemStats),
+    // Connection-level replies to client messages; never broadcast
+    Ack(Ack),
+    Error(String),
+    Pong,
+    Gap(Gap),
+    Reset(Reset),
+}
+
+impl Event {
+    /// The serialized `type` tag
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Hello(_) => "hello",
+            Event::Transfer(_) => "transfer",
+            Event::WatchedTransfer(_) => "watched_transfer",
+            Event::NetFlowUpdate(_) => "net_flow_update",
+            Event::NewBlock(_) => "new_block",
+            Event::StatsUpdate(_) => "stats_update",
+            Event::Ack(_) => "ack",
+            Event::Error(_) => "error",
+            Event::Pong => "pong",
+            Event::Gap(_) => "gap",
+            Event::Reset(_) => "reset",
+        }
+    }
+}
+
+/// A published event tagged with its position in the stream.
+///
+/// Serializes as the event envelope plus a `seq` field.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sequenced {
+    #[serde(rename = "seq")]
+    pub id: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Inclusive range of event ids that fell out of the replay buffer before a client could see them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Gap {
+    pub from: u64,
+    pub to: u64,
+}
+
+/// Sent instead of a backlog when a client resumes from an id this stream never reached,
+/// e.g. one from before the database was reset: its position is unknown, so it should
+/// reload what it shows and continue from `latest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reset {
+    pub requested: u64,
+    pub latest: u64,
+}
+
+/// Everything a client needs to continue the stream after a given id
+pub struct Resume {
+    pub gap: Option<Gap>,
+    pub reset: Option<Reset>,
+    pub backlog: Vec<Sequenced>,
+    pub receiver: broadcast::Receiver<Sequenced>,
+}
+
+/// Fan-out of indexer events to API clients, with a bounded replay buffer.
+///
+/// Indexer events are numbered by their `event_outbox` row, so every API node gives an
+/// event the same id and a client that remembers the last id it saw can ask any of them
+/// for everything after it. Without an outbox they are numbered from 1 in memory.
+/// Events a node makes itself, like stats updates, carry the latest id and aren't replayed.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Sequenced>,
+    history: Arc<Mutex<History>>,
+    /// Whether indexer events are recorded for API nodes in other processes
+    outbox: bool,
+    /// Held from a block's transaction until its events are published, so events
+    /// of blocks committed one after another are published in id order
+    commit_order: Arc<Mutex<()>>,
+}
+
+struct History {
+    last_id: u64,
+    events: VecDeque<Sequenced>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            sender,
+            history: Arc::new(Mutex::new(History {
+                last_id: 0,
+                events: VecDeque::with_capacity(REPLAY_BUFFER_SIZE),
+            })),
+            outbox: false,
+            commit_order: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Records indexer events in `event_outbox` as part of the transaction that produced them
+    pub fn with_outbox(mut self) -> Self {
+        self.outbox = true;
+        self
+    }
+
+    /// Runs `write` in a transaction, records the events it returns in the outbox in the
+    /// same transaction, then publishes them once it has committed.
+    ///
+    /// A block's events are thus in the outbox exactly when the block is stored. The
+    /// transaction takes the write lock up front, so nothing `write` reads changes before it commits.
+    /// It blocks on the database, so async callers run it in a blocking task.
+    pub fn commit<T>(
+        &self,
+        conn: &mut SqliteConnection,
+        write: impl FnOnce(&mut SqliteConnection) -> Result<(T, Vec<Event>)>,
+    ) -> Result<T> {
+        let _order = self.commit_order.lock().unwrap();
+        let (value, events) = conn.immediate_transaction(|conn| -> Result<_> {
+            let (value, events) = write(conn)?;
+            let mut numbered = Vec::with_capacity(events.len());
+            for event in events {
+                let id = if self.outbox { Some(Outbox::append(conn, &event)?) } else { None };
+                numbered.push((id, event));
+            }
+            Ok((value, numbered))
+        })?;
+
+        for (id, event) in events {
+            self.record(id, event);
+        }
+        Ok(value)
+    }
+
+    /// Publishes an event another process recorded in the outbox, under its outbox id
+    pub fn relay(&self, id: u64, event: Event) {
+        self.record(Some(id), event);
+    }
+
+    /// Sends an event this node made itself to live subscribers, without keeping it for replay.
+    /// It carries the id of the latest event, so resuming from it misses nothing.
+    pub fn publish(&self, event: Event) -> u64 {
+        let history = self.history.lock().unwrap();
+        // Ignore send errors (no active subscribers)
+        let _ = self.sender.send(Sequenced { id: history.last_id, event });
+        history.last_id
+    }
+
+    /// Keeps the event for replay and sends it to live subscribers, under `id` or the next one
+    fn record(&self, id: Option<u64>, event: Event) -> u64 {
+        let mut history = self.history.lock().unwrap();
+        let id = id.unwrap_or(history.last_id + 1);
+        history.last_id = id;
+        let sequenced = Sequenced { id, event };
+
+        if history.events.len() == REPLAY_BUFFER_SIZE {
+            history.events.pop_front();
+        }
+        history.events.push_back(sequenced.clone());
+
+        // Ignore send errors (no active subscribers)
+        let _ = self.sender.send(sequenced);
+        id
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Sequenced> {
+        self.sender.subscribe()
+    }
+
+    /// Id of the most recently published event, 0 before the first one
+    pub fn last_id(&self) -> u64 {
+        self.history.lock().unwrap().last_id
+    }
+
+    /// Refills the replay buffer with events recorded before this process started, oldest
+    /// first, and continues numbering after `last_id`, without sending anything live
+    pub fn restore(&self, last_id: u64, events: impl IntoIterator<Item = (u64, Event)>) {
+        let mut history = self.history.lock().unwrap();
+        for (id, event) in events {
+            if history.events.len() == REPLAY_BUFFER_SIZE {
+                history.events.pop_front();
+            }
+            history.events.push_back(Sequenced { id, event });
+        }
+        history.last_id = history.last_id.max(last_id);
+    }
+
+    /// Subscribes to live events and returns the retained events after `last_id`.
+    ///
+    /// Both are taken under the same lock, so the backlog and the live receiver
+    /// neither overlap nor leave a hole between them. Events that were already
+    /// evicted from the buffer are reported as a gap, and an id newer than any
+    /// event of this stream as a reset.
+    pub fn resume(&self, last_id: u64) -> Resume {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        if last_id > history.last_id {
+            return Resume {
+                gap: None,
+                reset: Some(Reset {
+                    requested: last_id,
+                    latest: history.last_id,
+                }),
+                backlog: Vec::new(),
+                receiver,
+            };
+        }
+
+        let oldest = history
+            .events
+            .front()
+            .map_or(history.last_id + 1, |sequenced| sequenced.id);
+        let gap = (last_id + 1 < oldest).then(|| Gap {
+            from: last_id + 1,
+            to: oldest - 1,
+        });
+
+        let backlog = history
+            .events
+            .iter()
+            .filter(|sequenced| sequenced.id > last_id)
+            .cloned()
+            .collect();
+
+        Resume {
+            gap,
+            reset: None,
+            backlog,
+            receiver,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::test_pool, models::OutboxEvent, schema::event_outbox, IndexerError};
+    use diesel::prelude::*;
+
+    fn new_block(number: i64) -> Event {
+        Event::NewBlock(BlockSummary {
+            chain_id: 137,
+            number,
+            hash: format!("{:#x}", number),
+            timestamp: chrono::DateTime::from_timestamp(1_700_000_000 + number, 0).unwrap(),
+            transfer_count: 0,
+        })
+    }
+
+    fn block_number(event: &Event) -> i64 {
+        match event {
+            Event::NewBlock(block) => block.number,
+            other => panic!("unexpected {} event", other.kind()),
+        }
+    }
+
+    #[test]
+    fn committed_events_are_numbered_by_their_outbox_row() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        let indexer = EventBus::new().with_outbox();
+        let mut live = indexer.subscribe();
+
+        indexer.commit(&mut conn, |_| Ok(((), vec![new_block(1), new_block(2)]))).unwrap();
+        indexer.commit(&mut conn, |_| Ok(((), vec![new_block(3)]))).unwrap();
+
+        let rows: Vec<OutboxEvent> = event_outbox::table.order(event_outbox::id.asc()).load(&mut conn).unwrap();
+        let row_ids: Vec<u64> = rows.iter().map(|row| row.id as u64).collect();
+        assert_eq!(row_ids.len(), 3);
+        for (row_id, number) in row_ids.iter().zip(1..) {
+            let sequenced = live.try_recv().unwrap();
+            assert_eq!(sequenced.id, *row_id);
+            assert_eq!(block_number(&sequenced.event), number);
+        }
+        assert_eq!(indexer.last_id(), row_ids[2]);
+
+        // An API node republishing the rows gives every event the indexer's id
+        let api = EventBus::new();
+        for row in &rows {
+            api.relay(row.id as u64, serde_json::from_str(&row.payload).unwrap());
+        }
+        let replayed: Vec<u64> = api.resume(0).backlog.iter().map(|sequenced| sequenced.id).collect();
+        assert_eq!(replayed, row_ids);
+    }
+
+    #[test]
+    fn failed_commit_records_and_publishes_nothing() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        let bus = EventBus::new().with_outbox();
+        let mut live = bus.subscribe();
+
+        let result: Result<()> = bus.commit(&mut conn, |_| Err(IndexerError::Config("block rejected".to_string())));
+        assert!(result.is_err());
+
+        let rows: i64 = event_outbox::table.count().get_result(&mut conn).unwrap();
+        assert_eq!(rows, 0);
+        assert!(live.try_recv().is_err());
+        assert_eq!(bus.last_id(), 0);
+    }
+
+    #[test]
+    fn node_events_carry_the_latest_id_and_are_not_replayed() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        let bus = EventBus::new();
+        bus.commit(&mut conn, |_| Ok(((), vec![new_block(1)]))).unwrap();
+
+        let mut live = bus.subscribe();
+        assert_eq!(bus.publish(Event::Pong), 1);
+        assert_eq!(live.try_recv().unwrap().id, 1);
+        assert!(bus.resume(0).backlog.iter().all(|sequenced| !matches!(sequenced.event, Event::Pong)));
+    }
+}
@@ -0,0 +1,664 @@
+use async_graphql::{
+    connection::{Connection, Edge},
+    ComplexObject, Context, EmptyMutation, InputObject, Object, Schema, SimpleObject, Subscription,
+};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use diesel::prelude::*;
+use futures_util::{stream, Stream};
+use std::collections::BTreeMap;
+
+use crate::{
+    balances::{balances_of, top_holders},
+    chains::{chain_statuses, ChainStatus},
+    config::ChainConfig,
+    database::DbPool,
+    events::{Event, EventBus},
+    history::{balances_as_of, flows_as_of_chains, AsOf},
+    labels::Labels,
+    metrics::metrics,
+    models::{Balance, NetFlow, Token, Transfer},
+    schema::{net_flows, transfers},
+    subscriptions::Filter,
+    tokens::TokenSet,
+    types::BlockSummary,
+    utils::{bigdecimal_to_string, string_to_bigdecimal},
+    Config,
+};
+
+pub type GraphqlSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// Default and maximum page size for transfer connections
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Longest hourly series an address can request (one week)
+const MAX_SERIES_HOURS: i64 = 24 * 7;
+
+/// Deepest selection accepted; `entities { addresses { transfers { edges { node { id } } } } }` is 6
+const MAX_QUERY_DEPTH: usize = 10;
+
+/// Most work one query may ask for. Every field costs 1, and a list field costs its
+/// selection once per item it can return, so nested pages multiply.
+const MAX_QUERY_COMPLEXITY: usize = 20_000;
+
+/// Items assumed for lists without a page size, such as an entity's addresses
+const UNPAGED_LIST_ITEMS: usize = 20;
+
+/// Complexity of a list of up to `first` items (the default page size when omitted)
+fn page_cost(first: Option<i64>, child_complexity: usize) -> usize {
+    (first.unwrap_or(DEFAULT_PAGE_SIZE).max(1) as usize).saturating_mul(child_complexity)
+}
+
+/// Complexity of a list without a page size
+fn list_cost(child_complexity: usize) -> usize {
+    UNPAGED_LIST_ITEMS.saturating_mul(child_complexity)
+}
+
+pub fn build_schema(config: &Config, pool: DbPool, events: EventBus, tokens: TokenSet, labels: Labels) -> GraphqlSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .data(pool)
+        .data(events)
+        .data(tokens)
+        .data(labels)
+        .data(config.chains.clone())
+        .finish()
+}
+
+#[derive(Debug, Clone, Default, InputObject)]
+pub struct TransferFilter {
+    pub chain_id: Option<i64>,
+    /// Matches either side of the transfer
+    pub address: Option<String>,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub binance_only: Option<bool>,
+    /// Only transfers touching an address on a watchlist
+    pub watched_only: Option<bool>,
+    pub min_block: Option<i64>,
+    pub max_block: Option<i64>,
+    /// Token address or symbol
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, InputObject)]
+pub struct EventFilter {
+    pub chain_id: Option<i64>,
+    pub address: Option<String>,
+    /// Decimal string, compared against the transfer value or absolute net flow
+    pub min_value: Option<String>,
+    pub entity: Option<String>,
+    /// Token address or symbol
+    pub token: Option<String>,
+}
+
+impl From<EventFilter> for Filter {
+    fn from(filter: EventFilter) -> Self {
+        Filter {
+            chain_id: filter.chain_id,
+            address: filter.address,
+            min_value: filter.min_value.as_deref().map(string_to_bigdecimal),
+            entity: filter.entity,
+            watchlist: None,
+            token: filter.token,
+            ..Default::default()
+        }
+    }
+}
+
+/// A known owner of one or more addresses, e.g. an exchange
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Entity {
+    pub name: String,
+    #[graphql(complexity = "list_cost(child_complexity)")]
+    pub addresses: Vec<AddressNode>,
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+#[graphql(complex, name = "Address")]
+pub struct AddressNode {
+    /// Chain the address was looked up on; `None` covers every chain
+    pub chain_id: Option<i64>,
+    pub address: String,
+    /// Entity the address is labelled with, on `chain_id` or on any chain
+    pub entity: Option<String>,
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct HourlyFlow {
+    pub hour: DateTime<Utc>,
+    pub inflow: String,
+    pub outflow: String,
+    pub net_flow: String,
+    pub transfer_count: i64,
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+#[graphql(complex, name = "Block")]
+pub struct BlockNode {
+    /// `None` covers the block with this number on every chain
+    pub chain_id: Option<i64>,
+    pub number: i64,
+}
+
+impl AddressNode {
+    fn new(labels: &Labels, chain_id: Option<i64>, address: &str) -> Self {
+        let address = address.to_lowercase();
+        let entity = match chain_id {
+            Some(chain_id) => labels.entity_of(chain_id, &address),
+            None => labels
+                .entities(None)
+                .into_iter()
+                .find(|(_, addresses)| addresses.iter().any(|(_, addr)| *addr == address))
+                .map(|(entity, _)| entity),
+        };
+        Self { chain_id, address, entity }
+    }
+}
+
+#[ComplexObject]
+impl AddressNode {
+    /// Cumulative flow totals per token, when the address is tracked; as of `atBlock` or `atTime`
+    /// they are rebuilt from the transfer log, tracked or not
+    #[graphql(complexity = "list_cost(child_complexity)")]
+    async fn net_flows(
+        &self,
+        ctx: &Context<'_>,
+        token: Option<String>,
+        at_block: Option<i64>,
+        at_time: Option<DateTime<Utc>>,
+    ) -> async_graphql::Result<Vec<NetFlow>> {
+        let token_addresses = resolve_token(ctx, token.as_deref(), self.chain_id)?;
+        let mut conn = ctx.data::<DbPool>()?.get()?;
+        if let Some(at) = AsOf::from_params(at_block, at_time)? {
+            let chain_ids = at.chains(ctx.data::<Vec<ChainConfig>>()?, self.chain_id)?;
+            let address = [self.address.clone()];
+            let tokens = ctx.data::<TokenSet>()?;
+            return Ok(flows_as_of_chains(&mut conn, &chain_ids, |_| address.to_vec(), tokens, token_addresses.as_deref(), at)?);
+        }
+        let mut query = net_flows::table.filter(net_flows::address.eq(&self.address)).into_boxed();
+        if let Some(chain_id) = self.chain_id {
+            query = query.filter(net_flows::chain_id.eq(chain_id));
+        }
+        if let Some(addrs) = token_addresses {
+            query = query.filter(net_flows::token.eq_any(addrs));
+        }
+        Ok(query.order(net_flows::last_updated.desc()).load(&mut conn)?)
+    }
+
+    /// Token balances derived from indexed transfers, now or as of `atBlock` or `atTime`
+    #[graphql(complexity = "list_cost(child_complexity)")]
+    async fn balances(
+        &self,
+        ctx: &Context<'_>,
+        token: Option<String>,
+        at_block: Option<i64>,
+        at_time: Option<DateTime<Utc>>,
+    ) -> async_graphql::Result<Vec<Balance>> {
+        let token_addresses = resolve_token(ctx, token.as_deref(), self.chain_id)?;
+        let pool = ctx.data::<DbPool>()?;
+        let mut rows = balances_of(pool, &self.address, self.chain_id, token_addresses.as_deref())?;
+        if let Some(at) = AsOf::from_params(at_block, at_time)? {
+            let chain_ids = at.chains(ctx.data::<Vec<ChainConfig>>()?, self.chain_id)?;
+            rows.retain(|row| chain_ids.contains(&row.chain_id));
+            rows = balances_as_of(rows, &mut pool.get()?, at)?;
+        }
+        Ok(rows)
+    }
+
+    #[graphql(complexity = "page_cost(first, child_complexity)")]
+    async fn transfers(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i64>,
+        after: Option<String>,
+        token: Option<String>,
+    ) -> async_graphql::Result<Connection<String, Transfer>> {
+        let filter = TransferFilter {
+            chain_id: self.chain_id,
+            address: Some(self.address.clone()),
+            token,
+            ..Default::default()
+        };
+        load_transfers(ctx, &filter, first, after)
+    }
+
+    /// Inflow and outflow per hour over the last `hours` hours (default 24), oldest first.
+    /// Amounts of different tokens are only meaningful apart, so pass `token` when several are indexed.
+    #[graphql(complexity = "(hours.unwrap_or(24).clamp(1, MAX_SERIES_HOURS) as usize).saturating_mul(child_complexity)")]
+    async fn hourly(&self, ctx: &Context<'_>, hours: Option<i64>, token: Option<String>) -> async_graphql::Result<Vec<HourlyFlow>> {
+        let hours = hours.unwrap_or(24).clamp(1, MAX_SERIES_HOURS);
+        let since = (Utc::now() - Duration::hours(hours)).naive_utc();
+        let token_addresses = resolve_token(ctx, token.as_deref(), self.chain_id)?;
+
+        let mut conn = ctx.data::<DbPool>()?.get()?;
+        let mut query = transfers::table
+            .filter(transfers::from_address.eq(&self.address).or(transfers::to_address.eq(&self.address)))
+            .filter(transfers::timestamp.ge(since))
+            .into_boxed();
+        if let Some(chain_id) = self.chain_id {
+            query = query.filter(transfers::chain_id.eq(chain_id));
+        }
+        if let Some(addrs) = token_addresses {
+            query = query.filter(transfers::token.eq_any(addrs));
+        }
+        let rows: Vec<Transfer> = query.load(&mut conn)?;
+
+        let mut buckets: BTreeMap<DateTime<Utc>, (BigDecimal, BigDecimal, i64)> = BTreeMap::new();
+        for row in rows {
+            let hour = row.timestamp.and_utc().duration_trunc(Duration::hours(1))?;
+            let amount = string_to_bigdecimal(&row.value);
+            let bucket = buckets.entry(hour).or_default();
+            if row.to_address == self.address {
+                bucket.0 += &amount;
+            }
+            if row.from_address == self.address {
+                bucket.1 += &amount;
+            }
+            bucket.2 += 1;
+        }
+
+        Ok(buckets
+            .into_iter()
+            .map(|(hour, (inflow, outflow, count))| HourlyFlow {
+                hour,
+                net_flow: (&inflow - &outflow).to_string(),
+                inflow: inflow.to_string(),
+                outflow: outflow.to_string(),
+                transfer_count: count,
+            })
+            .collect())
+    }
+}
+
+#[ComplexObject]
+impl BlockNode {
+    #[graphql(complexity = "list_cost(child_complexity)")]
+    async fn transfers(&self, ctx: &Context<'_>, token: Option<String>) -> async_graphql::Result<Vec<Transfer>> {
+        let token_addresses = resolve_token(ctx, token.as_deref(), self.chain_id)?;
+        let mut conn = ctx.data::<DbPool>()?.get()?;
+        let mut query = transfers::table.filter(transfers::block_number.eq(self.number)).into_boxed();
+        if let Some(chain_id) = self.chain_id {
+            query = query.filter(transfers::chain_id.eq(chain_id));
+        }
+        if let Some(addrs) = token_addresses {
+            query = query.filter(transfers::token.eq_any(addrs));
+        }
+        Ok(query
+            .order((transfers::chain_id.asc(), transfers::log_index.asc(), transfers::id.asc()))
+            .load(&mut conn)?)
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Indexed transfers, newest first, with cursor pagination
+    #[graphql(complexity = "page_cost(first, child_complexity)")]
+    async fn transfers(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TransferFilter>,
+        first: Option<i64>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<String, Transfer>> {
+        load_transfers(ctx, &filter.unwrap_or_default(), first, after)
+    }
+
+    /// Cumulative net flow per labelled exchange address, chain and token; watched wallets are under `address`
+    /// `atBlock` or `atTime` rebuilds the totals at that point from snapshots and the transfer log
+    #[graphql(complexity = "list_cost(child_complexity)")]
+    async fn net_flows(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<i64>,
+        token: Option<String>,
+        at_block: Option<i64>,
+        at_time: Option<DateTime<Utc>>,
+    ) -> async_graphql::Result<Vec<NetFlow>> {
+        let labels = ctx.data::<Labels>()?;
+        let token_addresses = resolve_token(ctx, token.as_deref(), chain_id)?;
+        let mut conn = ctx.data::<DbPool>()?.get()?;
+        if let Some(at) = AsOf::from_params(at_block, at_time)? {
+            let chain_ids = at.chains(ctx.data::<Vec<ChainConfig>>()?, chain_id)?;
+            let tokens = ctx.data::<TokenSet>()?;
+            let addresses_on = |chain| labels.addresses(Some(chain));
+            return Ok(flows_as_of_chains(&mut conn, &chain_ids, addresses_on, tokens, token_addresses.as_deref(), at)?);
+        }
+        let mut query = net_flows::table.filter(net_flows::address.eq_any(labels.addresses(chain_id))).into_boxed();
+        if let Some(chain_id) = chain_id {
+            query = query.filter(net_flows::chain_id.eq(chain_id));
+        }
+        if let Some(addrs) = token_addresses {
+            query = query.filter(net_flows::token.eq_any(addrs));
+        }
+        let rows: Vec<NetFlow> = query.order(net_flows::last_updated.desc()).load(&mut conn)?;
+        Ok(rows
+            .into_iter()
+            .filter(|row| labels.entity_of(row.chain_id, &row.address).is_some())
+            .collect())
+    }
+
+    /// Indexed tokens, by symbol
+    async fn tokens(&self, ctx: &Context<'_>, chain_id: Option<i64>) -> async_graphql::Result<Vec<Token>> {
+        Ok(ctx.data::<TokenSet>()?.all(chain_id))
+    }
+
+    /// Addresses holding the most of a token, largest balance first
+    #[graphql(complexity = "page_cost(first, child_complexity)")]
+    async fn top_holders(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        chain_id: Option<i64>,
+        first: Option<i64>,
+    ) -> async_graphql::Result<Vec<Balance>> {
+        let token_addresses = ctx.data::<TokenSet>()?.resolve(&token, chain_id)?;
+        Ok(top_holders(ctx.data::<DbPool>()?, chain_id, &token_addresses, first.unwrap_or(DEFAULT_PAGE_SIZE))?)
+    }
+
+    /// Configured chains with their indexing progress
+    async fn chains(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<ChainStatus>> {
+        Ok(chain_statuses(ctx.data::<DbPool>()?, ctx.data::<Vec<ChainConfig>>()?)?)
+    }
+
+    async fn address(&self, ctx: &Context<'_>, address: String, chain_id: Option<i64>) -> async_graphql::Result<AddressNode> {
+        Ok(AddressNode::new(ctx.data::<Labels>()?, chain_id, &address))
+    }
+
+    #[graphql(complexity = "list_cost(child_complexity)")]
+    async fn entities(&self, ctx: &Context<'_>, chain_id: Option<i64>) -> async_graphql::Result<Vec<Entity>> {
+        Ok(known_entities(ctx.data::<Labels>()?, chain_id))
+    }
+
+    async fn entity(&self, ctx: &Context<'_>, name: String, chain_id: Option<i64>) -> async_graphql::Result<Option<Entity>> {
+        Ok(known_entities(ctx.data::<Labels>()?, chain_id)
+            .into_iter()
+            .find(|entity| entity.name.eq_ignore_ascii_case(&name)))
+    }
+
+    /// Blocks that contained at least one indexed transfer (of `token`, if given), newest first
+    #[graphql(complexity = "page_cost(first, child_complexity)")]
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<i64>,
+        first: Option<i64>,
+        before: Option<i64>,
+        token: Option<String>,
+    ) -> async_graphql::Result<Vec<BlockNode>> {
+        let token_addresses = resolve_token(ctx, token.as_deref(), chain_id)?;
+        let mut conn = ctx.data::<DbPool>()?.get()?;
+        let mut query = transfers::table
+            .select((transfers::chain_id, transfers::block_number))
+            .distinct()
+            .into_boxed();
+        if let Some(chain_id) = chain_id {
+            query = query.filter(transfers::chain_id.eq(chain_id));
+        }
+        if let Some(before) = before {
+            query = query.filter(transfers::block_number.lt(before));
+        }
+        if let Some(addrs) = token_addresses {
+            query = query.filter(transfers::token.eq_any(addrs));
+        }
+        let blocks: Vec<(i64, i64)> = query
+            .order((transfers::block_number.desc(), transfers::chain_id.asc()))
+            .limit(first.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE))
+            .load(&mut conn)?;
+
+        Ok(blocks
+            .into_iter()
+            .map(|(chain_id, number)| BlockNode { chain_id: Some(chain_id), number })
+            .collect())
+    }
+
+    async fn block(&self, number: i64, chain_id: Option<i64>) -> BlockNode {
+        BlockNode { chain_id, number }
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    async fn transfers(&self, ctx: &Context<'_>, filter: Option<EventFilter>) -> async_graphql::Result<impl Stream<Item = Transfer>> {
+        event_stream(ctx, filter, |event| match event {
+            Event::Transfer(transfer) => Some(transfer),
+            _ => None,
+        })
+    }
+
+    async fn net_flow_updates(&self, ctx: &Context<'_>, filter: Option<EventFilter>) -> async_graphql::Result<impl Stream<Item = NetFlow>> {
+        event_stream(ctx, filter, |event| match event {
+            Event::NetFlowUpdate(flow) => Some(NetFlow {
+                chain_id: flow.chain_id,
+                address: flow.address,
+                token: flow.token,
+                net_flow: bigdecimal_to_string(&flow.net_flow),
+                inflow: bigdecimal_to_string(&flow.inflow),
+                outflow: bigdecimal_to_string(&flow.outflow),
+                transfer_count: flow.transfer_count,
+                last_updated: flow.last_updated.naive_utc(),
+            }),
+            _ => None,
+        })
+    }
+
+    async fn blocks(&self, ctx: &Context<'_>, chain_id: Option<i64>) -> async_graphql::Result<impl Stream<Item = BlockSummary>> {
+        let filter = EventFilter { chain_id, ..Default::default() };
+        event_stream(ctx, Some(filter), |event| match event {
+            Event::NewBlock(block) => Some(block),
+            _ => None,
+        })
+    }
+}
+
+fn known_entities(labels: &Labels, chain_id: Option<i64>) -> Vec<Entity> {
+    labels
+        .entities(chain_id)
+        .into_iter()
+        .map(|(name, addresses)| Entity {
+            addresses: addresses
+                .iter()
+                .map(|(chain_id, addr)| AddressNode::new(labels, Some(*chain_id), addr))
+                .collect(),
+            name,
+        })
+        .collect()
+}
+
+/// Live events from the indexer that pass `filter`, mapped by `select`
+fn event_stream<T, F>(
+    ctx: &Context<'_>,
+    filter: Option<EventFilter>,
+    select: F,
+) -> async_graphql::Result<impl Stream<Item = T>>
+where
+    T: Send + 'static,
+    F: Fn(Event) -> Option<T> + Send + 'static,
+{
+    let receiver = ctx.data::<EventBus>()?.subscribe();
+    let filter = Filter::from(filter.unwrap_or_default()).resolve(ctx.data::<TokenSet>()?, ctx.data::<Labels>()?)?;
+
+    Ok(stream::unfold((receiver, filter, select), |(mut receiver, filter, select)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(sequenced) => {
+                    if !filter.matches(&sequenced.event) {
+                        continue;
+                    }
+                    if let Some(item) = select(sequenced.event) {
+                        return Some((item, (receiver, filter, select)));
+                    }
+                }
+                // Subscriptions are best-effort; skip what a slow client missed
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    metrics().broadcast_lagged.with_label_values(&["graphql"]).inc_by(skipped);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }))
+}
+
+/// Keyset-paginated transfer page ordered by (block_number, id) descending.
+///
+/// Cursors are `<block_number>:<id>` of the last edge on the previous page.
+fn load_transfers(
+    ctx: &Context<'_>,
+    filter: &TransferFilter,
+    first: Option<i64>,
+    after: Option<String>,
+) -> async_graphql::Result<Connection<String, Transfer>> {
+    use crate::schema::transfers::dsl::*;
+
+    let page_size = first.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let mut query = transfers.into_boxed();
+
+    if let Some(chain) = filter.chain_id {
+        query = query.filter(chain_id.eq(chain));
+    }
+    if let Some(addr) = &filter.address {
+        let addr = addr.to_lowercase();
+        query = query.filter(from_address.eq(addr.clone()).or(to_address.eq(addr)));
+    }
+    if let Some(addr) = &filter.from_address {
+        query = query.filter(from_address.eq(addr.to_lowercase()));
+    }
+    if let Some(addr) = &filter.to_address {
+        query = query.filter(to_address.eq(addr.to_lowercase()));
+    }
+    if filter.binance_only.unwrap_or(false) {
+        query = query.filter(is_binance_related.eq(true));
+    }
+    if filter.watched_only.unwrap_or(false) {
+        query = query.filter(is_watched.eq(true));
+    }
+    if let Some(min_block) = filter.min_block {
+        query = query.filter(block_number.ge(min_block));
+    }
+    if let Some(max_block) = filter.max_block {
+        query = query.filter(block_number.le(max_block));
+    }
+    if let Some(addrs) = resolve_token(ctx, filter.token.as_deref(), filter.chain_id)? {
+        query = query.filter(token.eq_any(addrs));
+    }
+    if let Some(cursor) = &after {
+        // Newest first; within a block by log index, with transfers stored before it was recorded last
+        let (cursor_block, cursor_log, cursor_id) = decode_cursor(cursor)?;
+        query = match cursor_log {
+            Some(cursor_log) => query.filter(
+                block_number.lt(cursor_block).or(block_number.eq(cursor_block).and(
+                    log_index
+                        .lt(cursor_log)
+                        .or(log_index.eq(cursor_log).and(id.lt(cursor_id)))
+                        .or(log_index.is_null()),
+                )),
+            ),
+            None => query.filter(
+                block_number
+                    .lt(cursor_block)
+                    .or(block_number.eq(cursor_block).and(log_index.is_null()).and(id.lt(cursor_id))),
+            ),
+        };
+    }
+
+    let mut conn = ctx.data::<DbPool>()?.get()?;
+    let mut rows: Vec<Transfer> = query
+        .order((block_number.desc(), log_index.desc(), id.desc()))
+        .limit(page_size + 1)
+        .load(&mut conn)?;
+
+    let has_next_page = rows.len() as i64 > page_size;
+    rows.truncate(page_size as usize);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection.edges.extend(
+        rows.into_iter()
+            .map(|transfer| Edge::new(encode_cursor(&transfer), transfer)),
+    );
+    Ok(connection)
+}
+
+/// Contract addresses of a `token` argument given as an address or symbol
+fn resolve_token(ctx: &Context<'_>, token: Option<&str>, chain_id: Option<i64>) -> async_graphql::Result<Option<Vec<String>>> {
+    Ok(ctx.data::<TokenSet>()?.resolve_opt(token, chain_id)?)
+}
+
+/// `block:log_index:id`, the log index left empty when it isn't known
+fn encode_cursor(transfer: &Transfer) -> String {
+    let log_index = transfer.log_index.map(|index| index.to_string()).unwrap_or_default();
+    format!("{}:{}:{}", transfer.block_number, log_index, transfer.id)
+}
+
+fn decode_cursor(cursor: &str) -> async_graphql::Result<(i64, Option<i64>, String)> {
+    let invalid = || async_graphql::Error::new("Invalid cursor");
+    let mut parts = cursor.splitn(3, ':');
+    let (Some(block), Some(log_index), Some(id)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    let block = block.parse().map_err(|_| invalid())?;
+    let log_index = match log_index {
+        "" => None,
+        index => Some(index.parse().map_err(|_| invalid())?),
+    };
+    Ok((block, log_index, id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_pool;
+
+    fn schema() -> (tempfile::TempDir, GraphqlSchema) {
+        let (dir, pool) = test_pool();
+        let tokens = TokenSet::load(&pool).unwrap();
+        let config = Config::default();
+        let labels = Labels::from_config(&config);
+        (dir, build_schema(&config, pool, EventBus::new(), tokens, labels))
+    }
+
+    fn rejected(response: &async_graphql::Response, reason: &str) -> bool {
+        response.errors.iter().any(|error| error.message.contains(reason))
+    }
+
+    #[tokio::test]
+    async fn ordinary_queries_pass() {
+        let (_dir, schema) = schema();
+        let response = schema
+            .execute("{ transfers(first: 100) { edges { cursor node { id value blockNumber logIndex } } } entities { name } }")
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    }
+
+    #[tokio::test]
+    async fn deep_queries_are_rejected() {
+        let (_dir, schema) = schema();
+        let response = schema
+            .execute("{ entity(name: \"binance\") { addresses { transfers(first: 5) { edges { node { id } } } } } }")
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let response = schema
+            .execute("{ __schema { types { fields { type { ofType { ofType { ofType { ofType { ofType { ofType { name } } } } } } } } } } }")
+            .await;
+        assert!(rejected(&response, "nested too deep"), "{:?}", response.errors);
+    }
+
+    #[tokio::test]
+    async fn nested_pages_are_rejected_by_complexity() {
+        let (_dir, schema) = schema();
+        let response = schema
+            .execute("{ entities { addresses { transfers(first: 500) { edges { node { id value } } } } } }")
+            .await;
+        assert!(rejected(&response, "too complex"), "{:?}", response.errors);
+
+        let response = schema.execute("{ transfers(first: 9223372036854775807) { edges { node { id } } } }").await;
+        assert!(rejected(&response, "too complex"), "{:?}", response.errors);
+    }
+}
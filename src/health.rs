@@ -0,0 +1,233 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokio::time::Duration;
+use utoipa::ToSchema;
+
+use crate::{
+    config::ChainConfig,
+    models::IndexerState,
+    server::ServerState,
+    utils::{current_timestamp, current_utc_timestamp},
+};
+
+/// How long readiness waits for a database connection before failing
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Failing,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    #[schema(value_type = String)]
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+impl ComponentHealth {
+    fn ok(detail: String) -> Self {
+        Self { status: HealthStatus::Ok, detail }
+    }
+
+    fn failing(detail: String) -> Self {
+        Self { status: HealthStatus::Failing, detail }
+    }
+
+    fn is_ok(&self) -> bool {
+        matches!(self.status, HealthStatus::Ok)
+    }
+}
+
+/// Liveness: the process is up and serving requests
+#[utoipa::path(
+    get,
+    path = "/api/health/live",
+    tag = "indexer",
+    responses((status = 200, description = "Process is alive", body = HealthReport))
+)]
+pub async fn liveness() -> Json<HealthReport> {
+    Json(HealthReport {
+        status: HealthStatus::Ok,
+        timestamp: current_utc_timestamp(),
+        components: BTreeMap::new(),
+    })
+}
+
+/// Readiness: the database answers and every configured chain's indexer is keeping up
+#[utoipa::path(
+    get,
+    path = "/api/health/ready",
+    tag = "indexer",
+    responses(
+        (status = 200, description = "All components healthy", body = HealthReport),
+        (status = 503, description = "At least one component failing", body = HealthReport),
+    )
+)]
+pub async fn readiness(State(state): State<ServerState>) -> (StatusCode, Json<HealthReport>) {
+    let mut components = BTreeMap::new();
+    components.insert("database".to_string(), check_database(&state));
+    for chain in &state.config.chains {
+        match state.indexer_state(chain.chain_id as i64) {
+            Ok(indexer) => {
+                components.insert(format!("indexer:{}", chain.name), check_indexer(&state, &indexer));
+                components.insert(format!("lag:{}", chain.name), check_lag(&state, chain, &indexer));
+            }
+            Err(e) => {
+                let detail = format!("Failed to read indexer progress: {}", e);
+                components.insert(format!("indexer:{}", chain.name), ComponentHealth::failing(detail));
+            }
+        }
+    }
+
+    let healthy = components.values().all(ComponentHealth::is_ok);
+    let report = HealthReport {
+        status: if healthy { HealthStatus::Ok } else { HealthStatus::Failing },
+        timestamp: current_utc_timestamp(),
+        components,
+    };
+
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report))
+}
+
+fn check_database(state: &ServerState) -> ComponentHealth {
+    match state.pool.get_timeout(DB_CHECK_TIMEOUT) {
+        Ok(_) => {
+            let pool_state = state.pool.state();
+            ComponentHealth::ok(format!(
+                "{} of {} connections idle",
+                pool_state.idle_connections,
+                state.pool.max_size()
+            ))
+        }
+        Err(e) => ComponentHealth::failing(format!("No connection available: {}", e)),
+    }
+}
+
+/// Reads the progress the indexer records in the database, so it works for
+/// API nodes that don't run the indexer themselves
+fn check_indexer(state: &ServerState, indexer: &Option<IndexerState>) -> ComponentHealth {
+    let Some(indexer) = indexer else {
+        return ComponentHealth::failing("Indexer has not started yet".to_string());
+    };
+    if !indexer.is_running {
+        return ComponentHealth::failing("Indexer is not running".to_string());
+    }
+
+    let age = (current_timestamp() - indexer.last_block_at).num_seconds().max(0) as u64;
+    let limit = state.config.health_max_block_age_secs;
+    if age > limit {
+        ComponentHealth::failing(format!("No new block for {}s (limit {}s)", age, limit))
+    } else {
+        ComponentHealth::ok(format!("Last block {}s ago", age))
+    }
+}
+
+/// Blocks behind the head beyond the chain's confirmation depth, which the indexer waits out on purpose
+fn check_lag(state: &ServerState, chain: &ChainConfig, indexer: &Option<IndexerState>) -> ComponentHealth {
+    let Some(indexer) = indexer else {
+        return ComponentHealth::failing("Indexer has not started yet".to_string());
+    };
+    let head = indexer.chain_head.max(0) as u64;
+    let processed = indexer.current_block.max(0) as u64;
+    let lag = head.saturating_sub(chain.confirmations).saturating_sub(processed);
+    let limit = state.config.health_max_lag_blocks;
+
+    let detail = format!("{} blocks behind head {} (limit {})", lag, head, limit);
+    if lag > limit {
+        ComponentHealth::failing(detail)
+    } else {
+        ComponentHealth::ok(detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, database::test_pool, schema::indexer_state};
+    use diesel::prelude::*;
+
+    /// Records Polygon progress at `current_block` of `chain_head`, the last block `age_secs` ago
+    fn progress(state: &ServerState, current_block: i64, chain_head: i64, age_secs: i64) {
+        let row = IndexerState {
+            chain_id: 137,
+            current_block,
+            chain_head,
+            is_running: true,
+            last_block_at: current_timestamp() - chrono::Duration::seconds(age_secs),
+            updated_at: current_timestamp(),
+        };
+        diesel::replace_into(indexer_state::table)
+            .values(&row)
+            .execute(&mut state.pool.get().unwrap())
+            .unwrap();
+    }
+
+    async fn ready(state: &ServerState) -> (StatusCode, HealthReport) {
+        let (status, Json(report)) = readiness(State(state.clone())).await;
+        (status, report)
+    }
+
+    fn failing(report: &HealthReport) -> Vec<&str> {
+        report
+            .components
+            .iter()
+            .filter(|(_, component)| !component.is_ok())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn readiness_fails_until_the_indexer_keeps_up() {
+        let (_dir, pool) = test_pool();
+        let mut config = Config::default();
+        config.chains[0].confirmations = 12;
+        let state = ServerState::for_tests(config, pool);
+
+        let (status, report) = ready(&state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(failing(&report), vec!["indexer:polygon", "lag:polygon"]);
+
+        // 100 blocks behind, less the 12 it waits for on purpose
+        progress(&state, 1000, 1112, 0);
+        let (status, report) = ready(&state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(failing(&report), vec!["lag:polygon"]);
+        assert_eq!(report.components["lag:polygon"].detail, "100 blocks behind head 1112 (limit 50)");
+
+        progress(&state, 1100, 1112, 120);
+        let (status, report) = ready(&state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(failing(&report), vec!["indexer:polygon"]);
+
+        progress(&state, 1100, 1112, 5);
+        let (status, report) = ready(&state).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(matches!(report.status, HealthStatus::Ok));
+    }
+
+    #[tokio::test]
+    async fn database_check_fails_when_no_connection_frees_up_in_time() {
+        let (_dir, pool) = test_pool();
+        let state = ServerState::for_tests(Config::default(), pool.clone());
+
+        let held: Vec<_> = std::iter::from_fn(|| pool.try_get()).collect();
+        let database = check_database(&state);
+        assert!(!database.is_ok());
+        assert!(database.detail.starts_with("No connection available"), "{}", database.detail);
+
+        drop(held);
+        assert!(check_database(&state).is_ok());
+    }
+}
@@ -0,0 +1,498 @@
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::dsl::max;
+use diesel::prelude::*;
+use diesel::sql_types::Bool;
+use diesel::sqlite::Sqlite;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use utoipa::IntoParams;
+
+use crate::{
+    config::ChainConfig,
+    database::DbConnection,
+    models::{Balance, NetFlow, NetFlowSnapshot},
+    schema::{net_flow_snapshots, transfers},
+    tokens::TokenSet,
+    utils::{bigdecimal_to_string, string_to_bigdecimal},
+    IndexerError, Result,
+};
+
+/// Rows per snapshot insert, well under SQLite's bound parameter limit
+const SNAPSHOT_CHUNK: usize = 500;
+
+/// Addresses per `IN (...)` query; the transfers query binds them twice, so this stays
+/// well under SQLite's parameter limit
+const ADDRESS_CHUNK_SIZE: usize = 400;
+
+/// A point in a chain's history: the end of a block, or an instant in block time
+#[derive(Debug, Clone, Copy)]
+pub enum AsOf {
+    Block(i64),
+    Time(NaiveDateTime),
+}
+
+impl AsOf {
+    /// From `at_block` / `at_time`; `None` asks for the current totals
+    pub fn from_params(at_block: Option<i64>, at_time: Option<DateTime<Utc>>) -> Result<Option<Self>> {
+        match (at_block, at_time) {
+            (Some(_), Some(_)) => Err(IndexerError::BadRequest("Pass at_block or at_time, not both".to_string())),
+            (Some(block), None) if block < 0 => {
+                Err(IndexerError::BadRequest(format!("at_block must not be negative, got {}", block)))
+            }
+            (Some(block), None) => Ok(Some(AsOf::Block(block))),
+            (None, Some(time)) => Ok(Some(AsOf::Time(time.naive_utc()))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Chains to reconstruct: `chain_id`, else every configured chain. Block numbers
+    /// differ between chains, so `at_block` needs `chain_id` when several are configured.
+    pub fn chains(&self, chains: &[ChainConfig], chain_id: Option<i64>) -> Result<Vec<i64>> {
+        match (chain_id, self) {
+            (Some(chain_id), _) => Ok(vec![chain_id]),
+            (None, AsOf::Block(_)) if chains.len() > 1 => Err(IndexerError::BadRequest(
+                "at_block needs chain_id when several chains are indexed".to_string(),
+            )),
+            (None, _) => Ok(chains.iter().map(|chain| chain.chain_id as i64).collect()),
+        }
+    }
+
+    fn includes_transfers(&self) -> Box<dyn BoxableExpression<transfers::table, Sqlite, SqlType = Bool>> {
+        match *self {
+            AsOf::Block(block) => Box::new(transfers::block_number.le(block)),
+            AsOf::Time(time) => Box::new(transfers::timestamp.le(time)),
+        }
+    }
+
+    fn includes_snapshots(&self) -> Box<dyn BoxableExpression<net_flow_snapshots::table, Sqlite, SqlType = Bool>> {
+        match *self {
+            AsOf::Block(block) => Box::new(net_flow_snapshots::block_number.le(block)),
+            AsOf::Time(time) => Box::new(net_flow_snapshots::block_timestamp.le(time)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AsOfQuery {
+    /// Chain id; all chains when omitted
+    pub chain_id: Option<i64>,
+    /// Token address or symbol; all tokens when omitted
+    pub token: Option<String>,
+    /// Totals at the end of this block instead of now; needs `chain_id` when several chains are indexed
+    pub at_block: Option<i64>,
+    /// Totals at this RFC 3339 time (block time) instead of now
+    #[param(value_type = Option<String>)]
+    pub at_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct Totals {
+    /// Transfers up to this block are already counted
+    from_block: i64,
+    inflow: BigDecimal,
+    outflow: BigDecimal,
+    transfer_count: i64,
+    last_transfer_at: Option<NaiveDateTime>,
+}
+
+/// Inflow and outflow of every address and token pair as of `at`, from the latest
+/// snapshot of each pair before it plus the transfers logged after that snapshot
+fn totals_as_of(
+    conn: &mut SqliteConnection,
+    chain_id: i64,
+    addresses: &[String],
+    tokens: &[String],
+    at: AsOf,
+) -> Result<BTreeMap<(String, String), Totals>> {
+    let mut totals = BTreeMap::new();
+    for chunk in addresses.chunks(ADDRESS_CHUNK_SIZE) {
+        totals.extend(chunk_totals_as_of(conn, chain_id, chunk, tokens, at)?);
+    }
+    Ok(totals)
+}
+
+/// `totals_as_of` for up to `ADDRESS_CHUNK_SIZE` addresses
+fn chunk_totals_as_of(
+    conn: &mut SqliteConnection,
+    chain_id: i64,
+    addresses: &[String],
+    tokens: &[String],
+    at: AsOf,
+) -> Result<BTreeMap<(String, String), Totals>> {
+    let latest: Vec<(String, String, Option<i64>)> = net_flow_snapshots::table
+        .filter(net_flow_snapshots::chain_id.eq(chain_id))
+        .filter(net_flow_snapshots::address.eq_any(addresses))
+        .filter(net_flow_snapshots::token.eq_any(tokens))
+        .filter(at.includes_snapshots())
+        .group_by((net_flow_snapshots::address, net_flow_snapshots::token))
+        .select((net_flow_snapshots::address, net_flow_snapshots::token, max(net_flow_snapshots::block_number)))
+        .load(conn)?;
+    let latest: HashMap<(String, String), i64> = latest
+        .into_iter()
+        .filter_map(|(address, token, block)| Some(((address, token), block?)))
+        .collect();
+
+    // Pairs never snapshotted (not tracked back then) are summed from the start of the log
+    let mut totals: BTreeMap<(String, String), Totals> = addresses
+        .iter()
+        .flat_map(|address| tokens.iter().map(move |token| ((address.clone(), token.clone()), Totals { from_block: -1, ..Default::default() })))
+        .collect();
+    let blocks: HashSet<i64> = latest.values().copied().collect();
+    if !blocks.is_empty() {
+        let snapshots: Vec<NetFlowSnapshot> = net_flow_snapshots::table
+            .filter(net_flow_snapshots::chain_id.eq(chain_id))
+            .filter(net_flow_snapshots::block_number.eq_any(&blocks))
+            .filter(net_flow_snapshots::address.eq_any(addresses))
+            .filter(net_flow_snapshots::token.eq_any(tokens))
+            .load(conn)?;
+        for snapshot in snapshots {
+            let key = (snapshot.address, snapshot.token);
+            if latest.get(&key) != Some(&snapshot.block_number) {
+                continue;
+            }
+            if let Some(totals) = totals.get_mut(&key) {
+                *totals = Totals {
+                    from_block: snapshot.block_number,
+                    inflow: string_to_bigdecimal(&snapshot.inflow),
+                    outflow: string_to_bigdecimal(&snapshot.outflow),
+                    transfer_count: snapshot.transfer_count,
+                    last_transfer_at: snapshot.last_transfer_at,
+                };
+            }
+        }
+    }
+
+    let from_block = totals.values().map(|totals| totals.from_block).min().unwrap_or(-1);
+    let later: Vec<(i64, String, String, String, String, NaiveDateTime)> = transfers::table
+        .filter(transfers::chain_id.eq(chain_id))
+        .filter(transfers::block_number.gt(from_block))
+        .filter(transfers::token.eq_any(tokens))
+        .filter(transfers::from_address.eq_any(addresses).or(transfers::to_address.eq_any(addresses)))
+        .filter(at.includes_transfers())
+        .select((
+            transfers::block_number,
+            transfers::from_address,
+            transfers::to_address,
+            transfers::token,
+            transfers::value,
+            transfers::timestamp,
+        ))
+        .load(conn)?;
+
+    for (block_number, from, to, token, value, timestamp) in later {
+        let value = string_to_bigdecimal(&value);
+        for (address, is_outflow) in [(from, true), (to, false)] {
+            let Some(totals) = totals.get_mut(&(address, token.clone())) else {
+                continue;
+            };
+            if block_number <= totals.from_block {
+                continue;
+            }
+            if is_outflow {
+                totals.outflow += &value;
+            } else {
+                totals.inflow += &value;
+            }
+            totals.transfer_count += 1;
+            totals.last_transfer_at = totals.last_transfer_at.max(Some(timestamp));
+        }
+    }
+    Ok(totals)
+}
+
+/// Net flows of `addresses` in `tokens` as they stood at `at`, most recently active first.
+/// `last_updated` is the time of the latest transfer counted; pairs without any are left out.
+pub fn flows_as_of(
+    conn: &mut DbConnection,
+    chain_id: i64,
+    addresses: &[String],
+    tokens: &[String],
+    at: AsOf,
+) -> Result<Vec<NetFlow>> {
+    let mut flows: Vec<NetFlow> = totals_as_of(conn, chain_id, addresses, tokens, at)?
+        .into_iter()
+        .filter_map(|((address, token), totals)| {
+            Some(NetFlow {
+                chain_id,
+                address,
+                token,
+                net_flow: bigdecimal_to_string(&(&totals.inflow - &totals.outflow)),
+                inflow: bigdecimal_to_string(&totals.inflow),
+                outflow: bigdecimal_to_string(&totals.outflow),
+                transfer_count: totals.transfer_count,
+                last_updated: totals.last_transfer_at?,
+            })
+        })
+        .collect();
+    flows.sort_by_key(|flow| Reverse(flow.last_updated));
+    Ok(flows)
+}
+
+/// `flows_as_of` over several chains, for the addresses `addresses_on` gives for each
+/// chain and the `token_addresses` filter, or every token indexed on the chain
+pub fn flows_as_of_chains(
+    conn: &mut DbConnection,
+    chain_ids: &[i64],
+    addresses_on: impl Fn(i64) -> Vec<String>,
+    tokens: &TokenSet,
+    token_addresses: Option<&[String]>,
+    at: AsOf,
+) -> Result<Vec<NetFlow>> {
+    let mut flows = Vec::new();
+    for &chain_id in chain_ids {
+        let chain_tokens: Vec<String> = match token_addresses {
+            Some(addrs) => addrs.to_vec(),
+            None => tokens.all(Some(chain_id)).into_iter().map(|token| token.address).collect(),
+        };
+        flows.extend(flows_as_of(conn, chain_id, &addresses_on(chain_id), &chain_tokens, at)?);
+    }
+    flows.sort_by_key(|flow| Reverse(flow.last_updated));
+    Ok(flows)
+}
+
+/// Records the totals of every address and token pair at the end of `block_number`,
+/// zeros included, so later as-of queries can start there. Runs inside the block's transaction,
+/// or a rebuild's.
+pub fn take_snapshot(
+    conn: &mut SqliteConnection,
+    chain_id: i64,
+    block_number: i64,
+    block_time: NaiveDateTime,
+    addresses: &[String],
+    tokens: &[String],
+) -> Result<usize> {
+    let rows: Vec<NetFlowSnapshot> = totals_as_of(conn, chain_id, addresses, tokens, AsOf::Block(block_number))?
+        .into_iter()
+        .map(|((address, token), totals)| NetFlowSnapshot {
+            chain_id,
+            block_number,
+            address,
+            token,
+            net_flow: bigdecimal_to_string(&(&totals.inflow - &totals.outflow)),
+            inflow: bigdecimal_to_string(&totals.inflow),
+            outflow: bigdecimal_to_string(&totals.outflow),
+            transfer_count: totals.transfer_count,
+            last_transfer_at: totals.last_transfer_at,
+            block_timestamp: block_time,
+        })
+        .collect();
+
+    diesel::delete(
+        net_flow_snapshots::table
+            .filter(net_flow_snapshots::chain_id.eq(chain_id))
+            .filter(net_flow_snapshots::block_number.eq(block_number)),
+    )
+    .execute(conn)?;
+    for chunk in rows.chunks(SNAPSHOT_CHUNK) {
+        diesel::insert_into(net_flow_snapshots::table).values(chunk).execute(conn)?;
+    }
+    Ok(rows.len())
+}
+
+/// Drops snapshots that a transfer newly stored at `block_number` would change, such
+/// as when backfilling below them. Runs inside the block's transaction.
+pub fn invalidate_snapshots(conn: &mut SqliteConnection, chain_id: i64, block_number: i64) -> Result<usize> {
+    let deleted = diesel::delete(
+        net_flow_snapshots::table
+            .filter(net_flow_snapshots::chain_id.eq(chain_id))
+            .filter(net_flow_snapshots::block_number.ge(block_number)),
+    )
+    .execute(conn)?;
+    Ok(deleted)
+}
+
+/// Stored balances rolled back to `at` by undoing the transfers logged after it.
+///
+/// Exact when the current balance is and every transfer since `at` was indexed.
+/// `last_block` becomes the last block the rolled-back balance reflects.
+pub fn balances_as_of(rows: Vec<Balance>, conn: &mut DbConnection, at: AsOf) -> Result<Vec<Balance>> {
+    let rows: BTreeMap<(i64, String), Balance> =
+        rows.into_iter().map(|row| ((row.chain_id, row.token.clone()), row)).collect();
+    let Some(address) = rows.values().next().map(|row| row.address.clone()) else {
+        return Ok(Vec::new());
+    };
+    let chain_ids: HashSet<i64> = rows.keys().map(|(chain_id, _)| *chain_id).collect();
+    let tokens: HashSet<&str> = rows.keys().map(|(_, token)| token.as_str()).collect();
+
+    let later: Vec<(i64, i64, String, String, String, String)> = transfers::table
+        .filter(transfers::chain_id.eq_any(&chain_ids))
+        .filter(transfers::token.eq_any(&tokens))
+        .filter(transfers::from_address.eq(&address).or(transfers::to_address.eq(&address)))
+        .filter(diesel::dsl::not(at.includes_transfers()))
+        .select((
+            transfers::chain_id,
+            transfers::block_number,
+            transfers::from_address,
+            transfers::to_address,
+            transfers::token,
+            transfers::value,
+        ))
+        .load(conn)?;
+
+    let mut undone: HashMap<(i64, String), (BigDecimal, i64, i64)> = HashMap::new();
+    for (chain_id, block_number, from, to, token, value) in later {
+        let value = string_to_bigdecimal(&value);
+        let (change, count, first_block) = undone.entry((chain_id, token)).or_insert((BigDecimal::from(0), 0, i64::MAX));
+        if from == address {
+            *change -= &value;
+        }
+        if to == address {
+            *change += &value;
+        }
+        *count += 1;
+        *first_block = (*first_block).min(block_number);
+    }
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(key, mut row)| {
+            if let Some((change, count, first_block)) = undone.get(&key) {
+                row.balance = bigdecimal_to_string(&(string_to_bigdecimal(&row.balance) - change));
+                row.transfer_count = (row.transfer_count - count).max(0);
+                row.last_block = row.last_block.min(first_block - 1);
+            }
+            if let AsOf::Block(block) = at {
+                row.last_block = row.last_block.min(block);
+            }
+            // Not seeded by then and no transfer counted yet: the address hadn't shown up
+            let seeded = row.seeded_block.is_some_and(|seeded| seeded <= row.last_block);
+            (row.transfer_count > 0 || seeded).then_some(row)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::{test_pool, DbPool}, models::Transfer, tokens::POL_ADDRESS};
+    use chrono::NaiveDate;
+
+    const A: &str = "0x000000000000000000000000000000000000000a";
+    const B: &str = "0x000000000000000000000000000000000000000b";
+
+    /// Block `n` is mined at n minutes past midnight
+    fn block_time(block: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 1).unwrap().and_hms_opt(0, 0, 0).unwrap() + chrono::Duration::minutes(block)
+    }
+
+    fn store(pool: &DbPool, block: i64, from: &str, to: &str, value: &str) {
+        let transfer = Transfer {
+            id: format!("137_0x{:02x}_0", block),
+            chain_id: 137,
+            block_number: block,
+            transaction_hash: format!("0x{:02x}", block),
+            from_address: from.to_string(),
+            to_address: to.to_string(),
+            token: POL_ADDRESS.to_string(),
+            value: value.to_string(),
+            timestamp: block_time(block),
+            is_binance_related: false,
+            is_watched: false,
+            log_index: Some(0),
+            transaction_index: Some(0),
+        };
+        diesel::insert_into(transfers::table).values(&transfer).execute(&mut pool.get().unwrap()).unwrap();
+    }
+
+    fn snapshot(pool: &DbPool, block: i64, addresses: &[String]) -> usize {
+        take_snapshot(&mut pool.get().unwrap(), 137, block, block_time(block), addresses, &[POL_ADDRESS.to_string()]).unwrap()
+    }
+
+    /// (inflow, outflow, transfer count) of `A` as of `at`
+    fn totals_of_a(pool: &DbPool, at: AsOf) -> (BigDecimal, BigDecimal, i64) {
+        let flows = flows_as_of(&mut pool.get().unwrap(), 137, &[A.to_string()], &[POL_ADDRESS.to_string()], at).unwrap();
+        match flows.as_slice() {
+            [] => Default::default(),
+            [flow] => (string_to_bigdecimal(&flow.inflow), string_to_bigdecimal(&flow.outflow), flow.transfer_count),
+            _ => panic!("one pair asked for, got {:?}", flows),
+        }
+    }
+
+    fn totals(inflow: i64, outflow: i64, count: i64) -> (BigDecimal, BigDecimal, i64) {
+        (BigDecimal::from(inflow), BigDecimal::from(outflow), count)
+    }
+
+    fn history(pool: &DbPool) {
+        store(pool, 10, B, A, "5");
+        store(pool, 20, A, B, "2");
+        store(pool, 30, B, A, "1");
+    }
+
+    #[test]
+    fn totals_as_of_a_block_with_and_without_snapshots() {
+        let (_dir, pool) = test_pool();
+        history(&pool);
+        assert_eq!(totals_of_a(&pool, AsOf::Block(9)), Default::default());
+        assert_eq!(totals_of_a(&pool, AsOf::Block(25)), totals(5, 2, 2));
+
+        // From the snapshot on, earlier transfers aren't read again
+        assert_eq!(snapshot(&pool, 20, &[A.to_string()]), 1);
+        diesel::delete(transfers::table.filter(transfers::block_number.le(20)))
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(totals_of_a(&pool, AsOf::Block(25)), totals(5, 2, 2));
+        assert_eq!(totals_of_a(&pool, AsOf::Block(30)), totals(6, 2, 3));
+        // Before the snapshot, only what is still logged counts
+        assert_eq!(totals_of_a(&pool, AsOf::Block(19)), Default::default());
+    }
+
+    #[test]
+    fn at_time_resolves_by_block_time() {
+        let (_dir, pool) = test_pool();
+        history(&pool);
+        snapshot(&pool, 10, &[A.to_string()]);
+
+        let between = DateTime::from_naive_utc_and_offset(block_time(25), Utc);
+        let at = AsOf::from_params(None, Some(between)).unwrap().unwrap();
+        assert_eq!(totals_of_a(&pool, at), totals(5, 2, 2));
+        let flows = flows_as_of(&mut pool.get().unwrap(), 137, &[A.to_string()], &[POL_ADDRESS.to_string()], at).unwrap();
+        assert_eq!(flows[0].last_updated, block_time(20));
+
+        // A block's own time includes it
+        assert_eq!(totals_of_a(&pool, AsOf::Time(block_time(30))), totals(6, 2, 3));
+        assert_eq!(totals_of_a(&pool, AsOf::Time(block_time(10) - chrono::Duration::seconds(1))), Default::default());
+
+        assert!(AsOf::from_params(Some(20), Some(between)).is_err());
+        assert!(AsOf::from_params(Some(-1), None).is_err());
+        assert!(AsOf::from_params(None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn backfilling_below_a_snapshot_invalidates_it() {
+        let (_dir, pool) = test_pool();
+        history(&pool);
+        snapshot(&pool, 10, &[A.to_string()]);
+        snapshot(&pool, 20, &[A.to_string()]);
+
+        // Block 15 arrives late, as the indexer stores it
+        store(&pool, 15, B, A, "7");
+        assert_eq!(invalidate_snapshots(&mut pool.get().unwrap(), 137, 15).unwrap(), 1);
+
+        let kept: Vec<i64> = net_flow_snapshots::table
+            .select(net_flow_snapshots::block_number)
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(kept, [10]);
+        assert_eq!(totals_of_a(&pool, AsOf::Block(25)), totals(12, 2, 3));
+        assert_eq!(totals_of_a(&pool, AsOf::Block(12)), totals(5, 0, 1));
+    }
+
+    #[test]
+    fn totals_cover_addresses_across_chunks() {
+        let (_dir, pool) = test_pool();
+        history(&pool);
+        // A and B land in different chunks
+        let mut addresses: Vec<String> = (0..3 * ADDRESS_CHUNK_SIZE as u64).map(|n| format!("{:#042x}", n + 0x100)).collect();
+        addresses.insert(1, B.to_string());
+        addresses.push(A.to_string());
+
+        assert_eq!(snapshot(&pool, 20, &addresses), addresses.len());
+        store(&pool, 40, A, B, "3");
+        let flows =
+            flows_as_of(&mut pool.get().unwrap(), 137, &addresses, &[POL_ADDRESS.to_string()], AsOf::Block(40)).unwrap();
+        let counts: Vec<(&str, i64)> = flows.iter().map(|flow| (flow.address.as_str(), flow.transfer_count)).collect();
+        assert_eq!(counts, [(A, 4), (B, 4)]);
+    }
+}
@@ -1,54 +1,146 @@
 ﻿use ethers::prelude::*;
 use ethers::providers::{Provider, Ws};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio::time::Duration;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::{Notify, RwLock};
+use tokio::time::{Duration, Instant};
 use tracing::{info, warn, error, debug};
 use bigdecimal::BigDecimal;
+use chrono::{DateTime, NaiveDateTime, Utc};
 
 use crate::{
-    Config, Result, IndexerError,
+    Result, IndexerError,
+    archive::{self, BlockLogs},
+    balances,
+    config::ChainConfig,
+    coverage,
     database::DbPool,
-    models::{Transfer, NetFlow},
-    utils::{is_binance_address, format_address, wei_to_ether, generate_transfer_id, current_timestamp, 
-            bigdecimal_to_string, add_bigdecimal_strings, subtract_bigdecimal_strings},
-    schema::transfers,
+    events::{Event, EventBus},
+    history,
+    labels::Labels,
+    metrics::{metrics, observe_db_write, observe_rpc},
+    models::{Transfer, NetFlow, IndexerState, Token},
+    tokens::register_tokens,
+    types::{BlockSummary, NetFlowData},
+    watchlists::{WatchSet, WatchedTransfer},
+    utils::{format_address, to_token_units, generate_transfer_id, current_timestamp, 
+            bigdecimal_to_string, string_to_bigdecimal, add_bigdecimal_strings, subtract_bigdecimal_strings},
+    schema::{alert_firings, transfers, indexer_state},
 };
 use diesel::prelude::*;
 
-pub struct PolygonIndexer {
-    config: Config,
+/// Archived blocks loaded per query when replaying
+const REPLAY_BATCH_BLOCKS: i64 = 100;
+
+/// A transfer decoded from a log, with the id it was stored under before log indexes were recorded
+struct DecodedTransfer {
+    transfer: Transfer,
+    /// Numbers the log by its position in the transaction instead of the block
+    legacy_id: String,
+}
+
+/// Indexes one chain's token transfers; each configured chain runs its own.
+///
+/// Cloning is cheap and clones share the same state, so a block can be committed in a blocking task.
+#[derive(Clone)]
+pub struct ChainIndexer {
+    chain_id: i64,
+    /// Chain name, for logs and metrics
+    name: String,
+    /// How far behind the announced head blocks are indexed
+    confirmations: u64,
+    /// Net flows are snapshotted at block numbers divisible by this
+    snapshot_interval: u64,
+    /// Whether every log of each block is archived
+    archive_logs: bool,
+    labels: Labels,
     pool: DbPool,
     provider: Arc<Provider<Ws>>,
     current_block: Arc<RwLock<u64>>,
+    chain_head: Arc<RwLock<u64>>,
+    last_block_at: Arc<RwLock<Instant>>,
     is_running: Arc<RwLock<bool>>,
+    stop_requested: Arc<Notify>,
+    events: EventBus,
+    watchlists: WatchSet,
+    /// Indexed tokens by address
+    tokens: Arc<HashMap<String, Token>>,
+    /// The same tokens, for log filters
+    token_addresses: Vec<Address>,
 }
 
-impl PolygonIndexer {
-    pub async fn new(config: Config, pool: DbPool) -> Result<Self> {
-        info!("🔗 Connecting to Polygon WebSocket: {}", config.polygon_ws_url);
+impl ChainIndexer {
+    /// Connects to the chain's node and registers its tokens, and publishes its labels to `labels`
+    /// unless the chain has published ones, which it then follows
+    pub async fn new(chain: ChainConfig, pool: DbPool, events: EventBus, watchlists: WatchSet, labels: Labels) -> Result<Self> {
+        info!("🔗 Connecting to {} WebSocket: {}", chain.name, chain.ws_url);
         
-        let provider = Provider::<Ws>::connect(&config.polygon_ws_url)
+        let provider = Provider::<Ws>::connect(&chain.ws_url)
             .await
-            .map_err(|e| IndexerError::Web3(format!("Failed to connect to WebSocket: {}", e)))?;
+            .map_err(|e| IndexerError::Web3(format!("Failed to connect to {} WebSocket: {}", chain.name, e)))?;
         
         let provider = Arc::new(provider);
         
+        // A node serving another network would fill this chain's rows with foreign data
+        let node_chain_id = observe_rpc("eth_chainId", provider.get_chainid())
+            .await
+            .map_err(IndexerError::Ethereum)?;
+        if node_chain_id != U256::from(chain.chain_id) {
+            return Err(IndexerError::Config(format!(
+                "The {} node reports chain id {}, expected {}",
+                chain.name, node_chain_id, chain.chain_id
+            )));
+        }
+        
         // Get current block number
-        let current_block = provider
-            .get_block_number()
+        let chain_head = observe_rpc("eth_blockNumber", provider.get_block_number())
             .await
             .map_err(IndexerError::Ethereum)?
             .as_u64();
+        // Nothing above the confirmation depth is indexed yet
+        let current_block = chain_head.saturating_sub(chain.confirmations);
         
-        info!("📦 Current block: {}", current_block);
+        info!("📦 Current {} block: {}", chain.name, chain_head);
+        
+        let chain_id = chain.chain_id as i64;
+        let tokens = register_tokens(&provider, &pool, chain_id, &chain.tokens).await?;
+        let token_addresses = tokens
+            .iter()
+            .map(|token| token.address.parse::<Address>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| IndexerError::Config(format!("Invalid token address: {}", e)))?;
+        let tokens = tokens.into_iter().map(|token| (token.address.clone(), token)).collect();
+
+        // The published labels are the ones history was classified with; configuration
+        // changes take effect through `rebuild`, which also reclassifies history
+        Labels::publish_initial(&pool, &chain)?;
+        labels.reload(&pool)?;
+        if !labels.follows_config(chain_id) {
+            warn!(
+                "⚠️ The configured {} labels differ from the published ones, which stay in use; run `rebuild --chain {}` to apply them",
+                chain.name, chain.name
+            );
+        }
         
         Ok(Self {
-            config,
+            chain_id,
+            labels,
+            name: chain.name,
+            confirmations: chain.confirmations,
+            snapshot_interval: chain.snapshot_interval,
+            archive_logs: chain.archive_logs,
             pool,
             provider,
             current_block: Arc::new(RwLock::new(current_block)),
+            chain_head: Arc::new(RwLock::new(chain_head)),
+            // Counts from startup until the first block arrives
+            last_block_at: Arc::new(RwLock::new(Instant::now())),
             is_running: Arc::new(RwLock::new(false)),
+            stop_requested: Arc::new(Notify::new()),
+            events,
+            watchlists,
+            tokens: Arc::new(tokens),
+            token_addresses,
         })
     }
 
@@ -58,151 +150,502 @@ impl PolygonIndexer {
             *running = true;
         }
         
-        info!("⚡ Starting Polygon POL Token Indexer...");
+        info!(
+            "⚡ Starting {} token indexer ({} tokens, {} confirmations)...",
+            self.name, self.tokens.len(), self.confirmations
+        );
+        if let Err(e) = self.record_progress().await {
+            warn!("⚠️ Failed to record indexer progress: {}", e);
+        }
         
         // Start monitoring new blocks
         let provider = self.provider.clone();
-        let pool = self.pool.clone();
         let current_block = self.current_block.clone();
         let is_running = self.is_running.clone();
-        let pol_contract = self.config.pol_contract.clone();
         
-        let mut stream = provider.subscribe_blocks().await.map_err(IndexerError::Ethereum)?;
+        let mut stream = match provider.subscribe_blocks().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                *is_running.write().await = false;
+                self.record_stopped();
+                return Err(IndexerError::Ethereum(e));
+            }
+        };
         
-        info!("🔄 Subscribed to new blocks");
+        info!("🔄 Subscribed to new {} blocks", self.name);
         
         while *is_running.read().await {
+            // A block that has started processing always finishes before the
+            // stop request is looked at, so shutdown never cuts one in half
             tokio::select! {
-                Some(block) = stream.next() => {
-                    if let Err(e) = self.process_block(&block, &pool, &pol_contract).await {
-                        error!("❌ Error processing block {}: {}", block.number.unwrap_or_default(), e);
-                    }
-                    
-                    *current_block.write().await = block.number.unwrap_or_default().as_u64();
-                }
-                _ = tokio::time::sleep(Duration::from_millis(100)) => {
-                    if !*is_running.read().await {
+                biased;
+                _ = self.stop_requested.notified() => break,
+                block = stream.next() => {
+                    let Some(block) = block else {
+                        warn!("⚠️ {} block subscription ended", self.name);
                         break;
+                    };
+                    let head = block.number.unwrap_or_default().as_u64();
+                    let previous = *current_block.read().await;
+                    *self.chain_head.write().await = head;
+                    *self.last_block_at.write().await = Instant::now();
+                    metrics().set_progress(&self.name, head, previous);
+                    
+                    // With confirmations, the block to index sits that far below the new head
+                    let Some(number) = head.checked_sub(self.confirmations) else {
+                        continue;
+                    };
+                    let result = match self.confirmed_block(block, number).await {
+                        Ok(block) => self.process_block(&block, true).await,
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(()) => {
+                            *current_block.write().await = number;
+                            metrics().set_progress(&self.name, head, number);
+                        }
+                        Err(e) => {
+                            // The checkpoint stays on the last block that was stored
+                            error!("❌ Error processing {} block {}: {}", self.name, number, e);
+                            if let Err(e) = self.record_progress().await {
+                                warn!("⚠️ Failed to record indexer progress: {}", e);
+                            }
+                        }
                     }
                 }
             }
         }
         
-        info!("🛑 Indexer stopped");
+        *is_running.write().await = false;
+        self.record_stopped();
+        info!("🛑 {} indexer stopped at block {}", self.name, *current_block.read().await);
         Ok(())
     }
 
-    async fn process_block(&self, block: &Block<H256>, pool: &DbPool, pol_contract: &str) -> Result<()> {
-        let block_number = block.number.unwrap_or_default().as_u64();
-        debug!("🔍 Processing block: {}", block_number);
-        
-        // Get all transactions in this block
-        let block_with_txs = self.provider
-            .get_block_with_txs(block.hash.unwrap())
+    /// The block at `number`: the announced block itself without confirmations, else fetched
+    async fn confirmed_block(&self, announced: Block<H256>, number: u64) -> Result<Block<H256>> {
+        if announced.number.is_some_and(|n| n.as_u64() == number) {
+            return Ok(announced);
+        }
+        observe_rpc("eth_getBlockByNumber", self.provider.get_block(number))
             .await
-            .map_err(IndexerError::Ethereum)?;
+            .map_err(IndexerError::Ethereum)?
+            .ok_or_else(|| IndexerError::NotFound(format!("{} block {} not found", self.name, number)))
+    }
+
+    /// Indexes blocks `from..=to` again from the log archive, without fetching anything from the node.
+    /// Transfers already stored are skipped; blocks that were never archived are counted and left out.
+    pub async fn replay(&self, from: u64, to: u64) -> Result<()> {
+        info!("📼 Replaying archived {} blocks {} to {}", self.name, from, to);
+        let (mut replayed, mut missing, mut failed) = (0, 0, 0);
+        
+        let mut start = from as i64;
+        while start <= to as i64 {
+            let end = (start + REPLAY_BATCH_BLOCKS - 1).min(to as i64);
+            let blocks = archive::load_blocks(&mut self.pool.get()?, self.chain_id, start, end)?;
+            missing += (end - start + 1) as usize - blocks.len();
             
-        if let Some(block_with_txs) = block_with_txs {
-            for tx in block_with_txs.transactions {
-                // Check if this transaction interacted with POL token
-                if let Some(to) = tx.to {
-                    if format_address(&to) == pol_contract.to_lowercase() {
-                        if let Err(e) = self.process_pol_transaction(&tx, block_number, pool).await {
-                            warn!("⚠️ Error processing POL transaction {}: {}", tx.hash, e);
-                        }
+            for (block, logs) in blocks {
+                let number = block.block_number as u64;
+                let block_hash = H256::from_slice(&block.block_hash);
+                let result = match self.decode_logs(&logs, number, block.block_timestamp) {
+                    Ok(decoded) => self.commit_block(number, block_hash, block.block_timestamp.and_utc(), decoded, None, None).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => replayed += 1,
+                    Err(e) => {
+                        error!("❌ Error replaying {} block {}: {}", self.name, number, e);
+                        failed += 1;
                     }
                 }
             }
+            info!("📼 Replayed {} {} blocks up to {}", replayed, self.name, end);
+            start = end + 1;
+            // Lets a shutdown signal in between batches
+            tokio::task::yield_now().await;
         }
         
+        if missing > 0 {
+            warn!("⚠️ {} blocks in the range aren't archived; backfill them from the node", missing);
+        }
+        if failed > 0 {
+            warn!("⚠️ Replay finished with {} failed blocks", failed);
+        } else {
+            info!("✅ Replayed {} archived blocks", replayed);
+        }
         Ok(())
     }
 
-    async fn process_pol_transaction(&self, tx: &Transaction, block_number: u64, pool: &DbPool) -> Result<()> {
+    /// Indexes blocks `from..=to` once, without subscribing to new blocks or moving the checkpoint
+    pub async fn backfill(&self, from: u64, to: u64) -> Result<()> {
+        info!("⏪ Backfilling {} blocks {} to {}", self.name, from, to);
+        let mut failed = 0;
+
+        for number in from..=to {
+            let block = observe_rpc("eth_getBlockByNumber", self.provider.get_block(number))
+                .await
+                .map_err(IndexerError::Ethereum)?
+                .ok_or_else(|| IndexerError::NotFound(format!("{} block {} not found", self.name, number)))?;
+
+            if let Err(e) = self.process_block(&block, false).await {
+                error!("❌ Error processing {} block {}: {}", self.name, number, e);
+                failed += 1;
+            }
+
+            if (number - from + 1).is_multiple_of(100) {
+                info!("⏪ Backfilled {} of {} blocks", number - from + 1, to - from + 1);
+            }
+        }
+
+        if failed > 0 {
+            warn!("⚠️ Backfill finished with {} failed blocks", failed);
+        } else {
+            info!("✅ Backfilled {} blocks", to - from + 1);
+        }
+        Ok(())
+    }
+
+    /// Fetches a block's token transfers, then stores them with their net flow
+    /// changes (and, for live blocks, the checkpoint) in one transaction.
+    ///
+    /// All network calls happen before the transaction, which runs to the end in a
+    /// blocking task once started, so a cancelled or failed block leaves nothing
+    /// half-written. Events are published only once the block is committed.
+    async fn process_block(&self, block: &Block<H256>, checkpoint: bool) -> Result<()> {
+        let block_number = block.number.unwrap_or_default().as_u64();
+        debug!("🔍 Processing {} block: {}", self.name, block_number);
+        
+        let block_hash = block
+            .hash
+            .ok_or_else(|| IndexerError::Web3(format!("Block {} has no hash", block_number)))?;
+        let block_time = DateTime::<Utc>::from_timestamp(block.timestamp.as_u64() as i64, 0).unwrap_or_default();
+        
+        let (decoded, archive) = if self.archive_logs {
+            // Every log of the block is archived, and holds the transfers too, so no receipts are needed
+            let filter = Filter::new().at_block_hash(block_hash);
+            let logs = observe_rpc("eth_getLogs", self.provider.get_logs(&filter))
+                .await
+                .map_err(IndexerError::Ethereum)?;
+            let decoded = self.decode_logs(&logs, block_number, block_time.naive_utc())?;
+            let archive = BlockLogs::new(self.chain_id, block_number as i64, block_hash, block_time.naive_utc(), &logs);
+            (decoded, Some(archive))
+        } else {
+            // Find the transactions that moved an indexed token. Routers and other contracts
+            // move tokens too, so the transaction's recipient isn't enough to go by.
+            let filter = Filter::new()
+                .at_block_hash(block_hash)
+                .address(self.token_addresses.clone())
+                .topic0(transfer_topic());
+            let logs = observe_rpc("eth_getLogs", self.provider.get_logs(&filter))
+                .await
+                .map_err(IndexerError::Ethereum)?;
+            // Logs come in block order, so a transaction's logs are adjacent
+            let mut tx_hashes: Vec<H256> = logs.iter().filter_map(|log| log.transaction_hash).collect();
+            tx_hashes.dedup();
+            
+            let mut decoded = Vec::new();
+            for tx_hash in tx_hashes {
+                decoded.extend(self.decode_transaction(tx_hash, block_number, block_time.naive_utc()).await?);
+            }
+            (decoded, None)
+        };
+        
+        let checkpoint = if checkpoint {
+            Some(IndexerState {
+                chain_id: self.chain_id,
+                current_block: block_number as i64,
+                chain_head: *self.chain_head.read().await as i64,
+                is_running: *self.is_running.read().await,
+                last_block_at: current_timestamp(),
+                updated_at: current_timestamp(),
+            })
+        } else {
+            None
+        };
+        
+        self.commit_block(block_number, block_hash, block_time, decoded, archive, checkpoint).await
+    }
+
+    /// Stores a block's decoded transfers, its archived logs, the checkpoint, if any, and the
+    /// block's events in one transaction, then publishes the events.
+    ///
+    /// The transaction blocks on the database, so it runs in a blocking task rather than on the executor.
+    async fn commit_block(
+        &self,
+        block_number: u64,
+        block_hash: H256,
+        block_time: DateTime<Utc>,
+        decoded: Vec<DecodedTransfer>,
+        archive: Option<BlockLogs>,
+        checkpoint: Option<IndexerState>,
+    ) -> Result<()> {
+        let indexer = self.clone();
+        tokio::task::spawn_blocking(move || {
+            indexer.write_block(block_number, block_hash, block_time, decoded, archive.as_ref(), checkpoint.as_ref())
+        })
+        .await
+        .map_err(|e| IndexerError::Generic(format!("Block {} commit task failed: {}", block_number, e)))?
+    }
+
+    fn write_block(
+        &self,
+        block_number: u64,
+        block_hash: H256,
+        block_time: DateTime<Utc>,
+        decoded: Vec<DecodedTransfer>,
+        archive: Option<&BlockLogs>,
+        checkpoint: Option<&IndexerState>,
+    ) -> Result<()> {
+        let mut conn = self.pool.get()?;
+        let transfer_count = observe_db_write("store_block", || {
+            self.events.commit(&mut conn, |conn| {
+                if let Some(archive) = archive {
+                    archive.store(conn)?;
+                }
+                let (transfer_count, mut events) =
+                    self.store_block(block_number as i64, block_time.naive_utc(), decoded, checkpoint, conn)?;
+                events.push(Event::NewBlock(BlockSummary {
+                    chain_id: self.chain_id,
+                    number: block_number as i64,
+                    hash: format!("{:#x}", block_hash),
+                    timestamp: block_time,
+                    transfer_count,
+                }));
+                Ok((transfer_count, events))
+            })
+        })?;
+        
+        metrics().blocks_processed.with_label_values(&[&self.name]).inc();
+        metrics().transfers_stored.with_label_values(&[&self.name]).inc_by(transfer_count as u64);
+        
+        Ok(())
+    }
+
+    /// Decodes the receipt's Transfer logs emitted by indexed tokens, without writing anything
+    async fn decode_transaction(&self, tx_hash: H256, block_number: u64, block_time: NaiveDateTime) -> Result<Vec<DecodedTransfer>> {
         // Get transaction receipt to access logs
-        let receipt = self.provider
-            .get_transaction_receipt(tx.hash)
+        let receipt = observe_rpc("eth_getTransactionReceipt", self.provider.get_transaction_receipt(tx_hash))
             .await
             .map_err(IndexerError::Ethereum)?;
             
-        if let Some(receipt) = receipt {
-            for (log_index, log) in receipt.logs.iter().enumerate() {
-                // Check if this is a Transfer event (topic0 = Transfer event signature)
-                if log.topics.len() >= 3 && 
-                   log.topics[0] == H256::from_slice(&keccak256("Transfer(address,address,uint256)")) {
-                    
-                    let from = Address::from(log.topics[1]);
-                    let to = Address::from(log.topics[2]);
-                    let value = U256::from_big_endian(&log.data);
-                    
-                    let from_str = format_address(&from);
-                    let to_str = format_address(&to);
-                    
-                    // Check if this involves Binance
-                    let is_binance_related = is_binance_address(&from_str) || is_binance_address(&to_str);
-                    
-                    if is_binance_related {
-                        info!("💰 Binance-related POL transfer detected: {} -> {} ({})", 
-                              from_str, to_str, wei_to_ether(value));
-                    }
-                    
-                    // Store the transfer
-                    let transfer = Transfer {
-                        id: generate_transfer_id(&format!("{:#x}", tx.hash), log_index),
+        match receipt {
+            Some(receipt) => self.decode_logs(&receipt.logs, block_number, block_time),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Decodes the Transfer logs emitted by indexed tokens among logs in block order, such as a
+    /// receipt's or a whole block's. Transfer ids number logs by their position in the block.
+    fn decode_logs(&self, logs: &[Log], block_number: u64, block_time: NaiveDateTime) -> Result<Vec<DecodedTransfer>> {
+        let mut transfers = Vec::new();
+        let mut previous_tx: Option<H256> = None;
+        let mut position = 0;
+        
+        for log in logs {
+            let tx_hash = log.transaction_hash.unwrap_or_default();
+            if previous_tx == Some(tx_hash) {
+                position += 1;
+            } else {
+                previous_tx = Some(tx_hash);
+                position = 0;
+            }
+            
+            // Only ERC-20 Transfer events (three topics, amount in data) from indexed tokens
+            let Some(token) = self.tokens.get(&format_address(&log.address)) else {
+                continue;
+            };
+            if log.topics.len() == 3 && log.topics[0] == transfer_topic() && log.data.len() == 32 {
+                let (Some(log_index), Some(transaction_index)) = (log.log_index, log.transaction_index) else {
+                    return Err(IndexerError::Web3(format!(
+                        "A log of transaction {:#x} in block {} has no log or transaction index",
+                        tx_hash, block_number
+                    )));
+                };
+                
+                let from = Address::from(log.topics[1]);
+                let to = Address::from(log.topics[2]);
+                let value = to_token_units(U256::from_big_endian(&log.data), token.decimals as u32);
+                
+                let from_str = format_address(&from);
+                let to_str = format_address(&to);
+                
+                // Check if this involves Binance
+                let is_binance_related = self.labels.is_binance(self.chain_id, &from_str)
+                    || self.labels.is_binance(self.chain_id, &to_str);
+                
+                if is_binance_related {
+                    info!("💰 Binance-related {} transfer detected on {}: {} -> {} ({})", 
+                          token.symbol, self.name, from_str, to_str, value);
+                }
+                
+                // Check if this touches a watched address
+                let is_watched = self.watchlists.is_watched(&from_str) || self.watchlists.is_watched(&to_str);
+                
+                let tx_hash = format!("{:#x}", tx_hash);
+                transfers.push(DecodedTransfer {
+                    legacy_id: generate_transfer_id(self.chain_id, &tx_hash, position),
+                    transfer: Transfer {
+                        id: generate_transfer_id(self.chain_id, &tx_hash, log_index.as_usize()),
+                        chain_id: self.chain_id,
                         block_number: block_number as i64,
-                        transaction_hash: format!("{:#x}", tx.hash),
-                        from_address: from_str.clone(),
-                        to_address: to_str.clone(),
-                        value: bigdecimal_to_string(&wei_to_ether(value)),
-                        timestamp: current_timestamp(),
+                        transaction_hash: tx_hash,
+                        from_address: from_str,
+                        to_address: to_str,
+                        token: token.address.clone(),
+                        value: bigdecimal_to_string(&value),
+                        timestamp: block_time,
                         is_binance_related,
-                    };
-                    
-                    self.store_transfer(transfer, pool).await?;
-                    
-                    // Update net flows for Binance addresses
-                    if is_binance_related {
-                        self.update_net_flows(&from_str, &to_str, &wei_to_ether(value), pool).await?;
-                    }
-                }
+                        is_watched,
+                        log_index: Some(log_index.as_u64() as i64),
+                        transaction_index: Some(transaction_index.as_u64() as i64),
+                    },
+                });
             }
         }
         
-        Ok(())
+        Ok(transfers)
     }
 
-    async fn store_transfer(&self, transfer: Transfer, pool: &DbPool) -> Result<()> {
-        let mut conn = pool.get()?;
+    /// Writes a block's transfers, balances and net flows, plus the checkpoint, its indexed range
+    /// and any net flow snapshot due; runs inside the block's transaction.
+    ///
+    /// Transfers that are already stored are skipped, so replaying a block doesn't count them twice.
+    /// Ones stored before log indexes were recorded are moved to their new id, also without
+    /// counting them again. Returns how many were stored and the events to publish after commit.
+    fn store_block(
+        &self,
+        block_number: i64,
+        block_time: NaiveDateTime,
+        mut transfers: Vec<DecodedTransfer>,
+        checkpoint: Option<&IndexerState>,
+        conn: &mut SqliteConnection,
+    ) -> Result<(usize, Vec<Event>)> {
+        let mut events = Vec::new();
+        let mut stored = 0;
+        
+        // Labels a `rebuild` published while the block was decoded apply to it, since the
+        // rebuild only reclassifies blocks stored before it published them
+        if self.labels.refresh_chain(conn, self.chain_id)? {
+            info!("🏷️ Switched {} to newly published labels", self.name);
+        }
+        for DecodedTransfer { transfer, .. } in &mut transfers {
+            transfer.is_binance_related = self.labels.is_binance(self.chain_id, &transfer.from_address)
+                || self.labels.is_binance(self.chain_id, &transfer.to_address);
+        }
+        
+        // A legacy id may be another log's new id, so all of the block's legacy rows and their
+        // alert firings are looked up before any row is moved
+        let legacy: HashSet<String> = transfers::table
+            .filter(transfers::chain_id.eq(self.chain_id))
+            .filter(transfers::block_number.eq(block_number))
+            .filter(transfers::log_index.is_null())
+            .select(transfers::id)
+            .load::<String>(conn)?
+            .into_iter()
+            .collect();
+        let mut rekeyed: HashMap<String, Vec<String>> = HashMap::new();
+        for decoded in transfers.iter().filter(|decoded| legacy.contains(&decoded.legacy_id)) {
+            let firings: Vec<String> = alert_firings::table
+                .filter(alert_firings::transfer_id.eq(&decoded.legacy_id))
+                .select(alert_firings::id)
+                .load(conn)?;
+            rekeyed.insert(decoded.legacy_id.clone(), firings);
+        }
+        if !rekeyed.is_empty() {
+            diesel::delete(transfers::table.filter(transfers::id.eq_any(rekeyed.keys()))).execute(conn)?;
+        }
         
-        diesel::insert_into(transfers::table)
-            .values(&transfer)
-            .execute(&mut conn)?;
+        for DecodedTransfer { transfer, legacy_id } in transfers {
+            let inserted = diesel::insert_or_ignore_into(transfers::table)
+                .values(&transfer)
+                .execute(conn)?;
+            if let Some(firings) = rekeyed.get(&legacy_id) {
+                diesel::update(alert_firings::table.filter(alert_firings::id.eq_any(firings)))
+                    .set(alert_firings::transfer_id.eq(&transfer.id))
+                    .execute(conn)?;
+                if legacy_id != transfer.id {
+                    debug!("🔑 Transfer {} is now {}", legacy_id, transfer.id);
+                }
+                continue;
+            }
+            if inserted == 0 {
+                debug!("⏭️ Transfer {} already stored", transfer.id);
+                continue;
+            }
+            stored += 1;
+            balances::apply_transfer(&transfer, conn)?;
             
-        Ok(())
+            // Update net flows for labelled and watched addresses
+            let value = string_to_bigdecimal(&transfer.value);
+            let is_tracked = self.is_tracked(&transfer.from_address) || self.is_tracked(&transfer.to_address);
+            let flows = if is_tracked {
+                self.update_net_flows(&transfer.from_address, &transfer.to_address, &transfer.token, &value, conn)?
+            } else {
+                Vec::new()
+            };
+            
+            let watched_by = self.watchlists.lists_for(&[&transfer.from_address, &transfer.to_address]);
+            events.push(Event::Transfer(transfer.clone()));
+            if transfer.is_watched {
+                events.push(Event::WatchedTransfer(WatchedTransfer {
+                    transfer,
+                    watchlists: watched_by,
+                }));
+            }
+            events.extend(flows.into_iter().map(|flow| Event::NetFlowUpdate(NetFlowData::new(flow, &self.labels))));
+        }
+        
+        // Snapshots from this block on no longer match the log, e.g. when backfilling below them
+        if stored > 0 {
+            history::invalidate_snapshots(conn, self.chain_id, block_number)?;
+        }
+        if (block_number as u64).is_multiple_of(self.snapshot_interval) {
+            let mut addresses = self.labels.addresses(Some(self.chain_id));
+            addresses.extend(self.watchlists.addresses());
+            let tokens: Vec<String> = self.tokens.keys().cloned().collect();
+            let rows = history::take_snapshot(conn, self.chain_id, block_number, block_time, &addresses, &tokens)?;
+            debug!("📸 Snapshotted {} {} net flows at block {}", rows, self.name, block_number);
+        }
+        
+        coverage::record_block(conn, self.chain_id, block_number)?;
+        if let Some(checkpoint) = checkpoint {
+            diesel::replace_into(indexer_state::table)
+                .values(checkpoint)
+                .execute(conn)?;
+        }
+        
+        Ok((stored, events))
     }
 
-    async fn update_net_flows(&self, from: &str, to: &str, value: &BigDecimal, pool: &DbPool) -> Result<()> {
-        let mut conn = pool.get()?;
+    fn update_net_flows(&self, from: &str, to: &str, token_address: &str, value: &BigDecimal, conn: &mut SqliteConnection) -> Result<Vec<NetFlow>> {
+        let mut updated = Vec::new();
         
         // Update sender (outflow)
-        if is_binance_address(from) {
-            self.update_address_flow(from, value, true, &mut conn).await?;
+        if self.is_tracked(from) {
+            updated.push(self.update_address_flow(from, token_address, value, true, conn)?);
         }
         
         // Update receiver (inflow) 
-        if is_binance_address(to) {
-            self.update_address_flow(to, value, false, &mut conn).await?;
+        if self.is_tracked(to) {
+            updated.push(self.update_address_flow(to, token_address, value, false, conn)?);
         }
         
-        Ok(())
+        Ok(updated)
+    }
+
+    /// Addresses whose net flow is kept: labelled wallets, such as exchanges, and watched wallets
+    fn is_tracked(&self, addr: &str) -> bool {
+        self.labels.entity_of(self.chain_id, addr).is_some() || self.watchlists.is_watched(addr)
     }
 
-    async fn update_address_flow(&self, addr: &str, value: &BigDecimal, is_outflow: bool, conn: &mut diesel::r2d2::PooledConnection<diesel::r2d2::ConnectionManager<diesel::SqliteConnection>>) -> Result<()> {
+    /// Applies one transfer to an address's running totals for the token and returns the updated row
+    fn update_address_flow(&self, addr: &str, token_address: &str, value: &BigDecimal, is_outflow: bool, conn: &mut SqliteConnection) -> Result<NetFlow> {
         use crate::schema::net_flows::dsl::*;
         
         // Try to get existing record
         let existing: Option<NetFlow> = net_flows
-            .filter(address.eq(addr))
+            .find((self.chain_id, addr, token_address))
             .first(conn)
             .optional()?;
             
@@ -220,7 +663,7 @@ impl PolygonIndexer {
             flow.transfer_count += 1;
             flow.last_updated = current_timestamp();
             
-            diesel::update(net_flows.filter(address.eq(addr)))
+            diesel::update(net_flows.find((self.chain_id, addr, token_address)))
                 .set((
                     net_flow.eq(&flow.net_flow),
                     inflow.eq(&flow.inflow),
@@ -229,6 +672,8 @@ impl PolygonIndexer {
                     last_updated.eq(flow.last_updated),
                 ))
                 .execute(conn)?;
+            
+            Ok(flow)
         } else {
             // Create new record
             let (new_inflow, new_outflow, new_net_flow) = if is_outflow {
@@ -238,7 +683,9 @@ impl PolygonIndexer {
             };
             
             let new_flow = NetFlow {
+                chain_id: self.chain_id,
                 address: addr.to_string(),
+                token: token_address.to_string(),
                 net_flow: new_net_flow,
                 inflow: new_inflow,
                 outflow: new_outflow,
@@ -249,24 +696,475 @@ impl PolygonIndexer {
             diesel::insert_into(net_flows)
                 .values(&new_flow)
                 .execute(conn)?;
+            
+            Ok(new_flow)
         }
-        
-        Ok(())
     }
 
+    /// Asks `start` to return after the block it is processing, if any
     pub async fn stop(&self) -> Result<()> {
         let mut running = self.is_running.write().await;
         *running = false;
-        info!("🛑 Stopping indexer...");
+        self.stop_requested.notify_one();
+        info!("🛑 Stopping {} indexer...", self.name);
+        Ok(())
+    }
+
+    /// Writes the current progress to `indexer_state`, where API nodes read it.
+    ///
+    /// Called on start and after every block, so `last_block_at` is when the
+    /// latest block (or the startup) was seen.
+    async fn record_progress(&self) -> Result<()> {
+        let now = current_timestamp();
+        let state = IndexerState {
+            chain_id: self.chain_id,
+            current_block: *self.current_block.read().await as i64,
+            chain_head: *self.chain_head.read().await as i64,
+            is_running: *self.is_running.read().await,
+            last_block_at: now,
+            updated_at: now,
+        };
+
+        let mut conn = self.pool.get()?;
+        observe_db_write("record_progress", || {
+            diesel::replace_into(indexer_state::table)
+                .values(&state)
+                .execute(&mut conn)
+        })?;
         Ok(())
     }
 
+    fn record_stopped(&self) {
+        let result = self.pool.get().map_err(IndexerError::from).and_then(|mut conn| {
+            diesel::update(indexer_state::table.find(self.chain_id))
+                .set((indexer_state::is_running.eq(false), indexer_state::updated_at.eq(current_timestamp())))
+                .execute(&mut conn)
+                .map_err(IndexerError::from)
+        });
+        if let Err(e) = result {
+            warn!("⚠️ Failed to record {} indexer stop: {}", self.name, e);
+        }
+    }
+
     pub async fn get_current_block(&self) -> u64 {
         *self.current_block.read().await
     }
+
+    /// Latest block number announced by the node
+    pub async fn get_chain_head(&self) -> u64 {
+        *self.chain_head.read().await
+    }
+
+    /// Time since the block subscription last delivered a block (or since startup)
+    pub async fn last_block_age(&self) -> Duration {
+        self.last_block_at.read().await.elapsed()
+    }
+
+    pub async fn is_running(&self) -> bool {
+        *self.is_running.read().await
+    }
+}
+
+/// topic0 of the ERC-20 `Transfer(address,address,uint256)` event
+fn transfer_topic() -> H256 {
+    H256::from(ethers::utils::keccak256("Transfer(address,address,uint256)".as_bytes()))
 }
 
-fn keccak256(data: &str) -> [u8; 32] {
-    use ethers::utils::keccak256 as k256;
-    k256(data.as_bytes())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::TokenConfig, database::test_pool, models::AlertFiring, schema::net_flows, tokens::POL_ADDRESS, utils::BINANCE_ADDRESSES};
+    use axum::{
+        extract::{ws::{Message, WebSocket, WebSocketUpgrade}, State},
+        response::Response,
+        routing::get,
+        Router,
+    };
+
+    const SENDER: &str = "0x000000000000000000000000000000000000000a";
+    const RECIPIENT: &str = "0x000000000000000000000000000000000000000b";
+
+    /// Local node answering the calls `ChainIndexer::new` and `start` make, as `chain_id` at block 100
+    async fn stand_in_node(chain_id: u64) -> String {
+        async fn upgrade(ws: WebSocketUpgrade, State(chain_id): State<u64>) -> Response {
+            ws.on_upgrade(move |socket| answer(socket, chain_id))
+        }
+        async fn answer(mut socket: WebSocket, chain_id: u64) {
+            while let Some(Ok(message)) = socket.recv().await {
+                let Message::Text(text) = message else { continue };
+                let request: serde_json::Value = serde_json::from_str(&text).unwrap();
+                let result = match request["method"].as_str() {
+                    Some("eth_chainId") => serde_json::json!(format!("{:#x}", chain_id)),
+                    Some("eth_blockNumber") => serde_json::json!("0x64"),
+                    Some("eth_subscribe") => serde_json::json!("0x1"),
+                    _ => serde_json::Value::Null,
+                };
+                let response = serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+                if socket.send(Message::Text(response.to_string())).await.is_err() {
+                    break;
+                }
+            }
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, Router::new().route("/", get(upgrade)).with_state(chain_id)).await });
+        url
+    }
+
+    async fn indexer(pool: &DbPool) -> ChainIndexer {
+        indexer_with(pool, ChainConfig::new("polygon", 137)).await
+    }
+
+    async fn indexer_with(pool: &DbPool, mut chain: ChainConfig) -> ChainIndexer {
+        chain.ws_url = stand_in_node(chain.chain_id).await;
+        let labels = Labels::from_chains(std::slice::from_ref(&chain));
+        ChainIndexer::new(chain, pool.clone(), EventBus::new(), WatchSet::load(pool).unwrap(), labels)
+            .await
+            .unwrap()
+    }
+
+    /// A log at `log_index` in the block, which is a POL Transfer unless `token` says otherwise
+    fn log(tx: u64, log_index: u64, token: &str, from: &str, to: &str, amount: u64) -> Log {
+        let mut data = [0u8; 32];
+        (U256::exp10(18) * amount).to_big_endian(&mut data);
+        Log {
+            address: token.parse().unwrap(),
+            topics: vec![
+                transfer_topic(),
+                H256::from(from.parse::<Address>().unwrap()),
+                H256::from(to.parse::<Address>().unwrap()),
+            ],
+            data: data.to_vec().into(),
+            transaction_hash: Some(H256::from_low_u64_be(tx)),
+            transaction_index: Some(U64::from(tx)),
+            log_index: Some(U256::from(log_index)),
+            ..Default::default()
+        }
+    }
+
+    /// Stores a decoded transfer as it was before log indexes were recorded
+    fn store_legacy(pool: &DbPool, decoded: &DecodedTransfer) {
+        let transfer = Transfer {
+            id: decoded.legacy_id.clone(),
+            log_index: None,
+            transaction_index: None,
+            ..decoded.transfer.clone()
+        };
+        diesel::insert_into(transfers::table).values(&transfer).execute(&mut pool.get().unwrap()).unwrap();
+    }
+
+    fn fire(pool: &DbPool, id: &str, transfer_id: &str) {
+        let firing = AlertFiring {
+            id: id.to_string(),
+            rule_name: "large".to_string(),
+            dedup_key: id.to_string(),
+            summary: String::new(),
+            payload: "{}".to_string(),
+            transfer_id: transfer_id.to_string(),
+            fired_at: current_timestamp(),
+            chain_id: 137,
+        };
+        diesel::insert_into(alert_firings::table).values(&firing).execute(&mut pool.get().unwrap()).unwrap();
+    }
+
+    fn firing_transfer(pool: &DbPool, id: &str) -> String {
+        alert_firings::table
+            .find(id)
+            .select(alert_firings::transfer_id)
+            .first(&mut pool.get().unwrap())
+            .unwrap()
+    }
+
+    /// Commits block 50 and returns how many transfers its `NewBlock` event counted
+    async fn commit(indexer: &ChainIndexer, decoded: Vec<DecodedTransfer>) -> usize {
+        let mut events = indexer.events.subscribe();
+        indexer.commit_block(50, H256::zero(), Utc::now(), decoded, None, None).await.unwrap();
+        loop {
+            if let Event::NewBlock(block) = events.try_recv().unwrap().event {
+                return block.transfer_count;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_transfers_move_to_their_log_index_ids_once() {
+        let (_dir, pool) = test_pool();
+        let indexer = indexer(&pool).await;
+        let exchange = BINANCE_ADDRESSES[0];
+        let other_token = "0x00000000000000000000000000000000000000ff";
+
+        // Transaction 2 logs at 1 and 2, after another token's log; its first log's new id is
+        // its second log's legacy id
+        let logs = [
+            log(1, 0, other_token, SENDER, RECIPIENT, 9),
+            log(2, 1, POL_ADDRESS, SENDER, exchange, 1),
+            log(2, 2, POL_ADDRESS, exchange, RECIPIENT, 2),
+            log(3, 3, POL_ADDRESS, RECIPIENT, exchange, 4),
+        ];
+        let decoded = indexer.decode_logs(&logs, 50, current_timestamp()).unwrap();
+        let tx = format!("{:#x}", H256::from_low_u64_be(2));
+        let ids: Vec<_> = decoded.iter().map(|d| (d.legacy_id.clone(), d.transfer.id.clone())).collect();
+        assert_eq!(ids[..2], [
+            (format!("137_{}_0", tx), format!("137_{}_1", tx)),
+            (format!("137_{}_1", tx), format!("137_{}_2", tx)),
+        ]);
+
+        // Transaction 2 was indexed before log indexes were recorded and fired alerts
+        store_legacy(&pool, &decoded[0]);
+        store_legacy(&pool, &decoded[1]);
+        fire(&pool, "first", &decoded[0].legacy_id);
+        fire(&pool, "second", &decoded[1].legacy_id);
+
+        // Only transaction 3 is new
+        assert_eq!(commit(&indexer, decoded).await, 1);
+        let stored: Vec<(String, Option<i64>)> = transfers::table
+            .order(transfers::log_index.asc())
+            .select((transfers::id, transfers::log_index))
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        let expected: Vec<_> = logs[1..].iter().map(|log| {
+            let index = log.log_index.unwrap().as_u64();
+            (format!("137_{:#x}_{}", log.transaction_hash.unwrap(), index), Some(index as i64))
+        }).collect();
+        assert_eq!(stored, expected);
+        assert_eq!(firing_transfer(&pool, "first"), expected[0].0);
+        assert_eq!(firing_transfer(&pool, "second"), expected[1].0);
+
+        // The moved transfers aren't counted into net flows or balances again
+        let flow: NetFlow = net_flows::table
+            .find((137, exchange, POL_ADDRESS))
+            .first(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!((flow.transfer_count, string_to_bigdecimal(&flow.inflow)), (1, BigDecimal::from(4)));
+        let holders: i64 = crate::schema::balances::table.count().get_result(&mut pool.get().unwrap()).unwrap();
+        assert_eq!(holders, 2);
+
+        // Replaying the block changes nothing
+        let decoded = indexer.decode_logs(&logs, 50, current_timestamp()).unwrap();
+        assert_eq!(commit(&indexer, decoded).await, 0);
+        let count: i64 = transfers::table.count().get_result(&mut pool.get().unwrap()).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(firing_transfer(&pool, "second"), expected[1].0);
+    }
+
+    /// Polygon with only `SENDER` labelled, as `rebuild` publishes it after a label change
+    fn relabelled() -> ChainConfig {
+        ChainConfig {
+            labels: [(SENDER.to_string(), "Binance".to_string())].into(),
+            ..ChainConfig::new("polygon", 137)
+        }
+    }
+
+    #[tokio::test]
+    async fn published_labels_apply_from_the_next_block() {
+        let (_dir, pool) = test_pool();
+        let indexer = indexer(&pool).await;
+        let exchange = BINANCE_ADDRESSES[0];
+        let logs = [log(1, 0, POL_ADDRESS, SENDER, RECIPIENT, 1), log(2, 1, POL_ADDRESS, RECIPIENT, exchange, 2)];
+        // Decoded with the labels in effect before the rebuild
+        let decoded = indexer.decode_logs(&logs, 50, current_timestamp()).unwrap();
+        assert_eq!(decoded.iter().map(|d| d.transfer.is_binance_related).collect::<Vec<_>>(), [false, true]);
+
+        assert!(Labels::publish(&pool, &relabelled()).unwrap());
+        assert_eq!(commit(&indexer, decoded).await, 2);
+
+        let flags: Vec<bool> = transfers::table
+            .order(transfers::log_index.asc())
+            .select(transfers::is_binance_related)
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(flags, [true, false]);
+        let tracked: Vec<String> = net_flows::table.select(net_flows::address).load(&mut pool.get().unwrap()).unwrap();
+        assert_eq!(tracked, [SENDER]);
+    }
+
+    #[tokio::test]
+    async fn indexers_keep_the_published_labels_on_start() {
+        let (_dir, pool) = test_pool();
+        // The first start publishes the configured labels
+        let first = indexer(&pool).await;
+        assert!(first.labels.is_binance(137, BINANCE_ADDRESSES[0]));
+
+        // After a rebuild, indexers and backfills still configured the old way follow it
+        assert!(Labels::publish(&pool, &relabelled()).unwrap());
+        let restarted = indexer(&pool).await;
+        assert!(restarted.labels.is_binance(137, SENDER));
+        assert!(!restarted.labels.is_binance(137, BINANCE_ADDRESSES[0]));
+        assert!(!restarted.labels.follows_config(137));
+
+        let stored: Vec<String> = crate::schema::labels::table
+            .select(crate::schema::labels::address)
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(stored, [SENDER]);
+
+        // Configured like the published labels, it follows its configuration
+        assert!(indexer_with(&pool, relabelled()).await.labels.follows_config(137));
+    }
+
+    #[tokio::test]
+    async fn replay_indexes_archived_blocks_once() {
+        let (_dir, pool) = test_pool();
+        let indexer = indexer(&pool).await;
+        let (exchange, other_token) = (BINANCE_ADDRESSES[0], "0x00000000000000000000000000000000000000ff");
+        // Block 61 was never archived
+        for (block, logs) in [
+            (60, vec![log(1, 0, POL_ADDRESS, SENDER, exchange, 1), log(1, 1, other_token, SENDER, exchange, 9)]),
+            (62, vec![log(2, 0, POL_ADDRESS, exchange, SENDER, 2), log(3, 1, POL_ADDRESS, SENDER, exchange, 4)]),
+        ] {
+            let archive = BlockLogs::new(137, block, H256::from_low_u64_be(block as u64), current_timestamp(), &logs);
+            archive.store(&mut pool.get().unwrap()).unwrap();
+        }
+
+        let flow = || -> String {
+            net_flows::table
+                .filter(net_flows::address.eq(exchange))
+                .select(net_flows::net_flow)
+                .first(&mut pool.get().unwrap())
+                .unwrap()
+        };
+        indexer.replay(60, 62).await.unwrap();
+        let blocks: Vec<i64> = transfers::table
+            .select(transfers::block_number)
+            .order(transfers::id)
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(blocks, vec![60, 62, 62]);
+        assert_eq!(flow(), "3");
+
+        indexer.replay(60, 62).await.unwrap();
+        let stored: i64 = transfers::table.count().get_result(&mut pool.get().unwrap()).unwrap();
+        assert_eq!(stored, 3);
+        assert_eq!(flow(), "3");
+    }
+
+    #[tokio::test]
+    async fn a_block_that_fails_to_commit_leaves_nothing_behind() {
+        let (_dir, pool) = test_pool();
+        let indexer = indexer(&pool).await;
+        let logs = [log(1, 0, POL_ADDRESS, SENDER, BINANCE_ADDRESSES[0], 1)];
+        let checkpoint = |current_block| IndexerState {
+            chain_id: 137,
+            current_block,
+            chain_head: 50,
+            is_running: true,
+            last_block_at: current_timestamp(),
+            updated_at: current_timestamp(),
+        };
+        let write = |checkpoint| {
+            let decoded = indexer.decode_logs(&logs, 50, current_timestamp()).unwrap();
+            let archive = BlockLogs::new(137, 50, H256::zero(), current_timestamp(), &logs);
+            indexer.commit_block(50, H256::zero(), Utc::now(), decoded, Some(archive), Some(checkpoint))
+        };
+        let count = |table: &str| -> i64 {
+            #[derive(QueryableByName)]
+            struct Count {
+                #[diesel(sql_type = diesel::sql_types::BigInt)]
+                rows: i64,
+            }
+            diesel::sql_query(format!("SELECT COUNT(*) AS rows FROM {}", table))
+                .get_result::<Count>(&mut pool.get().unwrap())
+                .unwrap()
+                .rows
+        };
+
+        // The checkpoint is the block's last write
+        diesel::sql_query("CREATE TRIGGER fail_checkpoint BEFORE INSERT ON indexer_state BEGIN SELECT RAISE(ABORT, 'disk full'); END")
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        let mut events = indexer.events.subscribe();
+        let error = write(checkpoint(50)).await.unwrap_err().to_string();
+        assert!(error.contains("disk full"), "{}", error);
+        for table in ["transfers", "net_flows", "balances", "archived_blocks", "raw_logs", "indexed_ranges", "event_outbox"] {
+            assert_eq!(count(table), 0, "{}", table);
+        }
+        assert!(events.try_recv().is_err());
+
+        diesel::sql_query("DROP TRIGGER fail_checkpoint").execute(&mut pool.get().unwrap()).unwrap();
+        write(checkpoint(50)).await.unwrap();
+        assert_eq!((count("transfers"), count("net_flows")), (1, 1));
+        let stored: IndexerState = indexer_state::table.find(137).first(&mut pool.get().unwrap()).unwrap();
+        assert_eq!(stored.current_block, 50);
+        assert!(matches!(events.try_recv().unwrap().event, Event::Transfer(_)));
+    }
+
+    #[tokio::test]
+    async fn stop_ends_start_and_records_it() {
+        let (_dir, pool) = test_pool();
+        let indexer = indexer(&pool).await;
+        let running = tokio::spawn({
+            let indexer = indexer.clone();
+            async move { indexer.start().await }
+        });
+        let is_running = || -> bool {
+            indexer_state::table
+                .find(137)
+                .select(indexer_state::is_running)
+                .first(&mut pool.get().unwrap())
+                .unwrap_or(false)
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !is_running() {
+            assert!(Instant::now() < deadline, "the indexer never started");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        indexer.stop().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+        assert!(!is_running());
+    }
+
+    #[tokio::test]
+    async fn each_chain_indexes_into_its_own_rows() {
+        let (_dir, pool) = test_pool();
+        let exchange = BINANCE_ADDRESSES[0];
+        let usdt = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+        let polygon = indexer(&pool).await;
+        let ethereum = indexer_with(&pool, ChainConfig {
+            tokens: vec![TokenConfig { address: usdt.to_string(), symbol: Some("USDT".to_string()), decimals: Some(6) }],
+            labels: [(exchange.to_string(), "binance".to_string())].into(),
+            ..ChainConfig::new("ethereum", 1)
+        })
+        .await;
+
+        // The same transaction hash and log index on both chains, each in its chain's token;
+        // a token another chain indexes is not picked up
+        let logs = [log(1, 0, POL_ADDRESS, SENDER, exchange, 1), log(1, 1, usdt, exchange, SENDER, 2)];
+        assert_eq!(commit(&polygon, polygon.decode_logs(&logs, 50, current_timestamp()).unwrap()).await, 1);
+        assert_eq!(commit(&ethereum, ethereum.decode_logs(&logs, 50, current_timestamp()).unwrap()).await, 1);
+
+        let stored: Vec<(i64, String, String)> = transfers::table
+            .order(transfers::chain_id.asc())
+            .select((transfers::chain_id, transfers::id, transfers::token))
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        let tx = format!("{:#x}", H256::from_low_u64_be(1));
+        assert_eq!(stored, [
+            (1, format!("1_{}_1", tx), usdt.to_string()),
+            (137, format!("137_{}_0", tx), POL_ADDRESS.to_string()),
+        ]);
+        let flows: Vec<(i64, String, String)> = net_flows::table
+            .filter(net_flows::address.eq(exchange))
+            .order(net_flows::chain_id.asc())
+            .select((net_flows::chain_id, net_flows::token, net_flows::net_flow))
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        // Each amount is scaled by its own token's decimals; `log` writes them with 18
+        assert_eq!(flows, [
+            (1, usdt.to_string(), "-2000000000000".to_string()),
+            (137, POL_ADDRESS.to_string(), "1".to_string()),
+        ]);
+
+        // Progress is kept per chain
+        for indexer in [&polygon, &ethereum] {
+            indexer.record_progress().await.unwrap();
+        }
+        let states: Vec<i64> = indexer_state::table
+            .order(indexer_state::chain_id.asc())
+            .select(indexer_state::chain_id)
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(states, [1, 137]);
+    }
 }
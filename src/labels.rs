@@ -0,0 +1,284 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use tokio::time::{interval, Duration};
+use tracing::{info, warn};
+
+use crate::{
+    config::ChainConfig,
+    database::DbPool,
+    models::Label,
+    schema::{labelled_chains, labels},
+    utils::current_timestamp,
+    Config, Result,
+};
+
+/// Entity whose addresses set `is_binance_related`
+pub const BINANCE: &str = "binance";
+
+/// Canonical form of an entity name, so `Binance` and `binance` are one entity
+pub fn normalize_entity(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Address -> entity, per chain id
+type ChainLabels = HashMap<i64, HashMap<String, String>>;
+
+/// Entity labels of known addresses (exchange wallets and the like), per chain.
+///
+/// Built from each chain's `labels`; the same address may be labelled on one
+/// chain and not on another. Entity names are normalized with `normalize_entity`.
+///
+/// A chain's labels are published to the `labels` table by its first indexer start and
+/// afterwards only by `rebuild`, which applies changed labels to history too; `reload`
+/// takes a chain's labels from the table once they are published, so every node follows
+/// the labels the indexer uses. Cloning is cheap; every clone sees the same reloads.
+#[derive(Clone, Default)]
+pub struct Labels {
+    /// Labels from the configuration, for chains that haven't published any
+    configured: Arc<ChainLabels>,
+    chains: Arc<RwLock<ChainLabels>>,
+    /// When the loaded labels of each published chain were published
+    published: Arc<RwLock<HashMap<i64, NaiveDateTime>>>,
+}
+
+impl Labels {
+    pub fn from_config(config: &Config) -> Self {
+        Self::from_chains(&config.chains)
+    }
+
+    pub fn from_chains(chains: &[ChainConfig]) -> Self {
+        let configured: ChainLabels = chains.iter().map(|chain| (chain.chain_id as i64, chain_labels(chain))).collect();
+        Self {
+            chains: Arc::new(RwLock::new(configured.clone())),
+            configured: Arc::new(configured),
+            published: Arc::default(),
+        }
+    }
+
+    /// Configured labels, replaced per chain by the published ones
+    pub fn load(config: &Config, pool: &DbPool) -> Result<Self> {
+        let labels = Self::from_config(config);
+        labels.reload(pool)?;
+        Ok(labels)
+    }
+
+    pub fn reload(&self, pool: &DbPool) -> Result<()> {
+        let mut conn = pool.get()?;
+        self.reload_from(&mut conn)
+    }
+
+    fn reload_from(&self, conn: &mut SqliteConnection) -> Result<()> {
+        let published_chains: Vec<(i64, NaiveDateTime)> = labelled_chains::table
+            .select((labelled_chains::chain_id, labelled_chains::published_at))
+            .load(conn)?;
+        let rows: Vec<Label> = labels::table.load(conn)?;
+        let mut chains = (*self.configured).clone();
+        let mut published: ChainLabels = published_chains.iter().map(|(chain_id, _)| (*chain_id, HashMap::new())).collect();
+        for row in rows {
+            published.entry(row.chain_id).or_default().insert(row.address, row.entity);
+        }
+        chains.extend(published);
+        *self.chains.write().unwrap() = chains;
+        *self.published.write().unwrap() = published_chains.into_iter().collect();
+        Ok(())
+    }
+
+    /// Reloads if `chain_id`'s labels were published again since they were loaded; returns
+    /// whether they were. Inside a block's transaction, this makes the block use the labels
+    /// of every `rebuild` that committed before it.
+    pub fn refresh_chain(&self, conn: &mut SqliteConnection, chain_id: i64) -> Result<bool> {
+        let published_at: Option<NaiveDateTime> = labelled_chains::table
+            .find(chain_id)
+            .select(labelled_chains::published_at)
+            .first(conn)
+            .optional()?;
+        if published_at.is_none() || published_at == self.published.read().unwrap().get(&chain_id).copied() {
+            return Ok(false);
+        }
+        self.reload_from(conn)?;
+        Ok(true)
+    }
+
+    /// Whether a chain's labels in use are its configured ones
+    pub fn follows_config(&self, chain_id: i64) -> bool {
+        self.chains.read().unwrap().get(&chain_id) == self.configured.get(&chain_id)
+    }
+
+    /// Reloads on a timer, to pick up labels published by `rebuild` or an indexer in another process
+    pub async fn refresh_every(self, pool: DbPool, period: Duration) {
+        let mut ticker = interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.reload(&pool) {
+                warn!("⚠️ Failed to reload labels: {}", e);
+            }
+        }
+    }
+
+    /// Replaces the published labels of a chain with its configured ones; returns whether they changed
+    pub fn publish(pool: &DbPool, chain: &ChainConfig) -> Result<bool> {
+        Self::publish_chain(pool, chain, true)
+    }
+
+    /// Publishes a chain's configured labels unless some are published already, which then
+    /// stay in force; returns whether it published
+    pub fn publish_initial(pool: &DbPool, chain: &ChainConfig) -> Result<bool> {
+        Self::publish_chain(pool, chain, false)
+    }
+
+    fn publish_chain(pool: &DbPool, chain: &ChainConfig, replace: bool) -> Result<bool> {
+        let chain_id = chain.chain_id as i64;
+        let wanted = chain_labels(chain);
+        let mut conn = pool.get()?;
+        conn.immediate_transaction(|conn| {
+            let published_at: Option<chrono::NaiveDateTime> = labelled_chains::table
+                .find(chain_id)
+                .select(labelled_chains::published_at)
+                .first(conn)
+                .optional()?;
+            let current: HashMap<String, String> = labels::table
+                .filter(labels::chain_id.eq(chain_id))
+                .select((labels::address, labels::entity))
+                .load::<(String, String)>(conn)?
+                .into_iter()
+                .collect();
+            if published_at.is_some() && (!replace || current == wanted) {
+                return Ok(false);
+            }
+
+            diesel::delete(labels::table.filter(labels::chain_id.eq(chain_id))).execute(conn)?;
+            let now = current_timestamp();
+            let rows: Vec<Label> = wanted
+                .into_iter()
+                .map(|(address, entity)| Label { chain_id, address, entity, updated_at: now })
+                .collect();
+            // SQLite limits the parameters per statement
+            for chunk in rows.chunks(500) {
+                diesel::insert_into(labels::table).values(chunk).execute(conn)?;
+            }
+            diesel::replace_into(labelled_chains::table)
+                .values((labelled_chains::chain_id.eq(chain_id), labelled_chains::published_at.eq(now)))
+                .execute(conn)?;
+            info!("🏷️ Published {} labels for {}", rows.len(), chain.name);
+            Ok(true)
+        })
+    }
+
+    /// Name of the exchange or entity that owns an address on a chain, if known
+    pub fn entity_of(&self, chain_id: i64, address: &str) -> Option<String> {
+        self.chains
+            .read()
+            .unwrap()
+            .get(&chain_id)?
+            .get(&address.to_lowercase())
+            .cloned()
+    }
+
+    /// Whether a transfer touching the address counts as Binance-related
+    pub fn is_binance(&self, chain_id: i64, address: &str) -> bool {
+        self.entity_of(chain_id, address).as_deref() == Some(BINANCE)
+    }
+
+    /// Labelled addresses on `chain_id`, or on any chain, without duplicates
+    pub fn addresses(&self, chain_id: Option<i64>) -> Vec<String> {
+        let mut addresses: Vec<String> = self
+            .chains
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| chain_id.is_none_or(|wanted| **id == wanted))
+            .flat_map(|(_, labels)| labels.keys().cloned())
+            .collect();
+        addresses.sort();
+        addresses.dedup();
+        addresses
+    }
+
+    /// `(chain_id, address)` pairs per entity, optionally on one chain only
+    pub fn entities(&self, chain_id: Option<i64>) -> BTreeMap<String, Vec<(i64, String)>> {
+        let mut entities: BTreeMap<String, Vec<(i64, String)>> = BTreeMap::new();
+        for (id, labels) in self.chains.read().unwrap().iter() {
+            if chain_id.is_some_and(|wanted| *id != wanted) {
+                continue;
+            }
+            for (address, entity) in labels {
+                entities.entry(entity.clone()).or_default().push((*id, address.clone()));
+            }
+        }
+        for addresses in entities.values_mut() {
+            addresses.sort();
+        }
+        entities
+    }
+}
+
+/// A chain's configured labels, with lowercase addresses and normalized entities
+fn chain_labels(chain: &ChainConfig) -> HashMap<String, String> {
+    chain
+        .labels
+        .iter()
+        .map(|(address, entity)| (address.to_lowercase(), normalize_entity(entity)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entity_names_are_case_insensitive() {
+        let mut chain = ChainConfig::new("polygon", 137);
+        chain.labels = [
+            ("0xAA00000000000000000000000000000000000001".to_string(), "Binance".to_string()),
+            ("0xaa00000000000000000000000000000000000002".to_string(), " BINANCE ".to_string()),
+            ("0xaa00000000000000000000000000000000000003".to_string(), "OKX".to_string()),
+        ]
+        .into();
+        let labels = Labels::from_chains(&[chain]);
+
+        assert_eq!(labels.entity_of(137, "0xaa00000000000000000000000000000000000001").as_deref(), Some("binance"));
+        assert!(labels.is_binance(137, "0xAA00000000000000000000000000000000000001"));
+        assert!(labels.is_binance(137, "0xaa00000000000000000000000000000000000002"));
+        assert!(!labels.is_binance(137, "0xaa00000000000000000000000000000000000003"));
+
+        let entities = labels.entities(None);
+        assert_eq!(entities.keys().collect::<Vec<_>>(), vec!["binance", "okx"]);
+        assert_eq!(entities["binance"].len(), 2);
+    }
+
+    #[test]
+    fn reload_follows_published_labels() {
+        let (_dir, pool) = crate::database::test_pool();
+        let exchange = "0xaa00000000000000000000000000000000000001";
+        let mut polygon = ChainConfig::new("polygon", 137);
+        polygon.labels = [(exchange.to_string(), "binance".to_string())].into();
+        let ethereum = ChainConfig::new("ethereum", 1);
+        let mut ethereum_labelled = ethereum.clone();
+        ethereum_labelled.labels = [(exchange.to_string(), "okx".to_string())].into();
+
+        // A node started with the old labels, before any were published
+        let labels = Labels::from_chains(&[polygon.clone(), ethereum_labelled]);
+        labels.reload(&pool).unwrap();
+        assert!(labels.is_binance(137, exchange));
+
+        // `rebuild` publishes the new Polygon labels
+        let mut relabelled = polygon.clone();
+        relabelled.labels = [(exchange.to_string(), "Kraken".to_string())].into();
+        assert!(Labels::publish(&pool, &relabelled).unwrap());
+        assert!(!Labels::publish(&pool, &relabelled).unwrap());
+
+        let clone = labels.clone();
+        labels.reload(&pool).unwrap();
+        assert_eq!(clone.entity_of(137, exchange).as_deref(), Some("kraken"));
+        assert!(!clone.is_binance(137, exchange));
+        // Chains without published labels keep the configured ones
+        assert_eq!(clone.entity_of(1, exchange).as_deref(), Some("okx"));
+
+        // Publishing an empty list unlabels the chain rather than falling back to the config
+        assert!(Labels::publish(&pool, &ChainConfig { labels: Default::default(), ..polygon }).unwrap());
+        labels.reload(&pool).unwrap();
+        assert_eq!(labels.entity_of(137, exchange), None);
+    }
+}
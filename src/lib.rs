@@ -1,12 +1,35 @@
+pub mod alerts;
+pub mod archive;
+pub mod auth;
+pub mod balances;
+pub mod chains;
+pub mod cli;
 pub mod config;
+pub mod coverage;
 pub mod database;
 pub mod error;
+pub mod events;
+pub mod export;
+pub mod graphql;
+pub mod health;
+pub mod history;
 pub mod indexer;
+pub mod labels;
+pub mod metrics;
 pub mod models;
+pub mod outbox;
+pub mod parquet;
+pub mod rebuild;
 pub mod schema;
 pub mod server;
+pub mod shutdown;
+pub mod subscriptions;
+pub mod tokens;
 pub mod types;
 pub mod utils;
+pub mod verify;
+pub mod watchlists;
+pub mod webhooks;
 
 pub use config::Config;
 pub use error::{IndexerError, Result};
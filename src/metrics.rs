@@ -0,0 +1,287 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    histogram_opts, opts, Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Registry,
+    TextEncoder,
+};
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::database::DbPool;
+
+/// Process-wide Prometheus collectors for the indexer and API server
+pub struct Metrics {
+    registry: Registry,
+    pub blocks_processed: IntCounterVec,
+    pub transfers_stored: IntCounterVec,
+    pub rpc_duration: HistogramVec,
+    pub rpc_errors: IntCounterVec,
+    pub chain_head: IntGaugeVec,
+    pub last_processed_block: IntGaugeVec,
+    pub head_lag: IntGaugeVec,
+    pub db_write_duration: HistogramVec,
+    pub db_pool_connections: IntGauge,
+    pub db_pool_idle: IntGauge,
+    pub db_pool_max: IntGauge,
+    pub ws_clients: IntGauge,
+    pub broadcast_lagged: IntCounterVec,
+    pub http_duration: HistogramVec,
+    pub webhook_deliveries: IntCounterVec,
+    pub alerts: IntCounterVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("polygon_indexer".to_string()), None)
+            .expect("valid registry prefix");
+
+        let metrics = Self {
+            blocks_processed: IntCounterVec::new(opts!("blocks_processed_total", "Blocks processed by the indexer, by chain"), &["chain"]).unwrap(),
+            transfers_stored: IntCounterVec::new(opts!("transfers_stored_total", "Transfers written to the database, by chain"), &["chain"]).unwrap(),
+            rpc_duration: HistogramVec::new(
+                histogram_opts!("rpc_duration_seconds", "RPC call latency by method"),
+                &["method"],
+            )
+            .unwrap(),
+            rpc_errors: IntCounterVec::new(opts!("rpc_errors_total", "Failed RPC calls by method"), &["method"]).unwrap(),
+            chain_head: IntGaugeVec::new(opts!("chain_head_block", "Latest block announced by the node, by chain"), &["chain"]).unwrap(),
+            last_processed_block: IntGaugeVec::new(opts!("last_processed_block", "Latest block fully processed, by chain"), &["chain"]).unwrap(),
+            head_lag: IntGaugeVec::new(
+                opts!("head_lag_blocks", "Blocks between the chain head and the last processed block, by chain"),
+                &["chain"],
+            )
+            .unwrap(),
+            db_write_duration: HistogramVec::new(
+                histogram_opts!("db_write_duration_seconds", "Database write latency by operation"),
+                &["operation"],
+            )
+            .unwrap(),
+            db_pool_connections: IntGauge::with_opts(opts!("db_pool_connections", "Open database connections")).unwrap(),
+            db_pool_idle: IntGauge::with_opts(opts!("db_pool_idle_connections", "Idle database connections")).unwrap(),
+            db_pool_max: IntGauge::with_opts(opts!("db_pool_max_connections", "Database pool size limit")).unwrap(),
+            ws_clients: IntGauge::with_opts(opts!("ws_clients", "Connected WebSocket clients")).unwrap(),
+            broadcast_lagged: IntCounterVec::new(
+                opts!("broadcast_lagged_events_total", "Events a slow client fell behind on, by transport"),
+                &["transport"],
+            )
+            .unwrap(),
+            http_duration: HistogramVec::new(
+                histogram_opts!("http_request_duration_seconds", "HTTP request latency by route"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            webhook_deliveries: IntCounterVec::new(
+                opts!("webhook_deliveries_total", "Alert webhooks by final outcome"),
+                &["outcome"],
+            )
+            .unwrap(),
+            alerts: IntCounterVec::new(
+                opts!("alerts_total", "Rule matches by rule and whether they fired or were suppressed"),
+                &["rule", "outcome"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.blocks_processed.clone())).unwrap();
+        registry.register(Box::new(metrics.transfers_stored.clone())).unwrap();
+        registry.register(Box::new(metrics.rpc_duration.clone())).unwrap();
+        registry.register(Box::new(metrics.rpc_errors.clone())).unwrap();
+        registry.register(Box::new(metrics.chain_head.clone())).unwrap();
+        registry.register(Box::new(metrics.last_processed_block.clone())).unwrap();
+        registry.register(Box::new(metrics.head_lag.clone())).unwrap();
+        registry.register(Box::new(metrics.db_write_duration.clone())).unwrap();
+        registry.register(Box::new(metrics.db_pool_connections.clone())).unwrap();
+        registry.register(Box::new(metrics.db_pool_idle.clone())).unwrap();
+        registry.register(Box::new(metrics.db_pool_max.clone())).unwrap();
+        registry.register(Box::new(metrics.ws_clients.clone())).unwrap();
+        registry.register(Box::new(metrics.broadcast_lagged.clone())).unwrap();
+        registry.register(Box::new(metrics.http_duration.clone())).unwrap();
+        registry.register(Box::new(metrics.webhook_deliveries.clone())).unwrap();
+        registry.register(Box::new(metrics.alerts.clone())).unwrap();
+
+        metrics
+    }
+
+    /// Records a chain's head and last processed block, and the lag between them
+    pub fn set_progress(&self, chain: &str, head: u64, processed: u64) {
+        self.chain_head.with_label_values(&[chain]).set(head as i64);
+        self.last_processed_block.with_label_values(&[chain]).set(processed as i64);
+        self.head_lag.with_label_values(&[chain]).set(head.saturating_sub(processed) as i64);
+    }
+
+    /// Renders every collector in the Prometheus text format, sampling pool usage first
+    pub fn render(&self, pool: &DbPool) -> String {
+        let state = pool.state();
+        self.db_pool_connections.set(i64::from(state.connections));
+        self.db_pool_idle.set(i64::from(state.idle_connections));
+        self.db_pool_max.set(i64::from(pool.max_size()));
+
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::warn!("⚠️ Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Times an RPC call and counts it as an error if it fails
+pub async fn observe_rpc<T, E, F>(method: &str, call: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let started = Instant::now();
+    let result = call.await;
+    let metrics = metrics();
+    metrics
+        .rpc_duration
+        .with_label_values(&[method])
+        .observe(started.elapsed().as_secs_f64());
+    if result.is_err() {
+        metrics.rpc_errors.with_label_values(&[method]).inc();
+    }
+    result
+}
+
+/// Times a synchronous database write
+pub fn observe_db_write<T>(operation: &str, write: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = write();
+    metrics()
+        .db_write_duration
+        .with_label_values(&[operation])
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
+
+/// Counts a WebSocket client for as long as it is alive
+pub struct WsClientGuard;
+
+impl WsClientGuard {
+    pub fn new() -> Self {
+        metrics().ws_clients.inc();
+        WsClientGuard
+    }
+}
+
+impl Default for WsClientGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for WsClientGuard {
+    fn drop(&mut self) {
+        metrics().ws_clients.dec();
+    }
+}
+
+/// Middleware recording request latency labelled by the matched route template
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    metrics()
+        .http_duration
+        .with_label_values(&[method.as_str(), route.as_str(), response.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_pool;
+    use axum::{middleware, routing::get, Router};
+
+    // Collectors are process-wide, so each test uses label values of its own
+
+    #[test]
+    fn every_collector_is_rendered_once_used() {
+        let (_dir, pool) = test_pool();
+        let metrics = metrics();
+        metrics.blocks_processed.with_label_values(&["render-test"]).inc();
+        metrics.transfers_stored.with_label_values(&["render-test"]).inc_by(3);
+        metrics.set_progress("render-test", 10, 10);
+        metrics.broadcast_lagged.with_label_values(&["render-test"]).inc();
+        metrics.webhook_deliveries.with_label_values(&["render-test"]).inc();
+        metrics.alerts.with_label_values(&["render-test", "fired"]).inc();
+        metrics.http_duration.with_label_values(&["GET", "/render-test", "200"]).observe(0.1);
+        observe_db_write("render-test", || ());
+
+        let text = metrics.render(&pool);
+        for name in [
+            "blocks_processed_total",
+            "transfers_stored_total",
+            "chain_head_block",
+            "last_processed_block",
+            "head_lag_blocks",
+            "db_write_duration_seconds",
+            "db_pool_connections",
+            "db_pool_idle_connections",
+            "db_pool_max_connections",
+            "ws_clients",
+            "broadcast_lagged_events_total",
+            "http_request_duration_seconds",
+            "webhook_deliveries_total",
+            "alerts_total",
+        ] {
+            assert!(text.contains(&format!("# TYPE polygon_indexer_{} ", name)), "{} missing", name);
+        }
+        assert!(text.contains("polygon_indexer_transfers_stored_total{chain=\"render-test\"} 3"));
+        assert!(text.contains("polygon_indexer_db_pool_max_connections 10"));
+    }
+
+    #[tokio::test]
+    async fn rpc_calls_are_timed_and_failures_counted() {
+        let metrics = metrics();
+        let ok: Result<u64, String> = observe_rpc("rpc-test-ok", async { Ok(1) }).await;
+        let failed: Result<u64, String> = observe_rpc("rpc-test-failed", async { Err("timeout".to_string()) }).await;
+        assert_eq!((ok, failed), (Ok(1), Err("timeout".to_string())));
+
+        assert_eq!(metrics.rpc_duration.with_label_values(&["rpc-test-ok"]).get_sample_count(), 1);
+        assert_eq!(metrics.rpc_duration.with_label_values(&["rpc-test-failed"]).get_sample_count(), 1);
+        assert_eq!(metrics.rpc_errors.with_label_values(&["rpc-test-ok"]).get(), 0);
+        assert_eq!(metrics.rpc_errors.with_label_values(&["rpc-test-failed"]).get(), 1);
+
+        metrics.set_progress("progress-test", 120, 100);
+        assert_eq!(metrics.head_lag.with_label_values(&["progress-test"]).get(), 20);
+        // A node reporting a head below the processed block isn't negative lag
+        metrics.set_progress("progress-test", 90, 100);
+        assert_eq!(metrics.head_lag.with_label_values(&["progress-test"]).get(), 0);
+    }
+
+    #[tokio::test]
+    async fn requests_are_labelled_by_route_template() {
+        let app = Router::new()
+            .route("/route-test/:id", get(|| async { "ok" }))
+            .layer(middleware::from_fn(track_http));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        for id in [1, 2] {
+            reqwest::get(format!("{}/route-test/{}", base, id)).await.unwrap();
+        }
+        let histogram = metrics().http_duration.with_label_values(&["GET", "/route-test/:id", "200"]);
+        assert_eq!(histogram.get_sample_count(), 2);
+    }
+}
@@ -1,24 +1,33 @@
-﻿use diesel::prelude::*;
+﻿use async_graphql::SimpleObject;
+use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use crate::schema::*;
 
-#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize, ToSchema, SimpleObject)]
 #[diesel(table_name = transfers)]
 pub struct Transfer {
     pub id: String,
+    pub chain_id: i64,
     pub block_number: i64,
     pub transaction_hash: String,
     pub from_address: String,
     pub to_address: String,
-    pub value: String, // Store as string for SQLite
+    pub token: String, // Token contract address
+    pub value: String, // Store as string for SQLite, in token units
     pub timestamp: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
     pub is_binance_related: bool,
+    pub is_watched: bool,
+    pub log_index: Option<i64>, // Position of the log in its block; None if stored before it was recorded
+    pub transaction_index: Option<i64>, // Position of the transaction in its block; likewise
 }
 
-#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize, SimpleObject)]
 #[diesel(table_name = net_flows)]
 pub struct NetFlow {
+    pub chain_id: i64,
     pub address: String,
+    pub token: String, // Token contract address
     pub net_flow: String, // Store as string for SQLite
     pub inflow: String,   // Store as string for SQLite
     pub outflow: String,  // Store as string for SQLite
@@ -37,26 +46,200 @@ pub struct SystemStat {
     pub last_updated: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
 }
 
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub key_hash: String,
+    pub name: String,
+    pub rate_limit_per_minute: i32,
+    pub max_connections: i32,
+    pub enabled: bool,
+    pub created_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub rule_name: String,
+    pub url: String,
+    pub payload: String,
+    pub status: String, // pending, delivered or failed
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+    pub updated_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = alert_firings)]
+pub struct AlertFiring {
+    pub id: String, // Same as the webhook delivery id
+    pub rule_name: String,
+    pub dedup_key: String,
+    pub summary: String,
+    pub payload: String,
+    pub transfer_id: String,
+    pub fired_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+    pub chain_id: i64,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize, ToSchema)]
+#[diesel(table_name = watchlists)]
+pub struct Watchlist {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+    pub updated_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize, ToSchema)]
+#[diesel(table_name = watchlist_addresses)]
+pub struct WatchlistAddress {
+    pub watchlist_id: String,
+    pub address: String,
+    pub label: Option<String>,
+    pub added_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = indexer_state)]
+pub struct IndexerState {
+    pub chain_id: i64,
+    pub current_block: i64,
+    pub chain_head: i64,
+    pub is_running: bool,
+    pub last_block_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+    pub updated_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize, ToSchema, SimpleObject)]
+#[diesel(table_name = tokens)]
+pub struct Token {
+    pub chain_id: i64,
+    pub address: String,
+    pub symbol: String,
+    pub decimals: i32,
+    pub updated_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize, ToSchema, SimpleObject)]
+#[diesel(table_name = balances)]
+pub struct Balance {
+    pub chain_id: i64,
+    pub address: String,
+    pub token: String, // Token contract address
+    pub balance: String, // Store as string for SQLite, in token units
+    pub transfer_count: i64,
+    pub seeded_block: Option<i64>,
+    pub last_block: i64,
+    pub last_updated: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = net_flow_snapshots)]
+pub struct NetFlowSnapshot {
+    pub chain_id: i64,
+    pub block_number: i64,
+    pub address: String,
+    pub token: String, // Token contract address
+    pub net_flow: String, // Store as string for SQLite
+    pub inflow: String,
+    pub outflow: String,
+    pub transfer_count: i64,
+    pub last_transfer_at: Option<chrono::NaiveDateTime>,
+    pub block_timestamp: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = indexed_ranges)]
+pub struct IndexedRange {
+    pub chain_id: i64,
+    pub start_block: i64,
+    pub end_block: i64,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = labels)]
+pub struct Label {
+    pub chain_id: i64,
+    pub address: String, // Lowercase
+    pub entity: String, // Normalized with `labels::normalize_entity`
+    pub updated_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = archived_blocks)]
+pub struct ArchivedBlock {
+    pub chain_id: i64,
+    pub block_number: i64,
+    pub block_hash: Vec<u8>,
+    pub block_timestamp: chrono::NaiveDateTime,
+    pub log_count: i64,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = raw_logs)]
+pub struct RawLog {
+    pub chain_id: i64,
+    pub block_number: i64,
+    pub log_index: i64,
+    pub transaction_index: i64,
+    pub transaction_hash: Vec<u8>,
+    pub address: Vec<u8>,
+    pub topics: Vec<u8>, // 32-byte topics back to back
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Queryable)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub kind: String,
+    pub payload: String, // JSON-encoded Event
+    pub created_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = event_outbox)]
+pub struct NewOutboxEvent {
+    pub kind: String,
+    pub payload: String,
+    pub created_at: chrono::NaiveDateTime, // Use NaiveDateTime for SQLite
+}
+
 impl Transfer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
+        chain_id: i64,
         block_number: i64,
         transaction_hash: String,
         from_address: String,
         to_address: String,
+        token: String,
         value: String,
         timestamp: chrono::NaiveDateTime,
         is_binance_related: bool,
+        is_watched: bool,
+        log_index: Option<i64>,
+        transaction_index: Option<i64>,
     ) -> Self {
         Self {
             id,
+            chain_id,
             block_number,
             transaction_hash,
             from_address,
             to_address,
+            token,
             value,
             timestamp,
             is_binance_related,
+            is_watched,
+            log_index,
+            transaction_index,
         }
     }
 }
@@ -0,0 +1,228 @@
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use tokio::time::{interval, Duration};
+use tracing::{debug, info, warn};
+
+use crate::{
+    database::DbPool,
+    events::{Event, EventBus, REPLAY_BUFFER_SIZE},
+    models::{NewOutboxEvent, OutboxEvent},
+    schema::event_outbox,
+    utils::current_timestamp,
+    Result,
+};
+
+// SQLite's record of the last id of each AUTOINCREMENT table
+diesel::table! {
+    sqlite_sequence (name) {
+        name -> Text,
+        seq -> BigInt,
+    }
+}
+
+/// Rows read per poll by a following API node
+const FOLLOW_BATCH_SIZE: i64 = 500;
+
+/// How often the indexer deletes expired outbox rows
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Hands live events from the indexer process to API nodes through the `event_outbox` table.
+///
+/// The indexer appends a block's events in the block's transaction; each API node
+/// polls for rows newer than the last one it saw and republishes them on its
+/// own `EventBus` under their row id, so WebSocket, SSE and GraphQL clients behave
+/// the same as when everything runs in one process, whichever node they reach.
+#[derive(Clone)]
+pub struct Outbox {
+    pool: DbPool,
+}
+
+impl Outbox {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Records an event and returns its id; runs inside the transaction that produced it
+    pub fn append(conn: &mut SqliteConnection, event: &Event) -> Result<u64> {
+        let row = NewOutboxEvent {
+            kind: event.kind().to_string(),
+            payload: serde_json::to_string(event)?,
+            created_at: current_timestamp(),
+        };
+
+        diesel::insert_into(event_outbox::table).values(&row).execute(conn)?;
+        let id: i64 = diesel::select(sql::<BigInt>("last_insert_rowid()")).get_result(conn)?;
+        Ok(id as u64)
+    }
+
+    /// Loads the newest outbox rows into the replay buffer of `events` and continues its ids
+    /// from the outbox's counter, so clients can resume across restarts of this process
+    pub fn restore(&self, events: &EventBus) -> Result<()> {
+        let mut conn = self.pool.get()?;
+        // AUTOINCREMENT keeps the last id handed out even after its row was pruned
+        let last_id: Option<i64> = sqlite_sequence::table
+            .filter(sqlite_sequence::name.eq("event_outbox"))
+            .select(sqlite_sequence::seq)
+            .first(&mut conn)
+            .optional()?;
+        let mut rows: Vec<OutboxEvent> = event_outbox::table
+            .order(event_outbox::id.desc())
+            .limit(REPLAY_BUFFER_SIZE as i64)
+            .load(&mut conn)?;
+        rows.reverse();
+
+        let restored = rows.into_iter().filter_map(|row| match serde_json::from_str::<Event>(&row.payload) {
+            Ok(event) => Some((row.id as u64, event)),
+            Err(e) => {
+                warn!("⚠️ Skipping unreadable outbox event #{} ({}): {}", row.id, row.kind, e);
+                None
+            }
+        });
+        events.restore(last_id.unwrap_or(0) as u64, restored);
+        Ok(())
+    }
+
+    /// Republishes new outbox rows on `events` until the task is dropped.
+    ///
+    /// Starts after the last event `events` has; clients that need older data use the REST API.
+    pub async fn follow(self, events: EventBus, poll_interval: Duration) {
+        let mut last_id = events.last_id() as i64;
+        info!("📡 Following indexer events from the database (after #{})", last_id);
+
+        let mut ticker = interval(poll_interval);
+        loop {
+            ticker.tick().await;
+
+            // Drain everything that arrived since the last poll
+            loop {
+                let rows = match self.read_after(last_id) {
+                    Ok(rows) => rows,
+                    Err(e) => {
+                        warn!("⚠️ Failed to poll event outbox: {}", e);
+                        break;
+                    }
+                };
+                let drained = (rows.len() as i64) < FOLLOW_BATCH_SIZE;
+
+                for row in rows {
+                    last_id = row.id;
+                    match serde_json::from_str::<Event>(&row.payload) {
+                        Ok(event) => events.relay(row.id as u64, event),
+                        Err(e) => warn!("⚠️ Skipping unreadable outbox event #{} ({}): {}", row.id, row.kind, e),
+                    }
+                }
+
+                if drained {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Deletes rows older than `retention`, once a minute, until the task is dropped
+    pub async fn prune(self, retention: Duration) {
+        let mut ticker = interval(PRUNE_INTERVAL);
+        loop {
+            ticker.tick().await;
+
+            let cutoff = current_timestamp() - chrono::Duration::from_std(retention).unwrap_or_default();
+            match self.delete_before(cutoff) {
+                Ok(0) => {}
+                Ok(count) => debug!("🧹 Pruned {} outbox events", count),
+                Err(e) => warn!("⚠️ Failed to prune event outbox: {}", e),
+            }
+        }
+    }
+
+    fn delete_before(&self, cutoff: chrono::NaiveDateTime) -> Result<usize> {
+        let mut conn = self.pool.get()?;
+        let deleted = diesel::delete(event_outbox::table.filter(event_outbox::created_at.lt(cutoff)))
+            .execute(&mut conn)?;
+        Ok(deleted)
+    }
+
+    fn read_after(&self, last_id: i64) -> Result<Vec<OutboxEvent>> {
+        let mut conn = self.pool.get()?;
+        let rows = event_outbox::table
+            .filter(event_outbox::id.gt(last_id))
+            .order(event_outbox::id.asc())
+            .limit(FOLLOW_BATCH_SIZE)
+            .load(&mut conn)?;
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::test_pool, types::BlockSummary};
+
+    fn new_block(number: i64) -> Event {
+        Event::NewBlock(BlockSummary {
+            chain_id: 137,
+            number,
+            hash: format!("{:#x}", number),
+            timestamp: chrono::DateTime::from_timestamp(1_700_000_000 + number, 0).unwrap(),
+            transfer_count: 0,
+        })
+    }
+
+    fn ids(events: &[crate::events::Sequenced]) -> Vec<u64> {
+        events.iter().map(|sequenced| sequenced.id).collect()
+    }
+
+    #[test]
+    fn restarted_node_resumes_where_the_last_one_stopped() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        let before = EventBus::new().with_outbox();
+        before.commit(&mut conn, |_| Ok(((), vec![new_block(1), new_block(2), new_block(3)]))).unwrap();
+
+        let after = EventBus::new().with_outbox();
+        Outbox::new(pool.clone()).restore(&after).unwrap();
+        assert_eq!(after.last_id(), 3);
+
+        let resume = after.resume(1);
+        assert!(resume.gap.is_none() && resume.reset.is_none());
+        assert_eq!(ids(&resume.backlog), vec![2, 3]);
+
+        // Numbering continues instead of restarting at 1
+        after.commit(&mut conn, |_| Ok(((), vec![new_block(4)]))).unwrap();
+        assert_eq!(after.last_id(), 4);
+    }
+
+    #[test]
+    fn pruned_events_are_reported_as_a_gap() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        let before = EventBus::new().with_outbox();
+        before.commit(&mut conn, |_| Ok(((), vec![new_block(1), new_block(2), new_block(3)]))).unwrap();
+        diesel::delete(event_outbox::table).execute(&mut conn).unwrap();
+
+        let after = EventBus::new();
+        Outbox::new(pool.clone()).restore(&after).unwrap();
+        assert_eq!(after.last_id(), 3);
+
+        let resume = after.resume(1);
+        let gap = resume.gap.expect("gap");
+        assert_eq!((gap.from, gap.to), (2, 3));
+        assert!(resume.backlog.is_empty());
+        assert!(after.resume(3).gap.is_none());
+    }
+
+    #[test]
+    fn unknown_id_is_answered_with_a_reset() {
+        let (_dir, pool) = test_pool();
+        let mut conn = pool.get().unwrap();
+        let bus = EventBus::new().with_outbox();
+        bus.commit(&mut conn, |_| Ok(((), vec![new_block(1), new_block(2)]))).unwrap();
+
+        // E.g. an id a client kept from before the database was reset
+        let resume = bus.resume(40);
+        let reset = resume.reset.expect("reset");
+        assert_eq!((reset.requested, reset.latest), (40, 2));
+        assert!(resume.gap.is_none());
+        assert!(resume.backlog.is_empty());
+    }
+}
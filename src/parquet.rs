@@ -0,0 +1,338 @@
+use chrono::NaiveDateTime;
+use parquet::basic::{LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::MilliSeconds;
+use parquet::schema::types::Type;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Rows per row group
+const ROW_GROUP_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Int64,
+    Boolean,
+    Utf8,
+    Timestamp,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnType,
+    pub nullable: bool,
+}
+
+impl Column {
+    pub const fn required(name: &'static str, kind: ColumnType) -> Self {
+        Self { name, kind, nullable: false }
+    }
+
+    pub const fn optional(name: &'static str, kind: ColumnType) -> Self {
+        Self { name, kind, nullable: true }
+    }
+
+    fn schema_type(&self) -> parquet::errors::Result<Type> {
+        let (physical, logical) = match self.kind {
+            ColumnType::Int64 => (PhysicalType::INT64, None),
+            ColumnType::Boolean => (PhysicalType::BOOLEAN, None),
+            ColumnType::Utf8 => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+            ColumnType::Timestamp => (
+                PhysicalType::INT64,
+                Some(LogicalType::Timestamp {
+                    is_adjusted_to_u_t_c: true,
+                    unit: TimeUnit::MILLIS(MilliSeconds {}),
+                }),
+            ),
+        };
+        Type::primitive_type_builder(self.name, physical)
+            .with_repetition(if self.nullable { Repetition::OPTIONAL } else { Repetition::REQUIRED })
+            .with_logical_type(logical)
+            .build()
+    }
+}
+
+/// One cell of a row; `Null` only in nullable columns
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    Int64(i64),
+    Boolean(bool),
+    Utf8(&'a str),
+    Timestamp(NaiveDateTime),
+    Null,
+}
+
+/// Present values of one column in the current row group
+enum ColumnValues {
+    Int64(Vec<i64>),
+    Boolean(Vec<bool>),
+    Utf8(Vec<ByteArray>),
+}
+
+struct ColumnBuffer {
+    values: ColumnValues,
+    /// 1 per present value, 0 per null; only kept for nullable columns
+    definition_levels: Vec<i16>,
+}
+
+impl ColumnBuffer {
+    fn new(column: &Column) -> Self {
+        let values = match column.kind {
+            ColumnType::Int64 | ColumnType::Timestamp => ColumnValues::Int64(Vec::new()),
+            ColumnType::Boolean => ColumnValues::Boolean(Vec::new()),
+            ColumnType::Utf8 => ColumnValues::Utf8(Vec::new()),
+        };
+        Self {
+            values,
+            definition_levels: Vec::new(),
+        }
+    }
+}
+
+/// Streams rows into a Parquet file with a flat schema of INT64, BOOLEAN, UTF-8 and
+/// millisecond timestamp columns, uncompressed. Rows are buffered into row groups of
+/// `ROW_GROUP_SIZE`, so memory stays bounded.
+pub struct ParquetWriter<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    columns: &'static [Column],
+    buffers: Vec<ColumnBuffer>,
+    rows: usize,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    pub fn new(out: W, columns: &'static [Column]) -> io::Result<Self> {
+        let fields = columns
+            .iter()
+            .map(|column| column.schema_type().map(Arc::new))
+            .collect::<parquet::errors::Result<Vec<_>>>()
+            .map_err(parquet_error)?;
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()
+            .map_err(parquet_error)?;
+        let properties = WriterProperties::builder()
+            .set_created_by(concat!("polygon-indexer ", env!("CARGO_PKG_VERSION")).to_string())
+            .build();
+        Ok(Self {
+            writer: SerializedFileWriter::new(out, Arc::new(schema), Arc::new(properties)).map_err(parquet_error)?,
+            columns,
+            buffers: columns.iter().map(ColumnBuffer::new).collect(),
+            rows: 0,
+        })
+    }
+
+    pub fn write_row(&mut self, row: &[Value]) -> io::Result<()> {
+        if row.len() != self.columns.len() {
+            return Err(invalid(format!("expected {} values, got {}", self.columns.len(), row.len())));
+        }
+        for ((column, buffer), value) in self.columns.iter().zip(&mut self.buffers).zip(row) {
+            if column.nullable {
+                buffer.definition_levels.push(i16::from(!matches!(value, Value::Null)));
+            }
+            match (&mut buffer.values, column.kind, value) {
+                (_, _, Value::Null) if column.nullable => {}
+                (ColumnValues::Int64(values), ColumnType::Int64, Value::Int64(v)) => values.push(*v),
+                (ColumnValues::Int64(values), ColumnType::Timestamp, Value::Timestamp(t)) => {
+                    values.push(t.and_utc().timestamp_millis())
+                }
+                (ColumnValues::Boolean(values), _, Value::Boolean(b)) => values.push(*b),
+                (ColumnValues::Utf8(values), _, Value::Utf8(s)) => values.push(ByteArray::from(*s)),
+                (_, kind, value) => return Err(invalid(format!("{:?} value in {:?} column {}", value, kind, column.name))),
+            }
+        }
+        self.rows += 1;
+        if self.rows == ROW_GROUP_SIZE {
+            self.flush_row_group()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered rows as a row group
+    fn flush_row_group(&mut self) -> io::Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let columns = self.columns;
+        let buffers = std::mem::replace(&mut self.buffers, columns.iter().map(ColumnBuffer::new).collect());
+        let mut group = self.writer.next_row_group().map_err(parquet_error)?;
+        for (column, buffer) in columns.iter().zip(buffers) {
+            let mut writer = group
+                .next_column()
+                .map_err(parquet_error)?
+                .ok_or_else(|| invalid(format!("no column writer for {}", column.name)))?;
+            let levels = column.nullable.then_some(buffer.definition_levels.as_slice());
+            match &buffer.values {
+                ColumnValues::Int64(values) => writer.typed::<Int64Type>().write_batch(values, levels, None),
+                ColumnValues::Boolean(values) => writer.typed::<BoolType>().write_batch(values, levels, None),
+                ColumnValues::Utf8(values) => writer.typed::<ByteArrayType>().write_batch(values, levels, None),
+            }
+            .map_err(parquet_error)?;
+            writer.close().map_err(parquet_error)?;
+        }
+        group.close().map_err(parquet_error)?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    /// Writes the remaining rows and the footer, and returns the output
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_row_group()?;
+        let mut out = self.writer.into_inner().map_err(parquet_error)?;
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn parquet_error(error: ParquetError) -> io::Error {
+    match error {
+        ParquetError::External(error) => io::Error::other(error),
+        error => io::Error::other(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::{Field, RowAccessor};
+    use std::io::{Seek, SeekFrom};
+
+    const COLUMNS: &[Column] = &[
+        Column::required("id", ColumnType::Utf8),
+        Column::required("block_number", ColumnType::Int64),
+        Column::optional("transaction_index", ColumnType::Int64),
+        Column::optional("log_index", ColumnType::Int64),
+        Column::optional("entity", ColumnType::Utf8),
+        Column::required("timestamp", ColumnType::Timestamp),
+        Column::required("is_watched", ColumnType::Boolean),
+    ];
+
+    fn timestamp(row: usize) -> NaiveDateTime {
+        chrono::DateTime::from_timestamp(1_700_000_000 + row as i64, 0).unwrap().naive_utc()
+    }
+
+    /// Writes `rows` rows, every third one with null indexes and entity, and reads the file back
+    fn round_trip(rows: usize) -> SerializedFileReader<std::fs::File> {
+        let ids: Vec<String> = (0..rows).map(|row| format!("137_0x{:x}_{}", row, row % 4)).collect();
+        let mut writer = ParquetWriter::new(tempfile::tempfile().unwrap(), COLUMNS).unwrap();
+        for (row, id) in ids.iter().enumerate() {
+            let legacy = row % 3 == 0;
+            writer
+                .write_row(&[
+                    Value::Utf8(id),
+                    Value::Int64(row as i64 / 4),
+                    if legacy { Value::Null } else { Value::Int64(0) },
+                    if legacy { Value::Null } else { Value::Int64(row as i64 % 4) },
+                    if legacy { Value::Null } else { Value::Utf8("binance") },
+                    Value::Timestamp(timestamp(row)),
+                    Value::Boolean(row % 2 == 0),
+                ])
+                .unwrap();
+        }
+        let mut file = writer.finish().unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        SerializedFileReader::new(file).unwrap()
+    }
+
+    #[test]
+    fn rows_read_back_with_nulls_and_booleans() {
+        let reader = round_trip(7);
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 7);
+        assert_eq!(metadata.num_row_groups(), 1);
+
+        let rows: Vec<Vec<Field>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().get_column_iter().map(|(_, field)| field.clone()).collect())
+            .collect();
+        assert_eq!(rows.len(), 7);
+        assert_eq!(
+            rows[0],
+            vec![
+                Field::Str("137_0x0_0".to_string()),
+                Field::Long(0),
+                Field::Null,
+                Field::Null,
+                Field::Null,
+                Field::TimestampMillis(timestamp(0).and_utc().timestamp_millis()),
+                Field::Bool(true),
+            ]
+        );
+        assert_eq!(
+            rows[5],
+            vec![
+                Field::Str("137_0x5_1".to_string()),
+                Field::Long(1),
+                Field::Long(0),
+                Field::Long(1),
+                Field::Str("binance".to_string()),
+                Field::TimestampMillis(timestamp(5).and_utc().timestamp_millis()),
+                Field::Bool(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_are_split_into_row_groups() {
+        let rows = ROW_GROUP_SIZE * 2 + 3;
+        let reader = round_trip(rows);
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), rows as i64);
+        let group_rows: Vec<i64> = metadata.row_groups().iter().map(|group| group.num_rows()).collect();
+        assert_eq!(group_rows, vec![ROW_GROUP_SIZE as i64, ROW_GROUP_SIZE as i64, 3]);
+
+        let mut read = 0;
+        for (row, record) in reader.get_row_iter(None).unwrap().enumerate() {
+            let record = record.unwrap();
+            assert_eq!(record.get_string(0).unwrap(), &format!("137_0x{:x}_{}", row, row % 4));
+            assert_eq!(record.get_long(3).ok(), (row % 3 != 0).then_some(row as i64 % 4));
+            assert_eq!(record.get_bool(6).unwrap(), row % 2 == 0);
+            read += 1;
+        }
+        assert_eq!(read, rows);
+    }
+
+    #[test]
+    fn empty_file_has_schema_and_no_rows() {
+        let reader = round_trip(0);
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 0);
+        assert_eq!(metadata.num_row_groups(), 0);
+        let names: Vec<&str> = metadata
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| column.name())
+            .collect();
+        assert_eq!(names, COLUMNS.iter().map(|column| column.name).collect::<Vec<_>>());
+        assert_eq!(reader.get_row_iter(None).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn mismatched_values_are_rejected() {
+        let mut writer = ParquetWriter::new(Vec::new(), COLUMNS).unwrap();
+        assert!(writer.write_row(&[Value::Null]).is_err());
+        let mut row = vec![
+            Value::Null,
+            Value::Int64(0),
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Timestamp(timestamp(0)),
+            Value::Boolean(false),
+        ];
+        assert!(writer.write_row(&row).is_err(), "null in a required column");
+        row[0] = Value::Int64(1);
+        assert!(writer.write_row(&row).is_err(), "integer in a string column");
+    }
+}
@@ -0,0 +1,263 @@
+use diesel::connection::SimpleConnection;
+use diesel::dsl::{max, min};
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::{
+    config::ChainConfig,
+    database::DbPool,
+    history,
+    labels::Labels,
+    models::NetFlow,
+    schema::{net_flow_snapshots, net_flows, transfers},
+    tokens::TokenSet,
+    verify::{address_totals, store_totals, FlowTotals},
+    watchlists::WatchSet,
+    Result,
+};
+
+/// Blocks of transfers reclassified per write transaction, so the indexer can commit in between
+const RECLASSIFY_BATCH_BLOCKS: i64 = 10_000;
+
+/// Addresses per `IN (...)` query, well under SQLite's parameter limit
+const ADDRESS_CHUNK_SIZE: usize = 500;
+
+diesel::table! {
+    /// Connection-local list of the addresses `reclassify_transfers` flags, by flag
+    reclassify_addresses (flag, address) {
+        flag -> Text,
+        address -> Text,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(reclassify_addresses, transfers);
+
+const BINANCE_FLAG: &str = "binance";
+const WATCHED_FLAG: &str = "watched";
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RebuildReport {
+    /// Transfers whose `is_binance_related` or `is_watched` flag changed
+    pub transfers_reclassified: usize,
+    pub addresses_tracked: usize,
+    /// Net flow rows written or deleted because they differed from the transfers
+    pub net_flows_changed: usize,
+    /// Net flow rows of addresses that are no longer labelled or watched
+    pub net_flows_dropped: usize,
+    /// Snapshots taken again for the current addresses
+    pub snapshots_retaken: usize,
+}
+
+/// Re-derives a chain's transfer flags, net flows and net flow snapshots from its transfer
+/// log, against the current labels and watchlists.
+///
+/// The work is split into short write transactions, so a running indexer keeps storing
+/// blocks in between. The caller publishes `labels` first (see `Labels::publish`), which
+/// the indexer applies from the next block it stores, so the blocks it stores meanwhile match.
+pub fn rebuild_chain(
+    pool: &DbPool,
+    chain: &ChainConfig,
+    labels: &Labels,
+    watchlists: &WatchSet,
+    tokens: &TokenSet,
+) -> Result<RebuildReport> {
+    let chain_id = chain.chain_id as i64;
+    let labelled = labels.addresses(Some(chain_id));
+    let binance: Vec<String> = labelled.iter().filter(|address| labels.is_binance(chain_id, address)).cloned().collect();
+    let watched = watchlists.addresses();
+    let tracked: BTreeSet<String> = labelled.into_iter().chain(watched.iter().cloned()).collect();
+
+    let mut report = RebuildReport {
+        transfers_reclassified: reclassify_transfers(pool, chain_id, &binance, &watched)?,
+        addresses_tracked: tracked.len(),
+        ..RebuildReport::default()
+    };
+    let (changed, dropped) = rebuild_net_flows(pool, chain_id, &tracked)?;
+    report.net_flows_changed = changed;
+    report.net_flows_dropped = dropped;
+
+    let addresses: Vec<String> = tracked.into_iter().collect();
+    let token_addresses: Vec<String> = tokens.all(Some(chain_id)).into_iter().map(|token| token.address).collect();
+    report.snapshots_retaken = retake_snapshots(pool, chain_id, &addresses, &token_addresses)?;
+    Ok(report)
+}
+
+/// Sets `is_binance_related` and `is_watched` of every stored transfer from the given
+/// addresses, a batch of blocks at a time. Returns how many transfers changed.
+///
+/// The addresses go into a temporary table in chunks, so any number of them can be matched.
+fn reclassify_transfers(pool: &DbPool, chain_id: i64, binance: &[String], watched: &[String]) -> Result<usize> {
+    let mut conn = pool.get()?;
+    let (first, last): (Option<i64>, Option<i64>) = transfers::table
+        .filter(transfers::chain_id.eq(chain_id))
+        .select((min(transfers::block_number), max(transfers::block_number)))
+        .first(&mut conn)?;
+    let (Some(first), Some(last)) = (first, last) else {
+        return Ok(0);
+    };
+
+    conn.batch_execute(
+        "CREATE TEMP TABLE IF NOT EXISTS reclassify_addresses (flag TEXT NOT NULL, address TEXT NOT NULL, PRIMARY KEY (flag, address));
+         DELETE FROM reclassify_addresses;",
+    )?;
+    for (flag, addresses) in [(BINANCE_FLAG, binance), (WATCHED_FLAG, watched)] {
+        for chunk in addresses.chunks(ADDRESS_CHUNK_SIZE) {
+            let rows: Vec<_> = chunk
+                .iter()
+                .map(|address| (reclassify_addresses::flag.eq(flag), reclassify_addresses::address.eq(address)))
+                .collect();
+            diesel::insert_or_ignore_into(reclassify_addresses::table).values(&rows).execute(&mut conn)?;
+        }
+    }
+
+    let flagged = |flag: &'static str| {
+        reclassify_addresses::table
+            .filter(reclassify_addresses::flag.eq(flag))
+            .select(reclassify_addresses::address)
+    };
+    let is_binance = || transfers::from_address.eq_any(flagged(BINANCE_FLAG)).or(transfers::to_address.eq_any(flagged(BINANCE_FLAG)));
+    let is_watched = || transfers::from_address.eq_any(flagged(WATCHED_FLAG)).or(transfers::to_address.eq_any(flagged(WATCHED_FLAG)));
+    let mut changed = 0;
+    let mut from = first;
+    while from <= last {
+        let to = (from + RECLASSIFY_BATCH_BLOCKS - 1).min(last);
+        changed += diesel::update(
+            transfers::table
+                .filter(transfers::chain_id.eq(chain_id))
+                .filter(transfers::block_number.between(from, to))
+                .filter(transfers::is_binance_related.ne(is_binance()).or(transfers::is_watched.ne(is_watched()))),
+        )
+        .set((transfers::is_binance_related.eq(is_binance()), transfers::is_watched.eq(is_watched())))
+        .execute(&mut conn)?;
+        from = to + 1;
+    }
+    conn.batch_execute("DROP TABLE temp.reclassify_addresses")?;
+    Ok(changed)
+}
+
+/// Recomputes the net flows of every tracked address, one address per write transaction,
+/// then deletes those of addresses no longer tracked. Returns the rows changed and dropped.
+fn rebuild_net_flows(pool: &DbPool, chain_id: i64, tracked: &BTreeSet<String>) -> Result<(usize, usize)> {
+    let mut conn = pool.get()?;
+    let mut changed = 0;
+    for address in tracked {
+        changed += conn.immediate_transaction(|conn| -> Result<usize> {
+            let stored: Vec<NetFlow> = net_flows::table
+                .filter(net_flows::chain_id.eq(chain_id))
+                .filter(net_flows::address.eq(address))
+                .load(conn)?;
+            let mut computed = address_totals(conn, chain_id, address)?;
+            // Tokens with a stored row but no transfers left are deleted
+            for flow in &stored {
+                computed.entry(flow.token.clone()).or_default();
+            }
+
+            let mut written = 0;
+            for (token, totals) in &computed {
+                let current = stored.iter().find(|flow| &flow.token == token).map(FlowTotals::from);
+                if current.as_ref() != Some(totals) {
+                    written += store_totals(conn, chain_id, address, token, totals)?;
+                }
+            }
+            Ok(written)
+        })?;
+    }
+
+    let stored: Vec<String> = net_flows::table
+        .filter(net_flows::chain_id.eq(chain_id))
+        .select(net_flows::address)
+        .distinct()
+        .load(&mut conn)?;
+    let untracked: Vec<String> = stored.into_iter().filter(|address| !tracked.contains(address)).collect();
+    let mut dropped = 0;
+    for chunk in untracked.chunks(ADDRESS_CHUNK_SIZE) {
+        dropped += diesel::delete(
+            net_flows::table
+                .filter(net_flows::chain_id.eq(chain_id))
+                .filter(net_flows::address.eq_any(chunk)),
+        )
+        .execute(&mut conn)?;
+    }
+    Ok((changed, dropped))
+}
+
+/// Takes every existing snapshot of the chain again, oldest first, for `addresses`.
+/// Returns how many were retaken.
+fn retake_snapshots(pool: &DbPool, chain_id: i64, addresses: &[String], tokens: &[String]) -> Result<usize> {
+    let mut conn = pool.get()?;
+    let blocks: Vec<(i64, chrono::NaiveDateTime)> = net_flow_snapshots::table
+        .filter(net_flow_snapshots::chain_id.eq(chain_id))
+        .select((net_flow_snapshots::block_number, net_flow_snapshots::block_timestamp))
+        .distinct()
+        .order(net_flow_snapshots::block_number.asc())
+        .load(&mut conn)?;
+
+    for (block_number, block_time) in &blocks {
+        conn.immediate_transaction(|conn| {
+            history::take_snapshot(conn, chain_id, *block_number, *block_time, addresses, tokens)
+        })?;
+    }
+    Ok(blocks.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::test_pool, models::Transfer, utils::current_timestamp};
+
+    fn address(n: usize) -> String {
+        format!("0x{:040x}", n)
+    }
+
+    fn insert_transfer(pool: &DbPool, block: i64, from: &str, to: &str, is_watched: bool) {
+        let transfer = Transfer {
+            id: format!("137_0x{:02x}_0", block),
+            chain_id: 137,
+            block_number: block,
+            transaction_hash: format!("0x{:02x}", block),
+            from_address: from.to_string(),
+            to_address: to.to_string(),
+            token: "0x0000000000000000000000000000000000001010".to_string(),
+            value: "1".to_string(),
+            timestamp: current_timestamp(),
+            is_binance_related: false,
+            is_watched,
+            log_index: Some(0),
+            transaction_index: Some(0),
+        };
+        diesel::insert_into(transfers::table).values(&transfer).execute(&mut pool.get().unwrap()).unwrap();
+    }
+
+    fn flags(pool: &DbPool) -> Vec<(i64, bool, bool)> {
+        transfers::table
+            .select((transfers::block_number, transfers::is_binance_related, transfers::is_watched))
+            .order(transfers::block_number.asc())
+            .load(&mut pool.get().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn reclassifies_against_address_lists_longer_than_a_chunk() {
+        let (_dir, pool) = test_pool();
+        let watched: Vec<String> = (1..=3 * ADDRESS_CHUNK_SIZE).map(address).collect();
+        let binance = vec![address(100_000)];
+        let stranger = address(200_000);
+
+        insert_transfer(&pool, 1, &stranger, &watched[3 * ADDRESS_CHUNK_SIZE - 1], false);
+        insert_transfer(&pool, 2, &binance[0], &stranger, false);
+        // Flagged before its address was removed from every watchlist
+        insert_transfer(&pool, RECLASSIFY_BATCH_BLOCKS + 5, &stranger, &address(300_000), true);
+
+        assert_eq!(reclassify_transfers(&pool, 137, &binance, &watched).unwrap(), 3);
+        assert_eq!(
+            flags(&pool),
+            vec![(1, false, true), (2, true, false), (RECLASSIFY_BATCH_BLOCKS + 5, false, false)]
+        );
+
+        // Nothing left to change, and the temporary table is rebuilt on every run
+        assert_eq!(reclassify_transfers(&pool, 137, &binance, &watched).unwrap(), 0);
+        assert_eq!(reclassify_transfers(&pool, 137, &[], &[]).unwrap(), 2);
+        assert_eq!(flags(&pool), vec![(1, false, false), (2, false, false), (RECLASSIFY_BATCH_BLOCKS + 5, false, false)]);
+    }
+}
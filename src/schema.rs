@@ -3,19 +3,26 @@
 diesel::table! {
     transfers (id) {
         id -> Text,
+        chain_id -> BigInt,
         block_number -> BigInt,
         transaction_hash -> Text,
         from_address -> Text,
         to_address -> Text,
+        token -> Text,
         value -> Text,
         timestamp -> Timestamp,
         is_binance_related -> Bool,
+        is_watched -> Bool,
+        log_index -> Nullable<BigInt>,
+        transaction_index -> Nullable<BigInt>,
     }
 }
 
 diesel::table! {
-    net_flows (address) {
+    net_flows (chain_id, address, token) {
+        chain_id -> BigInt,
         address -> Text,
+        token -> Text,
         net_flow -> Text,
         inflow -> Text,
         outflow -> Text,
@@ -35,8 +42,189 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    api_keys (key_hash) {
+        key_hash -> Text,
+        name -> Text,
+        rate_limit_per_minute -> Integer,
+        max_connections -> Integer,
+        enabled -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Text,
+        rule_name -> Text,
+        url -> Text,
+        payload -> Text,
+        status -> Text,
+        attempts -> Integer,
+        response_status -> Nullable<Integer>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    alert_firings (id) {
+        id -> Text,
+        rule_name -> Text,
+        dedup_key -> Text,
+        summary -> Text,
+        payload -> Text,
+        transfer_id -> Text,
+        fired_at -> Timestamp,
+        chain_id -> BigInt,
+    }
+}
+
+diesel::table! {
+    watchlists (id) {
+        id -> Text,
+        name -> Text,
+        description -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    watchlist_addresses (watchlist_id, address) {
+        watchlist_id -> Text,
+        address -> Text,
+        label -> Nullable<Text>,
+        added_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    indexer_state (chain_id) {
+        chain_id -> BigInt,
+        current_block -> BigInt,
+        chain_head -> BigInt,
+        is_running -> Bool,
+        last_block_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    event_outbox (id) {
+        id -> BigInt,
+        kind -> Text,
+        payload -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    tokens (chain_id, address) {
+        chain_id -> BigInt,
+        address -> Text,
+        symbol -> Text,
+        decimals -> Integer,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    balances (chain_id, address, token) {
+        chain_id -> BigInt,
+        address -> Text,
+        token -> Text,
+        balance -> Text,
+        transfer_count -> BigInt,
+        seeded_block -> Nullable<BigInt>,
+        last_block -> BigInt,
+        last_updated -> Timestamp,
+    }
+}
+
+diesel::table! {
+    net_flow_snapshots (chain_id, block_number, address, token) {
+        chain_id -> BigInt,
+        block_number -> BigInt,
+        address -> Text,
+        token -> Text,
+        net_flow -> Text,
+        inflow -> Text,
+        outflow -> Text,
+        transfer_count -> BigInt,
+        last_transfer_at -> Nullable<Timestamp>,
+        block_timestamp -> Timestamp,
+    }
+}
+
+diesel::table! {
+    indexed_ranges (chain_id, start_block) {
+        chain_id -> BigInt,
+        start_block -> BigInt,
+        end_block -> BigInt,
+    }
+}
+
+diesel::table! {
+    archived_blocks (chain_id, block_number) {
+        chain_id -> BigInt,
+        block_number -> BigInt,
+        block_hash -> Binary,
+        block_timestamp -> Timestamp,
+        log_count -> BigInt,
+    }
+}
+
+diesel::table! {
+    labels (chain_id, address) {
+        chain_id -> BigInt,
+        address -> Text,
+        entity -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    labelled_chains (chain_id) {
+        chain_id -> BigInt,
+        published_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    raw_logs (chain_id, block_number, log_index) {
+        chain_id -> BigInt,
+        block_number -> BigInt,
+        log_index -> BigInt,
+        transaction_index -> BigInt,
+        transaction_hash -> Binary,
+        address -> Binary,
+        topics -> Binary,
+        data -> Binary,
+    }
+}
+
+diesel::joinable!(alert_firings -> transfers (transfer_id));
+diesel::joinable!(watchlist_addresses -> watchlists (watchlist_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
     transfers,
     net_flows,
     system_stats,
+    api_keys,
+    webhook_deliveries,
+    alert_firings,
+    watchlists,
+    watchlist_addresses,
+    indexer_state,
+    event_outbox,
+    tokens,
+    balances,
+    net_flow_snapshots,
+    indexed_ranges,
+    archived_blocks,
+    raw_logs,
+    labels,
+    labelled_chains,
 );
@@ -1,40 +1,75 @@
 ﻿use axum::{
-extract::{Query, State, WebSocketUpgrade, ws::{WebSocket, Message}},
-response::{Html, IntoResponse, Response},
-routing::{get, get_service},
-Json, Router,
+async_trait,
+extract::{FromRequest, FromRequestParts, Query, Request, State, WebSocketUpgrade, ws::{CloseFrame, WebSocket, Message}},
+http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, Method},
+middleware,
+response::{IntoResponse, Response, sse::{Event as SseEvent, KeepAlive, Sse}},
+routing::{delete, get, get_service, post},
+Extension, Json, Router,
 };
 use tower::ServiceBuilder;
 use tower_http::{
-cors::CorsLayer,
+cors::{AllowOrigin, CorsLayer},
 trace::TraceLayer,
 services::ServeDir,
 };
-use serde::Deserialize;
-use std::sync::Arc;
-use tokio::{sync::broadcast, time::{interval, Duration}};
-use tracing::info;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{convert::Infallible, sync::Arc};
+use tokio::{sync::broadcast::{self, error::RecvError}, time::{interval, Duration, Instant}};
+use tracing::{info, warn};
 use diesel::prelude::*;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{stream::{self, SplitSink, Stream}, SinkExt, StreamExt};
+use utoipa::{IntoParams, OpenApi};
+use async_graphql::http::{WebSocketProtocols as GraphqlProtocol, WsMessage, ALL_WEBSOCKET_PROTOCOLS};
 
 use crate::{
 Config, IndexerError,
+alerts::{self, AlertEngine, AlertFiringView, AlertRule, AlertRuleView, AlertTestRequest, Aggregate, Conditions, Direction, Evaluation, WindowCondition, WindowSummary},
+auth::{connection_guard, require_api_key, Auth, Caller},
+balances,
+chains::{self, ChainStatus},
 database::DbPool,
-indexer::PolygonIndexer,
-models::{Transfer, NetFlow},
+error::ErrorResponse,
+export,
+graphql::{self, GraphqlSchema},
+health::{self, ComponentHealth, HealthReport, HealthStatus},
+history::{self, AsOf, AsOfQuery},
+events::{Event, EventBus, Resume, Sequenced},
+labels::Labels,
+metrics::{metrics, track_http, WsClientGuard},
+shutdown::Shutdown,
+models::{Balance, IndexerState, Transfer, NetFlow, Token, Watchlist, WatchlistAddress},
+schema::indexer_state,
+subscriptions::{Ack, ClientMessage, Subscriptions},
+tokens::{self, TokenSet},
 types::{NetFlowData, SystemStats},
-utils::{string_to_bigdecimal, current_utc_timestamp},
+watchlists::{self, CreateWatchlist, NewWatchedAddress, UpdateWatchlist, WatchSet, WatchlistView},
 };
 // NOTE: Do NOT import crate::server::{...}. The handlers are defined in this same file,
 // so they are already in scope and can be referenced directly in the Router.
 
 
+/// How often the server pings each WebSocket client
+const WS_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Connections that send nothing (not even a pong) for this long are closed
+const WS_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
 #[derive(Clone)]
 pub struct ServerState {
     pub config: Config,
     pub pool: DbPool,
-    pub indexer: Arc<PolygonIndexer>,
-    pub broadcast: broadcast::Sender<String>,
+    pub broadcast: EventBus,
+    pub graphql: GraphqlSchema,
+    pub auth: Arc<Auth>,
+    /// `None` when no alert rules are configured
+    pub alerts: Option<Arc<AlertEngine>>,
+    pub watchlists: WatchSet,
+    pub tokens: TokenSet,
+    /// Entity labels of each configured chain, as published by its indexer
+    pub labels: Labels,
+    /// Ends live connections and stops the server once triggered
+    pub shutdown: Shutdown,
 }
 
 pub struct Server {
@@ -42,47 +77,173 @@ pub struct Server {
 }
 
 #[derive(Deserialize)]
+pub struct WebSocketQuery {
+    /// Last `seq` the client saw; events after it are replayed on connect
+    resume_from: Option<u64>,
+}
+
+#[derive(Deserialize, IntoParams)]
 pub struct TransferQuery {
+    /// Page size, at most 1000 (default 100)
     limit: Option<i64>,
+    /// Number of rows to skip (default 0)
     offset: Option<i64>,
+    /// Only return transfers touching a Binance address
     binance_only: Option<bool>,
+    /// Only return transfers touching an address on a watchlist
+    watched_only: Option<bool>,
+    /// Only return transfers on this chain
+    chain_id: Option<i64>,
+    /// Token address or symbol
+    token: Option<String>,
 }
 
-impl Server {
-    pub fn new(config: Config, pool: DbPool, indexer: Arc<PolygonIndexer>) -> Self {
-        let (broadcast, _) = broadcast::channel(1000);
+#[derive(Deserialize, IntoParams)]
+pub struct TokenQuery {
+    /// Chain id; all chains when omitted
+    pub chain_id: Option<i64>,
+    /// Token address or symbol; all tokens when omitted
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ChainQuery {
+    /// Chain id; all chains when omitted
+    pub chain_id: Option<i64>,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Polygon Token Indexer API"),
+    paths(
+        health::liveness, health::readiness,
+        get_transfers, get_net_flow, get_stats, tokens::list_tokens, chains::list_chains,
+        balances::get_balances, balances::get_top_holders, export::export_dataset,
+        alerts::list_alert_rules, alerts::test_alert_rules, alerts::alert_history,
+        watchlists::list_watchlists, watchlists::create_watchlist, watchlists::get_watchlist,
+        watchlists::update_watchlist, watchlists::delete_watchlist, watchlists::add_watched_address,
+        watchlists::remove_watched_address, watchlists::watchlist_flows,
+    ),
+    components(schemas(
+        Transfer, NetFlowData, SystemStats, Token, ChainStatus, Balance, ErrorResponse,
+        HealthReport, ComponentHealth, HealthStatus,
+        AlertRuleView, AlertRule, Conditions, Direction, WindowCondition, Aggregate,
+        AlertTestRequest, Evaluation, WindowSummary, AlertFiringView,
+        Watchlist, WatchlistAddress, WatchlistView, CreateWatchlist, UpdateWatchlist, NewWatchedAddress,
+    )),
+    tags(
+        (name = "transfers", description = "Indexed token transfers"),
+        (name = "tokens", description = "Indexed tokens"),
+        (name = "flows", description = "Exchange net flows"),
+        (name = "balances", description = "Token balances derived from indexed transfers"),
+        (name = "export", description = "CSV and Parquet downloads"),
+        (name = "indexer", description = "Indexer status"),
+        (name = "alerts", description = "Alert rules and firing history"),
+        (name = "watchlists", description = "Named lists of followed addresses"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Query string extractor that reports parse failures as a JSON `bad_request` error
+pub struct ApiQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = IndexerError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Query::<T>::from_request_parts(parts, state)
+            .await
+            .map(|Query(value)| ApiQuery(value))
+            .map_err(|rejection| IndexerError::BadRequest(rejection.body_text()))
+    }
+}
+
+/// JSON body extractor that reports parse failures as a JSON `bad_request` error
+pub struct ApiJson<T>(pub T);
 
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = IndexerError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        Json::<T>::from_request(request, state)
+            .await
+            .map(|Json(value)| ApiJson(value))
+            .map_err(|rejection| IndexerError::BadRequest(rejection.body_text()))
+    }
+}
+
+impl ServerState {
+    /// Progress reported by a chain's indexer, which may run in another process; `None` before it first starts
+    pub fn indexer_state(&self, chain_id: i64) -> crate::Result<Option<IndexerState>> {
+        let mut conn = self.pool.get()?;
+        let state = indexer_state::table.find(chain_id).first(&mut conn).optional()?;
+        Ok(state)
+    }
+
+    /// Last block processed on `chain_id`, or on the first configured chain; 0 before its indexer first starts
+    pub fn current_block(&self, chain_id: Option<i64>) -> crate::Result<u64> {
+        let Some(chain_id) = chain_id.or_else(|| self.config.chains.first().map(|chain| chain.chain_id as i64)) else {
+            return Ok(0);
+        };
+        Ok(self.indexer_state(chain_id)?.map_or(0, |state| state.current_block as u64))
+    }
+}
+
+#[cfg(test)]
+impl ServerState {
+    /// State of an API node for `config` on `pool`, without alerting
+    pub(crate) fn for_tests(config: Config, pool: DbPool) -> Self {
+        let labels = Labels::from_config(&config);
+        let tokens = TokenSet::load(&pool).unwrap();
+        let watchlists = WatchSet::load(&pool).unwrap();
+        Server::new(config, pool, EventBus::new(), None, watchlists, tokens, labels, Shutdown::default()).unwrap().state
+    }
+}
+
+impl Server {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config: Config,
+        pool: DbPool,
+        broadcast: EventBus,
+        alerts: Option<Arc<AlertEngine>>,
+        watchlists: WatchSet,
+        tokens: TokenSet,
+        labels: Labels,
+        shutdown: Shutdown,
+    ) -> crate::Result<Self> {
+        let graphql = graphql::build_schema(&config, pool.clone(), broadcast.clone(), tokens.clone(), labels.clone());
+        let auth = Arc::new(Auth::new(&config, pool.clone())?);
         let state = ServerState {
             config,
             pool,
-            indexer,
             broadcast,
+            graphql,
+            auth,
+            alerts,
+            watchlists,
+            tokens,
+            labels,
+            shutdown,
         };
 
-        Self { state }
+        Ok(Self { state })
     }
 
     pub async fn start(self) -> crate::Result<()> {
         let addr = format!("{}:{}", self.state.config.host, self.state.config.port);
         info!("🌐 Starting web server on {}", addr);
-
-        // Serve ./ui as site root
-        let static_files = get_service(ServeDir::new("ui/dist").append_index_html_on_directories(true));
-
-        // Build router
-        let app = Router::new()
-            .route("/ws", get(websocket_handler))
-            .route("/api/health", get(health_check))
-            .route("/api/transfers", get(get_transfers))
-            .route("/api/netflow", get(get_net_flow))
-            .route("/api/stats", get(get_stats))
-            .nest_service("/", static_files)
-            .layer(
-                ServiceBuilder::new()
-                    .layer(TraceLayer::new_for_http())
-                    .layer(CorsLayer::very_permissive())
-            )
-            .with_state(self.state.clone());
+        let app = self.router();
 
         // WS broadcast task
         let broadcast_state = self.state.clone();
@@ -94,17 +255,99 @@ impl Server {
         let listener = tokio::net::TcpListener::bind(&addr).await?;
         info!("🚀 Server listening on http://{}", addr);
 
+        // Stop accepting connections on shutdown and wait for in-flight requests;
+        // WebSocket and SSE connections end themselves when they see the same signal
+        let shutdown = self.state.shutdown.clone();
         axum::serve(listener, app)
+            .with_graceful_shutdown(async move { shutdown.wait().await })
             .await
             .map_err(|e| IndexerError::Generic(format!("Server error: {}", e)))?;
 
+        info!("🛑 Web server stopped");
         Ok(())
     }
+
+    fn router(&self) -> Router {
+        // Serve ./ui as site root
+        let static_files = get_service(ServeDir::new("ui/dist").append_index_html_on_directories(true));
+
+        // Data routes require an API key when authentication is enabled
+        let protected = Router::new()
+            .route("/ws", get(websocket_handler))
+            .route("/api/stream", get(sse_handler))
+            .route("/api/transfers", get(get_transfers))
+            .route("/api/netflow", get(get_net_flow))
+            .route("/api/stats", get(get_stats))
+            .route("/api/tokens", get(tokens::list_tokens))
+            .route("/api/chains", get(chains::list_chains))
+            .route("/api/balances/:address", get(balances::get_balances))
+            .route("/api/holders", get(balances::get_top_holders))
+            .route("/api/export/:dataset", get(export::export_dataset))
+            .route("/api/alerts", get(alerts::list_alert_rules))
+            .route("/api/alerts/test", post(alerts::test_alert_rules))
+            .route("/api/alerts/history", get(alerts::alert_history))
+            .route("/api/watchlists", get(watchlists::list_watchlists).post(watchlists::create_watchlist))
+            .route(
+                "/api/watchlists/:id",
+                get(watchlists::get_watchlist)
+                    .put(watchlists::update_watchlist)
+                    .delete(watchlists::delete_watchlist),
+            )
+            .route("/api/watchlists/:id/addresses", post(watchlists::add_watched_address))
+            .route("/api/watchlists/:id/addresses/:address", delete(watchlists::remove_watched_address))
+            .route("/api/watchlists/:id/flows", get(watchlists::watchlist_flows))
+            .route("/api/graphql", post(graphql_handler))
+            .route("/api/graphql/ws", get(graphql_ws_handler))
+            .route_layer(middleware::from_fn_with_state(self.state.clone(), require_api_key));
+
+        Router::new()
+            .merge(protected)
+            .route("/api/health", get(health::liveness))
+            .route("/api/health/live", get(health::liveness))
+            .route("/api/health/ready", get(health::readiness))
+            .route("/api/openapi.json", get(openapi_spec))
+            .route("/metrics", get(metrics_handler))
+            .nest_service("/", static_files)
+            .layer(
+                ServiceBuilder::new()
+                    .layer(TraceLayer::new_for_http())
+                    .layer(middleware::from_fn(track_http))
+                    .layer(cors_layer(&self.state.config.cors_allowed_origins))
+            )
+            .with_state(self.state.clone())
+    }
 }
 
-async fn serve_index() -> Html<&'static str> {
-    let html = r#"<!doctype html><html><body>Static UI is served from ./ui. Replace or remove serve_index.</body></html>"#;
-    Html(html)
+// CORS from the configured allow-list. An empty list adds no CORS headers, so only
+// same-origin pages (like the bundled UI) can call the API from a browser.
+fn cors_layer(allowed_origins: &[String]) -> CorsLayer {
+    if allowed_origins.iter().any(|origin| origin == "*") {
+        return CorsLayer::permissive();
+    }
+
+    let origins: Vec<HeaderValue> = allowed_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("⚠️ Ignoring invalid CORS origin: {}", origin);
+                None
+            }
+        })
+        .collect();
+    if origins.is_empty() {
+        return CorsLayer::new();
+    }
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            HeaderName::from_static("x-api-key"),
+            HeaderName::from_static("last-event-id"),
+        ])
 }
 
 async fn websocket_broadcast_task(state: ServerState) {
@@ -112,18 +355,15 @@ async fn websocket_broadcast_task(state: ServerState) {
     let mut ticker = interval(Duration::from_secs(5));
 
     loop {
-        ticker.tick().await;
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = state.shutdown.wait() => break,
+        }
 
         // Gather current stats and broadcast to all subscribers
-        match get_current_stats(&state).await {
+        match get_current_stats(&state, None, None).await {
             Ok(stats) => {
-                if let Ok(payload) = serde_json::to_string(&serde_json::json!({
-                    "type": "stats_update",
-                    "data": stats
-                })) {
-                    // Ignore send errors (no active subscribers)
-                    let _ = state.broadcast.send(payload);
-                }
+                state.broadcast.publish(Event::StatsUpdate(stats));
             }
             Err(err) => {
                 // Silently continue on errors; optionally log if you have tracing set up
@@ -132,23 +372,38 @@ async fn websocket_broadcast_task(state: ServerState) {
         }
     }
 }
-async fn get_current_stats(state: &ServerState) -> crate::Result<SystemStats> {
+async fn get_current_stats(
+    state: &ServerState,
+    chain: Option<i64>,
+    token_addresses: Option<&[String]>,
+) -> crate::Result<SystemStats> {
     use crate::schema::transfers::dsl::*;
 
     // DB connection
     let mut conn = state.pool.get()?;
 
     // Total transfers
-    let total_transfers: i64 = transfers.count().get_result(&mut conn)?;
+    let mut total = transfers.count().into_boxed();
+    if let Some(chain) = chain {
+        total = total.filter(chain_id.eq(chain));
+    }
+    if let Some(addrs) = token_addresses {
+        total = total.filter(token.eq_any(addrs));
+    }
+    let total_transfers: i64 = total.get_result(&mut conn)?;
 
     // Binance-related transfers
-    let binance_transfers: i64 = transfers
-        .filter(is_binance_related.eq(true))
-        .count()
-        .get_result(&mut conn)?;
+    let mut binance = transfers.filter(is_binance_related.eq(true)).count().into_boxed();
+    if let Some(chain) = chain {
+        binance = binance.filter(chain_id.eq(chain));
+    }
+    if let Some(addrs) = token_addresses {
+        binance = binance.filter(token.eq_any(addrs));
+    }
+    let binance_transfers: i64 = binance.get_result(&mut conn)?;
 
     // Current block from indexer
-    let current_block = state.indexer.get_current_block().await;
+    let current_block = state.current_block(chain)?;
 
     // Build stats; adjust total_volume/uptime as needed
     Ok(SystemStats {
@@ -160,178 +415,755 @@ async fn get_current_stats(state: &ServerState) -> crate::Result<SystemStats> {
     })
 }
 
+/// Indexer statistics
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "indexer",
+    params(TokenQuery),
+    responses(
+        (status = 200, description = "Current indexer statistics", body = SystemStats),
+        (status = 400, description = "Unknown token", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    )
+)]
 async fn get_stats(
+    ApiQuery(query): ApiQuery<TokenQuery>,
     State(state): State<ServerState>,
-) -> impl IntoResponse {
-    use crate::schema::transfers::dsl::*;
-
-    // Get a DB connection
-    let mut conn = match state.pool.get() {
-        Ok(c) => c,
-        Err(e) => return Json(serde_json::json!({ "error": e.to_string() })).into_response(),
-    };
-
-    // Aggregate totals
-    let total_transfers: i64 = match transfers.count().get_result(&mut conn) {
-        Ok(count) => count,
-        Err(e) => return Json(serde_json::json!({ "error": e.to_string() })).into_response(),
-    };
-
-    let binance_transfers: i64 = match transfers
-        .filter(is_binance_related.eq(true))
-        .count()
-        .get_result(&mut conn)
-    {
-        Ok(count) => count,
-        Err(e) => return Json(serde_json::json!({ "error": e.to_string() })).into_response(),
-    };
-
-    // Current head from indexer
-    let current_block = state.indexer.get_current_block().await;
-
-    // Build response payload (adjust fields as your SystemStats requires)
-    let stats = SystemStats {
-        total_transfers,
-        binance_transfers,
-        total_volume: bigdecimal::BigDecimal::from(0),
-        current_block: current_block as i64,
-        uptime_seconds: 0,
-    };
-
-    Json(stats).into_response()
+) -> crate::Result<Json<SystemStats>> {
+    let token_addresses = state.tokens.resolve_opt(query.token.as_deref(), query.chain_id)?;
+    Ok(Json(get_current_stats(&state, query.chain_id, token_addresses.as_deref()).await?))
 }
+
+/// Cumulative net flow per labelled exchange address, chain and token
+#[utoipa::path(
+    get,
+    path = "/api/netflow",
+    tag = "flows",
+    params(AsOfQuery),
+    responses(
+        (status = 200, description = "Net flow per chain, address and token, most recently updated first", body = [NetFlowData]),
+        (status = 400, description = "Unknown token, both at_block and at_time, or at_block without chain_id on a multi-chain indexer", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    )
+)]
 async fn get_net_flow(
+    ApiQuery(query): ApiQuery<AsOfQuery>,
     State(state): State<ServerState>,
-) -> impl IntoResponse {
+) -> crate::Result<Json<Vec<NetFlowData>>> {
     use crate::schema::net_flows::dsl::*;
 
+    let at = AsOf::from_params(query.at_block, query.at_time)?;
+    let token_addresses = state.tokens.resolve_opt(query.token.as_deref(), query.chain_id)?;
+
     // Acquire a DB connection
-    let mut conn = match state.pool.get() {
-        Ok(c) => c,
-        Err(e) => return Json(serde_json::json!({ "error": e.to_string() })).into_response(),
-    };
+    let mut conn = state.pool.get()?;
 
-    // Load all net flow rows ordered by last_updated (tweak as needed)
-    let rows: Vec<NetFlow> = match net_flows
+    // Historical totals are rebuilt from snapshots and the transfer log
+    if let Some(at) = at {
+        let chain_ids = at.chains(&state.config.chains, query.chain_id)?;
+        let rows = history::flows_as_of_chains(
+            &mut conn,
+            &chain_ids,
+            |chain| state.labels.addresses(Some(chain)),
+            &state.tokens,
+            token_addresses.as_deref(),
+            at,
+        )?;
+        return Ok(Json(rows.into_iter().map(|row| NetFlowData::new(row, &state.labels)).collect()));
+    }
+
+    // Load exchange net flow rows ordered by last_updated; watched wallets are under /api/watchlists
+    let mut q = net_flows.filter(address.eq_any(state.labels.addresses(query.chain_id))).into_boxed();
+    if let Some(chain) = query.chain_id {
+        q = q.filter(chain_id.eq(chain));
+    }
+    if let Some(addrs) = token_addresses {
+        q = q.filter(token.eq_any(addrs));
+    }
+    let rows: Vec<NetFlow> = q
         .order(last_updated.desc())
-        .load(&mut conn)
-    {
-        Ok(v) => v,
-        Err(e) => return Json(serde_json::json!({ "error": e.to_string() })).into_response(),
-    };
+        .load(&mut conn)?;
 
-    // Map DB rows to API response type
+    // Map DB rows to API response type; an address labelled on one chain isn't an exchange on the others
     let data: Vec<NetFlowData> = rows
         .into_iter()
-        .map(|row| NetFlowData {
-            address: row.address,
-            net_flow: string_to_bigdecimal(&row.net_flow),
-            inflow: string_to_bigdecimal(&row.inflow),
-            outflow: string_to_bigdecimal(&row.outflow),
-            transfer_count: row.transfer_count,
-            last_updated: chrono::DateTime::from_naive_utc_and_offset(row.last_updated, chrono::Utc),
-        })
+        .map(|row| NetFlowData::new(row, &state.labels))
+        .filter(|flow| flow.entity.is_some())
         .collect();
 
-    Json(data).into_response()
+    Ok(Json(data))
 }
+
+/// Recent token transfers, newest block first
+#[utoipa::path(
+    get,
+    path = "/api/transfers",
+    tag = "transfers",
+    params(TransferQuery),
+    responses(
+        (status = 200, description = "Page of transfers", body = [Transfer]),
+        (status = 400, description = "Invalid query string or unknown token", body = ErrorResponse),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    )
+)]
 async fn get_transfers(
-    Query(query): Query<TransferQuery>,
+    ApiQuery(query): ApiQuery<TransferQuery>,
     State(state): State<ServerState>,
-) -> impl IntoResponse {
+) -> crate::Result<Json<Vec<Transfer>>> {
     use crate::schema::transfers::dsl::*;
 
     // Get a DB connection
-    let mut conn = match state.pool.get() {
-        Ok(c) => c,
-        Err(e) => return Json(serde_json::json!({ "error": e.to_string() })).into_response(),
-    };
+    let mut conn = state.pool.get()?;
 
     // Pagination defaults and bounds
     let limit_val = query.limit.unwrap_or(100).min(1000);
     let offset_val = query.offset.unwrap_or(0);
+    if limit_val < 0 || offset_val < 0 {
+        return Err(IndexerError::BadRequest("limit and offset must not be negative".to_string()));
+    }
 
-    // Build query with optional Binance filter
+    let token_addresses = state.tokens.resolve_opt(query.token.as_deref(), query.chain_id)?;
+
+    // Build query with optional chain, Binance, watchlist and token filters
     let mut q = transfers.into_boxed();
+    if let Some(chain) = query.chain_id {
+        q = q.filter(chain_id.eq(chain));
+    }
+    if let Some(addrs) = token_addresses {
+        q = q.filter(token.eq_any(addrs));
+    }
     if query.binance_only.unwrap_or(false) {
         q = q.filter(is_binance_related.eq(true));
     }
+    if query.watched_only.unwrap_or(false) {
+        q = q.filter(is_watched.eq(true));
+    }
 
     // Execute
-    let rows: Vec<Transfer> = match q
-        .order(block_number.desc())
+    let rows: Vec<Transfer> = q
+        .order((block_number.desc(), log_index.desc(), id.desc()))
         .limit(limit_val)
         .offset(offset_val)
-        .load(&mut conn)
-    {
-        Ok(v) => v,
-        Err(e) => return Json(serde_json::json!({ "error": e.to_string() })).into_response(),
-    };
+        .load(&mut conn)?;
 
-    Json(rows).into_response()
+    Ok(Json(rows))
 }
-async fn health_check() -> Json<serde_json::Value> {
-    Json(serde_json::json!({
-        "status": "healthy",
-        "timestamp": current_utc_timestamp()
-    }))
+
+// Prometheus scrape endpoint
+async fn metrics_handler(State(state): State<ServerState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().render(&state.pool),
+    )
+}
+
+/// OpenAPI document describing the REST API
+async fn openapi_spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
 }
 
 // WebSocket upgrade handler for GET /ws
 async fn websocket_handler(
     ws: WebSocketUpgrade,
+    ApiQuery(query): ApiQuery<WebSocketQuery>,
+    caller: Option<Extension<Caller>>,
     State(state): State<ServerState>,
-) -> Response {
-    ws.on_upgrade(|socket| websocket_connection(socket, state))
+) -> crate::Result<Response> {
+    let guard = connection_guard(&state, caller.as_deref())?;
+    Ok(ws.on_upgrade(move |socket| async move {
+        let _guard = guard;
+        websocket_connection(socket, state, query.resume_from).await
+    }))
 }
 
-// Per-connection task: forward broadcast messages to this socket and
-// drain client messages (optional) until close.
-async fn websocket_connection(mut socket: WebSocket, state: ServerState) {
-    // Subscribe to the broadcast channel
-    let mut rx = state.broadcast.subscribe();
+// Per-connection task: forward broadcast events the client subscribed to,
+// answer subscription requests and keep the connection alive with pings.
+async fn websocket_connection(socket: WebSocket, state: ServerState, resume_from: Option<u64>) {
+    let _client = WsClientGuard::new();
+    let (mut sender, mut receiver) = socket.split();
+    let mut subscriptions = Subscriptions::default();
 
     // Send an initial message
-    if let Ok(init) = serde_json::to_string(&serde_json::json!({
-        "type": "hello",
-        "data": "Connected to Polygon Indexer"
-    })) {
-        let _ = socket.send(Message::Text(init)).await;
+    if send_event(&mut sender, &Event::Hello("Connected to Polygon Indexer".to_string())).await.is_err() {
+        return;
     }
 
-    // Split a copy of the socket sender for outgoing messages
-    let (mut sender, mut receiver) = socket.split();
+    // Subscribe to the broadcast channel, replaying what the client missed if it asked to resume
+    let mut last_seq = resume_from.unwrap_or_else(|| state.broadcast.last_id());
+    let resume = state.broadcast.resume(last_seq);
+    let mut rx = match catch_up(&mut sender, &subscriptions, resume, &mut last_seq).await {
+        Ok(rx) => rx,
+        Err(_) => return,
+    };
+
+    let mut keepalive = interval(WS_PING_INTERVAL);
+    let mut last_seen = Instant::now();
 
-    // Task to forward server broadcasts to this client
-    let send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            if sender.send(Message::Text(msg)).await.is_err() {
-                break; // client disconnected
+    loop {
+        tokio::select! {
+            received = rx.recv() => {
+                match received {
+                    Ok(sequenced) => {
+                        last_seq = sequenced.id;
+                        if subscriptions.wants(&sequenced.event) && send_event(&mut sender, &sequenced).await.is_err() {
+                            break; // client disconnected
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        metrics().broadcast_lagged.with_label_values(&["ws"]).inc_by(skipped);
+                        // Fell behind the channel: refill from the replay buffer instead of dropping events
+                        let resume = state.broadcast.resume(last_seq);
+                        rx = match catch_up(&mut sender, &subscriptions, resume, &mut last_seq).await {
+                            Ok(rx) => rx,
+                            Err(_) => break,
+                        };
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+            incoming = receiver.next() => {
+                last_seen = Instant::now();
+                let reply = match incoming {
+                    Some(Ok(Message::Text(text))) => handle_client_message(&text, &mut subscriptions, &state),
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => None, // pong and binary frames only refresh liveness
+                };
+                if let Some(reply) = reply {
+                    if send_event(&mut sender, &reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            _ = state.shutdown.wait() => {
+                let _ = sender.send(going_away()).await;
+                break;
+            }
+            _ = keepalive.tick() => {
+                if last_seen.elapsed() > WS_IDLE_TIMEOUT {
+                    info!("🔌 Closing idle WebSocket connection");
+                    break;
+                }
+                if sender.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
             }
         }
-    });
+    }
 
-    // Task to read client messages and stop on close/error
-    let recv_task = tokio::spawn(async move {
-        use futures_util::StreamExt;
-        while let Some(incoming) = receiver.next().await {
-            match incoming {
-                Ok(Message::Close(_)) => break,
-                Ok(_other) => {
-                    // Optionally handle ping/pong/text/binary from client here
-                }
-                Err(_) => break,
+    let _ = sender.close().await;
+}
+
+/// Close frame sent to live clients when the server shuts down, so they know to reconnect
+fn going_away() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: axum::extract::ws::close_code::AWAY,
+        reason: "Server shutting down".into(),
+    }))
+}
+
+fn handle_client_message(text: &str, subscriptions: &mut Subscriptions, state: &ServerState) -> Option<Event> {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => return Some(Event::Error(format!("Invalid message: {}", e))),
+    };
+
+    let reply = match message {
+        ClientMessage::Subscribe { channel, filter } => {
+            let filter = match filter.resolve(&state.tokens, &state.labels) {
+                Ok(filter) => filter,
+                Err(e) => return Some(Event::Error(e.to_string())),
+            };
+            subscriptions.subscribe(channel, filter);
+            Event::Ack(Ack { op: "subscribe".to_string(), channel })
+        }
+        ClientMessage::Unsubscribe { channel } => {
+            if subscriptions.unsubscribe(channel) {
+                Event::Ack(Ack { op: "unsubscribe".to_string(), channel })
+            } else {
+                Event::Error(format!("Not subscribed to {:?}", channel))
+            }
+        }
+        ClientMessage::Ping => Event::Pong,
+    };
+
+    Some(reply)
+}
+
+// Sends an explicit gap or reset notice, if any, then the replayed events the client is
+// subscribed to, and hands back the live receiver
+async fn catch_up(
+    sender: &mut SplitSink<WebSocket, Message>,
+    subscriptions: &Subscriptions,
+    resume: Resume,
+    last_seq: &mut u64,
+) -> Result<broadcast::Receiver<Sequenced>, axum::Error> {
+    if let Some(gap) = resume.gap {
+        send_event(sender, &Event::Gap(gap)).await?;
+    }
+    if let Some(reset) = resume.reset {
+        *last_seq = reset.latest;
+        send_event(sender, &Event::Reset(reset)).await?;
+    }
+    for sequenced in resume.backlog {
+        *last_seq = sequenced.id;
+        if subscriptions.wants(&sequenced.event) {
+            send_event(sender, &sequenced).await?;
+        }
+    }
+    Ok(resume.receiver)
+}
+
+async fn send_event(
+    sender: &mut SplitSink<WebSocket, Message>,
+    event: &impl Serialize,
+) -> Result<(), axum::Error> {
+    match serde_json::to_string(event) {
+        Ok(payload) => sender.send(Message::Text(payload)).await,
+        Err(_) => Ok(()),
+    }
+}
+
+// Server-Sent Events alternative to /ws for clients behind proxies that break upgrades.
+// Browsers resend the last id they saw as Last-Event-ID when reconnecting, and the
+// retained events after it are replayed before the live stream continues.
+async fn sse_handler(
+    headers: HeaderMap,
+    caller: Option<Extension<Caller>>,
+    State(state): State<ServerState>,
+) -> crate::Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>> {
+    let guard = connection_guard(&state, caller.as_deref())?;
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let resume = state.broadcast.resume(last_event_id.unwrap_or_else(|| state.broadcast.last_id()));
+
+    // A reset also moves the browser's Last-Event-ID on to the latest event
+    let notice = match (resume.gap, resume.reset) {
+        (Some(gap), _) => serde_json::to_string(&Event::Gap(gap)).ok().map(|payload| SseEvent::default().data(payload)),
+        (None, Some(reset)) => {
+            let latest = reset.latest;
+            serde_json::to_string(&Event::Reset(reset))
+                .ok()
+                .map(|payload| SseEvent::default().id(latest.to_string()).data(payload))
+        }
+        (None, None) => None,
+    }
+    .map(Ok);
+
+    // The connection slot is held for as long as the stream is alive
+    let live = stream::unfold((resume.receiver, guard), |(mut rx, guard)| async move {
+        // A lagging client is dropped; it resumes from Last-Event-ID on reconnect
+        match rx.recv().await {
+            Ok(sequenced) => Some((sequenced, (rx, guard))),
+            Err(RecvError::Lagged(skipped)) => {
+                metrics().broadcast_lagged.with_label_values(&["sse"]).inc_by(skipped);
+                None
             }
+            Err(RecvError::Closed) => None,
         }
     });
 
-    // End when either task finishes
-    let _ = tokio::select! {
-        _ = send_task => {},
-        _ = recv_task => {},
-    };
+    let replayed = stream::iter(resume.backlog)
+        .chain(live)
+        .filter_map(|sequenced| async move {
+            let payload = serde_json::to_string(&sequenced).ok()?;
+            Some(Ok(SseEvent::default().id(sequenced.id.to_string()).data(payload)))
+        });
+    // Ending the stream on shutdown lets the graceful shutdown complete
+    let shutdown = state.shutdown.clone();
+    let events = stream::iter(notice)
+        .chain(replayed)
+        .take_until(async move { shutdown.wait().await });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// GraphQL queries over transfers, net flows, addresses and blocks
+async fn graphql_handler(
+    State(state): State<ServerState>,
+    ApiJson(request): ApiJson<async_graphql::BatchRequest>,
+) -> Json<async_graphql::BatchResponse> {
+    Json(state.graphql.execute_batch(request).await)
+}
+
+// GraphQL subscriptions over either graphql-transport-ws or the legacy graphql-ws protocol
+async fn graphql_ws_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    caller: Option<Extension<Caller>>,
+    State(state): State<ServerState>,
+) -> crate::Result<Response> {
+    let guard = connection_guard(&state, caller.as_deref())?;
+
+    // Same choice `protocols()` makes: the first protocol the client offered that we support
+    let protocol = headers
+        .get("sec-websocket-protocol")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|offered| offered.split(',').find_map(|name| name.trim().parse().ok()))
+        .unwrap_or(GraphqlProtocol::GraphQLWS);
+
+    Ok(ws.protocols(ALL_WEBSOCKET_PROTOCOLS).on_upgrade(move |socket| async move {
+        let _guard = guard;
+        graphql_ws_connection(socket, state.graphql, protocol, state.shutdown).await
+    }))
 }
 
-// Note: health_check, get_transfers, get_net_flow, get_stats,
+async fn graphql_ws_connection(socket: WebSocket, schema: GraphqlSchema, protocol: GraphqlProtocol, shutdown: Shutdown) {
+    let _client = WsClientGuard::new();
+    let (mut sender, receiver) = socket.split();
+
+    let incoming = Box::pin(receiver
+        .take_while(|message| std::future::ready(matches!(message, Ok(message) if !matches!(message, Message::Close(_)))))
+        .filter_map(|message| async move {
+            match message {
+                Ok(Message::Text(text)) => Some(text.into_bytes()),
+                Ok(Message::Binary(bytes)) => Some(bytes),
+                _ => None,
+            }
+        }));
+
+    let mut outgoing = async_graphql::http::WebSocket::new(schema, incoming, protocol);
+    loop {
+        let message = tokio::select! {
+            message = outgoing.next() => message,
+            _ = shutdown.wait() => {
+                let _ = sender.send(going_away()).await;
+                break;
+            }
+        };
+        let Some(message) = message else { break };
+        let message = match message {
+            WsMessage::Text(text) => Message::Text(text),
+            WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame { code, reason: reason.into() })),
+        };
+        if sender.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Note: get_transfers, get_net_flow, get_stats,
 // websocket_handler, websocket_broadcast_task must either be defined below
 // in this file or imported via the HANDLERS IMPORT section above.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BlockSummary;
+    use axum::{body::Body, http::StatusCode};
+
+    #[tokio::test]
+    async fn malformed_graphql_body_is_a_typed_bad_request() {
+        let request = Request::builder()
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{\"query\": "))
+            .unwrap();
+        let rejection = ApiJson::<async_graphql::BatchRequest>::from_request(request, &())
+            .await
+            .err()
+            .expect("rejected");
+
+        let response = rejection.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["code"], "bad_request");
+    }
+
+    type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    const SENDER: &str = "0x000000000000000000000000000000000000000a";
+    const RECIPIENT: &str = "0x000000000000000000000000000000000000000b";
+
+    /// Serves the API for `config` on a local port and returns its base URL and state
+    async fn serve(config: Config, pool: DbPool) -> (String, ServerState) {
+        let server = Server { state: ServerState::for_tests(config, pool) };
+        let state = server.state.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let shutdown = state.shutdown.clone();
+        tokio::spawn(async move {
+            axum::serve(listener, server.router()).with_graceful_shutdown(async move { shutdown.wait().await }).await
+        });
+        (base, state)
+    }
+
+    async fn connect(base: &str, path: &str) -> Client {
+        let url = format!("{}{}", base.replacen("http", "ws", 1), path);
+        tokio_tungstenite::connect_async(url).await.unwrap().0
+    }
+
+    /// The next text message, skipping pings
+    async fn receive(client: &mut Client) -> serde_json::Value {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), client.next()).await.unwrap().unwrap().unwrap();
+            if let tokio_tungstenite::tungstenite::Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Reads the next `count` events of an SSE stream as their id and JSON data
+    async fn sse_events(stream: &mut reqwest::Response, count: usize) -> Vec<(Option<u64>, serde_json::Value)> {
+        let (mut buffer, mut events) = (String::new(), Vec::new());
+        while events.len() < count {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), stream.chunk()).await.unwrap().unwrap().unwrap();
+            buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+            while let Some(end) = buffer.find("\n\n") {
+                let block: String = buffer.drain(..end + 2).collect();
+                let field = |name: &str| block.lines().find_map(|line| line.strip_prefix(name).map(str::to_string));
+                // Keepalives are comments, without data
+                if let Some(data) = field("data:") {
+                    let id = field("id:").map(|id| id.trim().parse().unwrap());
+                    events.push((id, serde_json::from_str(data.trim()).unwrap()));
+                }
+            }
+        }
+        events
+    }
+
+    /// Sends a client message and returns the reply
+    async fn request(client: &mut Client, text: &str) -> serde_json::Value {
+        client.send(tokio_tungstenite::tungstenite::Message::Text(text.to_string())).await.unwrap();
+        receive(client).await
+    }
+
+    /// Publishes a block of `value` POL from `SENDER` to `RECIPIENT` on `chain_id`, as the indexer commits one
+    fn publish_block(state: &ServerState, chain_id: i64, number: i64, value: &str) {
+        let transfer = Transfer::new(
+            format!("{}:0x{:02x}_0", chain_id, number),
+            chain_id,
+            number,
+            format!("0x{:02x}", number),
+            SENDER.to_string(),
+            RECIPIENT.to_string(),
+            crate::tokens::POL_ADDRESS.to_string(),
+            value.to_string(),
+            crate::utils::current_timestamp(),
+            false,
+            false,
+            Some(0),
+            Some(0),
+        );
+        let block = BlockSummary {
+            chain_id,
+            number,
+            hash: format!("0x{:064x}", number),
+            timestamp: crate::utils::current_utc_timestamp(),
+            transfer_count: 1,
+        };
+        let events = vec![Event::Transfer(transfer), Event::NewBlock(block)];
+        state.broadcast.commit(&mut state.pool.get().unwrap(), |_| Ok(((), events))).unwrap();
+    }
+
+    #[tokio::test]
+    async fn allowed_origins_may_edit_and_delete_watchlists() {
+        let (_dir, pool) = crate::database::test_pool();
+        let config = Config {
+            cors_allowed_origins: vec!["https://ui.example".to_string()],
+            ..Config::default()
+        };
+        let (base, _) = serve(config, pool).await;
+        let client = reqwest::Client::new();
+
+        for (method, path) in [
+            (Method::PUT, "/api/watchlists/1"),
+            (Method::DELETE, "/api/watchlists/1"),
+            (Method::DELETE, "/api/watchlists/1/addresses/0x000000000000000000000000000000000000000a"),
+        ] {
+            let response = client
+                .request(reqwest::Method::OPTIONS, format!("{}{}", base, path))
+                .header("origin", "https://ui.example")
+                .header("access-control-request-method", method.as_str())
+                .header("access-control-request-headers", "content-type,x-api-key")
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(response.status().as_u16(), 200, "{} {}", method, path);
+            let headers = response.headers();
+            assert_eq!(headers["access-control-allow-origin"], "https://ui.example");
+            let allowed = headers["access-control-allow-methods"].to_str().unwrap();
+            assert!(allowed.split(',').any(|allowed| allowed.trim() == method.as_str()), "{} not in {}", method, allowed);
+        }
+    }
+
+    #[tokio::test]
+    async fn data_routes_need_a_key_within_its_limits() {
+        let (_dir, pool) = crate::database::test_pool();
+        let key = crate::auth::create_key(&pool, "dashboard", 3, 5).unwrap();
+        let streamer = crate::auth::create_key(&pool, "streamer", 600, 1).unwrap();
+        let (base, _) = serve(Config { require_api_key: true, ..Config::default() }, pool).await;
+        let client = reqwest::Client::new();
+        let status = |request: reqwest::RequestBuilder| async move { request.send().await.unwrap().status().as_u16() };
+
+        assert_eq!(status(client.get(format!("{}/api/tokens", base))).await, 401);
+        assert_eq!(status(client.get(format!("{}/api/tokens", base)).header("x-api-key", "pk_wrong")).await, 401);
+        assert_eq!(status(client.get(format!("{}/api/health/live", base))).await, 200);
+
+        // Header, bearer token and query parameter, then over the three requests a minute
+        assert_eq!(status(client.get(format!("{}/api/tokens", base)).header("x-api-key", &key)).await, 200);
+        assert_eq!(status(client.get(format!("{}/api/tokens", base)).bearer_auth(&key)).await, 200);
+        assert_eq!(status(client.get(format!("{}/api/tokens?api_key={}", base, key))).await, 200);
+        assert_eq!(status(client.get(format!("{}/api/tokens", base)).header("x-api-key", &key)).await, 429);
+
+        // One open stream per key for the streamer
+        let stream = client.get(format!("{}/api/stream?api_key={}", base, streamer)).send().await.unwrap();
+        assert_eq!(stream.status().as_u16(), 200);
+        assert_eq!(status(client.get(format!("{}/api/stream?api_key={}", base, streamer))).await, 429);
+        assert_eq!(status(client.get(format!("{}/api/tokens", base)).header("x-api-key", &streamer)).await, 200);
+        drop(stream);
+    }
+
+    #[tokio::test]
+    async fn websocket_clients_get_every_indexer_event_and_can_resume() {
+        let (_dir, pool) = crate::database::test_pool();
+        let (base, state) = serve(Config::default(), pool).await;
+        let mut client = connect(&base, "/ws?resume_from=0").await;
+        assert_eq!(receive(&mut client).await["type"], "hello");
+
+        publish_block(&state, 137, 10, "5");
+        let transfer = receive(&mut client).await;
+        assert_eq!((transfer["type"].as_str(), transfer["seq"].as_u64()), (Some("transfer"), Some(1)));
+        assert_eq!(transfer["data"]["block_number"], 10);
+        let block = receive(&mut client).await;
+        assert_eq!((block["type"].as_str(), block["seq"].as_u64()), (Some("new_block"), Some(2)));
+        assert_eq!(block["data"]["transfer_count"], 1);
+
+        // A client that saw the transfer gets the block on reconnect, then live events
+        let mut resumed = connect(&base, "/ws?resume_from=1").await;
+        assert_eq!(receive(&mut resumed).await["type"], "hello");
+        assert_eq!(receive(&mut resumed).await["seq"], 2);
+        publish_block(&state, 137, 11, "5");
+        assert_eq!(receive(&mut resumed).await["seq"], 3);
+        assert_eq!(receive(&mut client).await["seq"], 3);
+    }
+
+    #[tokio::test]
+    async fn websocket_clients_choose_channels_and_filters() {
+        let (_dir, pool) = crate::database::test_pool();
+        let (base, state) = serve(Config::default(), pool).await;
+        let mut client = connect(&base, "/ws?resume_from=0").await;
+        assert_eq!(receive(&mut client).await["type"], "hello");
+
+        let ack = request(&mut client, r#"{"op": "subscribe", "channel": "transfers", "filter": {"chain_id": 1, "min_value": "10"}}"#).await;
+        assert_eq!(ack["type"], "ack");
+        assert_eq!(ack["data"], serde_json::json!({"op": "subscribe", "channel": "transfers"}));
+        assert_eq!(request(&mut client, r#"{"op": "unsubscribe", "channel": "blocks"}"#).await["type"], "error");
+        assert_eq!(request(&mut client, r#"{"op": "ping"}"#).await["type"], "pong");
+        assert_eq!(request(&mut client, r#"{"op": "subscribe", "channel": "everything"}"#).await["type"], "error");
+
+        // Only the large Ethereum transfer passes; blocks aren't subscribed
+        publish_block(&state, 137, 10, "50");
+        publish_block(&state, 1, 11, "5");
+        publish_block(&state, 1, 12, "50");
+        let delivered = receive(&mut client).await;
+        assert_eq!((delivered["type"].as_str(), delivered["data"]["block_number"].as_i64()), (Some("transfer"), Some(12)));
+        assert_eq!(request(&mut client, r#"{"op": "ping"}"#).await["type"], "pong");
+    }
+
+    #[tokio::test]
+    async fn event_streams_resume_from_the_last_event_id() {
+        let (_dir, pool) = crate::database::test_pool();
+        let (base, state) = serve(Config::default(), pool).await;
+        let client = reqwest::Client::new();
+        publish_block(&state, 137, 10, "5");
+        publish_block(&state, 137, 11, "5");
+
+        let mut stream = client.get(format!("{}/api/stream", base)).header("last-event-id", "2").send().await.unwrap();
+        assert_eq!(stream.headers()["content-type"], "text/event-stream");
+        let replayed = sse_events(&mut stream, 2).await;
+        assert_eq!(replayed.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![Some(3), Some(4)]);
+        assert_eq!(replayed[0].1["type"], "transfer");
+        assert_eq!(replayed[0].1["data"]["block_number"], 11);
+
+        publish_block(&state, 137, 12, "5");
+        let live = sse_events(&mut stream, 2).await;
+        assert_eq!(live.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![Some(5), Some(6)]);
+
+        // An id this node never issued moves the browser on to the latest one
+        let mut stream = client.get(format!("{}/api/stream", base)).header("last-event-id", "99").send().await.unwrap();
+        let reset = sse_events(&mut stream, 1).await;
+        assert_eq!(reset[0].0, Some(6));
+        assert_eq!(reset[0].1["type"], "reset");
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_live_clients_and_stops_serving() {
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, protocol::frame::coding::CloseCode, Message};
+
+        let (_dir, pool) = crate::database::test_pool();
+        let (base, state) = serve(Config::default(), pool).await;
+        let mut websocket = connect(&base, "/ws").await;
+        let mut request = format!("{}/api/graphql/ws", base.replacen("http", "ws", 1)).into_client_request().unwrap();
+        request.headers_mut().insert("sec-websocket-protocol", "graphql-transport-ws".parse().unwrap());
+        let mut graphql = tokio_tungstenite::connect_async(request).await.unwrap().0;
+        let mut stream = reqwest::get(format!("{}/api/stream", base)).await.unwrap();
+        assert_eq!(receive(&mut websocket).await["type"], "hello");
+        publish_block(&state, 137, 10, "5");
+        assert_eq!(receive(&mut websocket).await["type"], "transfer");
+        assert_eq!(sse_events(&mut stream, 1).await[0].1["type"], "transfer");
+
+        state.shutdown.trigger();
+        for client in [&mut websocket, &mut graphql] {
+            let close = loop {
+                match tokio::time::timeout(Duration::from_secs(5), client.next()).await.unwrap() {
+                    Some(Ok(Message::Close(frame))) => break frame,
+                    Some(Ok(_)) => continue,
+                    other => panic!("{:?}", other),
+                }
+            };
+            assert_eq!(close.map(|frame| frame.code), Some(CloseCode::Away));
+        }
+        // Events already sent are flushed, then the stream ends
+        let rest = tokio::time::timeout(Duration::from_secs(5), async {
+            while stream.chunk().await.unwrap().is_some() {}
+        });
+        rest.await.unwrap();
+
+        assert!(reqwest::get(format!("{}/api/health", base)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn chains_report_their_own_progress() {
+        let (_dir, pool) = crate::database::test_pool();
+        let state = IndexerState {
+            chain_id: 137,
+            current_block: 90,
+            chain_head: 100,
+            is_running: true,
+            last_block_at: crate::utils::current_timestamp(),
+            updated_at: crate::utils::current_timestamp(),
+        };
+        diesel::insert_into(indexer_state::table).values(&state).execute(&mut pool.get().unwrap()).unwrap();
+        let mut config = Config::default();
+        config.chains.push(crate::config::ChainConfig { confirmations: 12, ..crate::config::ChainConfig::new("ethereum", 1) });
+        let (base, _) = serve(config, pool).await;
+
+        let chains: serde_json::Value = reqwest::get(format!("{}/api/chains", base)).await.unwrap().json().await.unwrap();
+        assert_eq!(chains, serde_json::json!([
+            {
+                "chain_id": 137, "name": "polygon", "confirmations": 0, "current_block": 90, "chain_head": 100,
+                "is_running": true, "last_block_at": chains[0]["last_block_at"],
+            },
+            {
+                "chain_id": 1, "name": "ethereum", "confirmations": 12, "current_block": null, "chain_head": null,
+                "is_running": false, "last_block_at": null,
+            },
+        ]));
+
+        // A block number means something on one chain only
+        let status = |query: &str| {
+            let url = format!("{}/api/netflow?{}", base, query);
+            async move { reqwest::get(url).await.unwrap().status().as_u16() }
+        };
+        assert_eq!(status("at_block=90").await, 400);
+        assert_eq!(status("at_block=90&chain_id=137").await, 200);
+        assert_eq!(status("at_time=2026-01-01T00:00:00Z").await, 200);
+    }
+}
@@ -0,0 +1,343 @@
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    events::Event,
+    labels::Labels,
+    tokens::TokenSet,
+    utils::string_to_bigdecimal,
+    Result,
+};
+
+/// Messages a WebSocket client may send
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe {
+        channel: Channel,
+        #[serde(default)]
+        filter: Filter,
+    },
+    Unsubscribe {
+        channel: Channel,
+    },
+    Ping,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Transfers,
+    NetFlows,
+    Blocks,
+    Stats,
+    /// Transfers touching an address on any watchlist
+    Watchlists,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [
+        Channel::Transfers,
+        Channel::NetFlows,
+        Channel::Blocks,
+        Channel::Stats,
+        Channel::Watchlists,
+    ];
+
+    /// The channel an event is delivered on; `None` for connection-level messages
+    pub fn of(event: &Event) -> Option<Self> {
+        match event {
+            Event::Transfer(_) => Some(Channel::Transfers),
+            Event::WatchedTransfer(_) => Some(Channel::Watchlists),
+            Event::NetFlowUpdate(_) => Some(Channel::NetFlows),
+            Event::NewBlock(_) => Some(Channel::Blocks),
+            Event::StatsUpdate(_) => Some(Channel::Stats),
+            _ => None,
+        }
+    }
+}
+
+/// Acknowledges a subscribe or unsubscribe request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ack {
+    pub op: String,
+    pub channel: Channel,
+}
+
+/// Optional constraints on a subscription. Each field only applies to events that carry it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    pub chain_id: Option<i64>,
+    pub address: Option<String>,
+    pub min_value: Option<BigDecimal>,
+    pub entity: Option<String>,
+    /// Name of a watchlist the transfer must touch
+    pub watchlist: Option<String>,
+    /// Token address or symbol
+    pub token: Option<String>,
+    /// Filled in by `resolve`
+    #[serde(skip)]
+    pub(crate) resolved: Box<Resolved>,
+}
+
+/// What a filter's token and entity stand for, in the form events carry
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Resolved {
+    /// Contract addresses of `token`
+    token_addresses: Option<Vec<String>>,
+    /// `(chain_id, address)` pairs labelled with `entity`
+    entity_addresses: Option<HashSet<(i64, String)>>,
+}
+
+impl Filter {
+    /// Resolves the token and entity to the addresses events carry; call before `matches`
+    pub fn resolve(mut self, tokens: &TokenSet, labels: &Labels) -> Result<Self> {
+        self.resolved.token_addresses = tokens.resolve_opt(self.token.as_deref(), self.chain_id)?;
+        self.resolved.entity_addresses = self.entity.as_ref().map(|wanted| {
+            labels
+                .entities(self.chain_id)
+                .into_iter()
+                .filter(|(entity, _)| entity.eq_ignore_ascii_case(wanted))
+                .flat_map(|(_, addresses)| addresses)
+                .collect()
+        });
+        Ok(self)
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        match event {
+            Event::Transfer(transfer) => {
+                let addresses = [transfer.from_address.as_str(), transfer.to_address.as_str()];
+                self.matches_chain(transfer.chain_id)
+                    && self.matches_addresses(transfer.chain_id, &addresses)
+                    && self.matches_token(&transfer.token)
+                    && self.matches_value(&string_to_bigdecimal(&transfer.value))
+            }
+            Event::WatchedTransfer(watched) => {
+                let transfer = &watched.transfer;
+                let addresses = [transfer.from_address.as_str(), transfer.to_address.as_str()];
+                self.matches_chain(transfer.chain_id)
+                    && self.matches_addresses(transfer.chain_id, &addresses)
+                    && self.matches_token(&transfer.token)
+                    && self.matches_value(&string_to_bigdecimal(&transfer.value))
+                    && self.watchlist.as_ref().is_none_or(|wanted| {
+                        watched.watchlists.iter().any(|name| name.eq_ignore_ascii_case(wanted))
+                    })
+            }
+            Event::NetFlowUpdate(flow) => {
+                self.matches_chain(flow.chain_id)
+                    && self.matches_addresses(flow.chain_id, &[flow.address.as_str()])
+                    && self.matches_token(&flow.token)
+                    && self.matches_value(&flow.net_flow.abs())
+            }
+            Event::NewBlock(block) => self.matches_chain(block.chain_id),
+            _ => true,
+        }
+    }
+
+    fn matches_chain(&self, chain_id: i64) -> bool {
+        self.chain_id.is_none_or(|wanted| wanted == chain_id)
+    }
+
+    fn matches_addresses(&self, chain_id: i64, addresses: &[&str]) -> bool {
+        let address_ok = self.address.as_ref().is_none_or(|wanted| {
+            addresses.iter().any(|addr| addr.eq_ignore_ascii_case(wanted))
+        });
+        let entity_ok = self.resolved.entity_addresses.as_ref().is_none_or(|labelled| {
+            addresses
+                .iter()
+                .any(|addr| labelled.contains(&(chain_id, addr.to_lowercase())))
+        });
+        address_ok && entity_ok
+    }
+
+    fn matches_token(&self, token: &str) -> bool {
+        self.resolved
+            .token_addresses
+            .as_ref()
+            .is_none_or(|wanted| wanted.iter().any(|addr| addr.eq_ignore_ascii_case(token)))
+    }
+
+    fn matches_value(&self, value: &BigDecimal) -> bool {
+        self.min_value.as_ref().is_none_or(|min| value >= min)
+    }
+}
+
+/// Per-connection subscription state.
+///
+/// A fresh connection receives every channel unfiltered, as before the protocol existed.
+/// The first `subscribe` switches the connection to explicit mode, where only subscribed
+/// channels are delivered.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    explicit: bool,
+    channels: HashMap<Channel, Filter>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, channel: Channel, filter: Filter) {
+        self.explicit = true;
+        self.channels.insert(channel, filter);
+    }
+
+    /// Returns false when the channel was not subscribed
+    pub fn unsubscribe(&mut self, channel: Channel) -> bool {
+        if !self.explicit {
+            // Leaving the firehose keeps every other channel
+            self.explicit = true;
+            for other in Channel::ALL {
+                self.channels.insert(other, Filter::default());
+            }
+        }
+        self.channels.remove(&channel).is_some()
+    }
+
+    pub fn wants(&self, event: &Event) -> bool {
+        let Some(channel) = Channel::of(event) else {
+            return true;
+        };
+        if !self.explicit {
+            return true;
+        }
+        self.channels
+            .get(&channel)
+            .is_some_and(|filter| filter.matches(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ChainConfig, database::test_pool, models::{Token, Transfer}, schema::tokens, types::{BlockSummary, NetFlowData},
+        utils::{current_timestamp, current_utc_timestamp, BINANCE_ADDRESSES}, watchlists::WatchedTransfer,
+    };
+    use diesel::prelude::*;
+
+    const WALLET: &str = "0x000000000000000000000000000000000000000a";
+    const USDC: &str = "0x00000000000000000000000000000000000000c1";
+
+    fn transfer(chain_id: i64, to: &str, token: &str, value: &str) -> Transfer {
+        Transfer::new(
+            "t".to_string(),
+            chain_id,
+            1,
+            "0x01".to_string(),
+            WALLET.to_string(),
+            to.to_string(),
+            token.to_string(),
+            value.to_string(),
+            current_timestamp(),
+            false,
+            false,
+            Some(0),
+            Some(0),
+        )
+    }
+
+    fn block(chain_id: i64) -> Event {
+        Event::NewBlock(BlockSummary {
+            chain_id,
+            number: 1,
+            hash: "0x01".to_string(),
+            timestamp: current_utc_timestamp(),
+            transfer_count: 0,
+        })
+    }
+
+    fn flow(address: &str, net_flow: i64) -> Event {
+        Event::NetFlowUpdate(NetFlowData {
+            chain_id: 137,
+            address: address.to_string(),
+            token: USDC.to_string(),
+            entity: None,
+            net_flow: net_flow.into(),
+            inflow: BigDecimal::from(0),
+            outflow: BigDecimal::from(0),
+            transfer_count: 1,
+            last_updated: current_utc_timestamp(),
+        })
+    }
+
+    /// A filter from its JSON form, resolved against USDC on Polygon and the default Binance labels
+    fn filter(json: serde_json::Value) -> Filter {
+        let (_dir, pool) = test_pool();
+        let usdc = Token {
+            chain_id: 137,
+            address: USDC.to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+            updated_at: current_timestamp(),
+        };
+        diesel::insert_into(tokens::table).values(&usdc).execute(&mut pool.get().unwrap()).unwrap();
+        let labels = Labels::from_chains(&[ChainConfig::new("polygon", 137)]);
+        serde_json::from_value::<Filter>(json).unwrap().resolve(&TokenSet::load(&pool).unwrap(), &labels).unwrap()
+    }
+
+    #[test]
+    fn fresh_connections_get_every_channel_until_they_subscribe() {
+        let mut subscriptions = Subscriptions::default();
+        let usdc = Event::Transfer(transfer(137, WALLET, USDC, "1"));
+        assert!(subscriptions.wants(&usdc) && subscriptions.wants(&block(1)));
+
+        subscriptions.subscribe(Channel::Blocks, Filter::default());
+        assert!(!subscriptions.wants(&usdc));
+        assert!(subscriptions.wants(&block(1)));
+        // Connection-level messages are always delivered
+        assert!(subscriptions.wants(&Event::Pong));
+
+        assert!(subscriptions.unsubscribe(Channel::Blocks));
+        assert!(!subscriptions.unsubscribe(Channel::Blocks));
+        assert!(!subscriptions.wants(&block(1)));
+    }
+
+    #[test]
+    fn leaving_the_firehose_keeps_the_other_channels() {
+        let mut subscriptions = Subscriptions::default();
+        assert!(subscriptions.unsubscribe(Channel::Transfers));
+        assert!(!subscriptions.wants(&Event::Transfer(transfer(137, WALLET, USDC, "1"))));
+        assert!(subscriptions.wants(&block(137)));
+        assert!(subscriptions.wants(&flow(WALLET, 1)));
+    }
+
+    #[test]
+    fn filters_apply_to_the_fields_each_event_carries() {
+        let exchange = BINANCE_ADDRESSES[0];
+        let big_usdc_deposits = filter(serde_json::json!({"chain_id": 137, "token": "usdc", "entity": "Binance", "min_value": "100"}));
+        let cases = [
+            (transfer(137, exchange, USDC, "250"), true),
+            (transfer(1, exchange, USDC, "250"), false),
+            (transfer(137, exchange, crate::tokens::POL_ADDRESS, "250"), false),
+            (transfer(137, WALLET, USDC, "250"), false),
+            (transfer(137, exchange, USDC, "99.5"), false),
+        ];
+        for (transfer, expected) in cases {
+            assert_eq!(big_usdc_deposits.matches(&Event::Transfer(transfer.clone())), expected, "{:?}", transfer);
+        }
+        // Blocks only carry a chain, net flows are compared by size
+        assert!(big_usdc_deposits.matches(&block(137)) && !big_usdc_deposits.matches(&block(1)));
+        assert!(big_usdc_deposits.matches(&flow(exchange, -300)));
+        assert!(!big_usdc_deposits.matches(&flow(exchange, 50)));
+
+        let treasury = filter(serde_json::json!({"watchlist": "Treasury", "address": exchange}));
+        let watched = |watchlists: &[&str]| {
+            Event::WatchedTransfer(WatchedTransfer {
+                transfer: transfer(137, exchange, USDC, "1"),
+                watchlists: watchlists.iter().map(|name| name.to_string()).collect(),
+            })
+        };
+        assert!(treasury.matches(&watched(&["ops", "treasury"])));
+        assert!(!treasury.matches(&watched(&["ops"])));
+    }
+
+    #[test]
+    fn unknown_filter_fields_and_tokens_are_rejected() {
+        let message = r#"{"op": "subscribe", "channel": "transfers", "filter": {"min_val": "1"}}"#;
+        assert!(serde_json::from_str::<ClientMessage>(message).is_err());
+
+        let unknown = serde_json::from_value::<Filter>(serde_json::json!({"token": "DOGE"})).unwrap();
+        assert!(unknown.resolve(&TokenSet::default(), &Labels::default()).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use axum::{extract::State, Json};
+use diesel::prelude::*;
+use ethers::abi::{self, ParamType};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use tokio::time::{interval, Duration};
+use tracing::{info, warn};
+
+use crate::{
+    config::TokenConfig,
+    database::DbPool,
+    metrics::observe_rpc,
+    models::Token,
+    schema::tokens,
+    server::{ApiQuery, ChainQuery, ServerState},
+    utils::current_timestamp,
+    IndexerError, Result,
+};
+
+/// Polygon's native token contract, the only token indexed by default
+pub const POL_ADDRESS: &str = "0x0000000000000000000000000000000000001010";
+
+/// `decimals()` and `symbol()` selectors
+const DECIMALS_SELECTOR: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+const SYMBOL_SELECTOR: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+
+/// In-memory copy of the `tokens` table, used to resolve `token` filters given as
+/// an address or a symbol, on one chain or across all of them.
+///
+/// The indexer writes the table on startup; API nodes in other processes reload
+/// it on a timer.
+#[derive(Clone, Default)]
+pub struct TokenSet {
+    /// (chain id, address) -> token
+    tokens: Arc<RwLock<HashMap<(i64, String), Token>>>,
+}
+
+impl TokenSet {
+    pub fn load(pool: &DbPool) -> Result<Self> {
+        let set = Self::default();
+        set.reload(pool)?;
+        Ok(set)
+    }
+
+    pub fn reload(&self, pool: &DbPool) -> Result<()> {
+        let mut conn = pool.get()?;
+        let rows: Vec<Token> = tokens::table.load(&mut conn)?;
+        *self.tokens.write().unwrap() = rows
+            .into_iter()
+            .map(|token| ((token.chain_id, token.address.clone()), token))
+            .collect();
+        Ok(())
+    }
+
+    /// Reloads on a timer, for an API node whose indexer runs in another process
+    pub async fn refresh_every(self, pool: DbPool, period: Duration) {
+        let mut ticker = interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.reload(&pool) {
+                warn!("⚠️ Failed to reload tokens: {}", e);
+            }
+        }
+    }
+
+    pub fn get(&self, chain_id: i64, address: &str) -> Option<Token> {
+        self.tokens.read().unwrap().get(&(chain_id, address.to_string())).cloned()
+    }
+
+    /// Known tokens on `chain_id`, or on every chain, by symbol
+    pub fn all(&self, chain_id: Option<i64>) -> Vec<Token> {
+        let mut tokens: Vec<Token> = self
+            .tokens
+            .read()
+            .unwrap()
+            .values()
+            .filter(|token| chain_id.is_none_or(|wanted| token.chain_id == wanted))
+            .cloned()
+            .collect();
+        tokens.sort_by(|a, b| {
+            a.symbol.cmp(&b.symbol).then_with(|| a.chain_id.cmp(&b.chain_id)).then_with(|| a.address.cmp(&b.address))
+        });
+        tokens
+    }
+
+    /// Symbol for display, falling back to the address for unknown tokens
+    pub fn symbol_of(&self, chain_id: i64, address: &str) -> String {
+        self.get(chain_id, address).map_or_else(|| address.to_string(), |token| token.symbol)
+    }
+
+    /// Contract addresses of a token given by address or symbol (case-insensitive), on
+    /// `chain_id` or on every chain.
+    ///
+    /// A symbol usually names a different contract on each chain, so it may resolve to
+    /// several addresses. Symbols are not unique on-chain either; one that matches two
+    /// tokens on the same chain is rejected so the caller uses the address.
+    pub fn resolve(&self, token: &str, chain_id: Option<i64>) -> Result<Vec<String>> {
+        let wanted = token.trim().to_lowercase();
+        let tokens = self.tokens.read().unwrap();
+        let on_chain = |t: &&Token| chain_id.is_none_or(|id| t.chain_id == id);
+
+        if tokens.values().filter(on_chain).any(|t| t.address == wanted) {
+            return Ok(vec![wanted]);
+        }
+
+        let matches: Vec<&Token> = tokens
+            .values()
+            .filter(on_chain)
+            .filter(|t| t.symbol.to_lowercase() == wanted)
+            .collect();
+        if matches.is_empty() {
+            return Err(IndexerError::BadRequest(match chain_id {
+                Some(id) => format!("Unknown token '{}' on chain {}", token, id),
+                None => format!("Unknown token '{}'", token),
+            }));
+        }
+        for (i, a) in matches.iter().enumerate() {
+            if let Some(b) = matches[i + 1..].iter().find(|b| b.chain_id == a.chain_id) {
+                return Err(IndexerError::BadRequest(format!(
+                    "Token symbol '{}' is ambiguous on chain {}; use {} or {}",
+                    token, a.chain_id, a.address, b.address
+                )));
+            }
+        }
+
+        let mut addresses: Vec<String> = matches.iter().map(|t| t.address.clone()).collect();
+        addresses.sort();
+        addresses.dedup();
+        Ok(addresses)
+    }
+
+    /// `resolve` for an optional filter
+    pub fn resolve_opt(&self, token: Option<&str>, chain_id: Option<i64>) -> Result<Option<Vec<String>>> {
+        token.map(|token| self.resolve(token, chain_id)).transpose()
+    }
+}
+
+/// Fills in missing symbols and decimals from the contracts and records a chain's tokens in the database
+pub async fn register_tokens(
+    provider: &Provider<Ws>,
+    pool: &DbPool,
+    chain_id: i64,
+    configured: &[TokenConfig],
+) -> Result<Vec<Token>> {
+    let mut resolved = Vec::with_capacity(configured.len());
+    for config in configured {
+        let address = Address::from_str(&config.address)
+            .map_err(|_| IndexerError::Config(format!("Invalid token address '{}'", config.address)))?;
+
+        let decimals = match config.decimals {
+            Some(decimals) => decimals,
+            None => read_decimals(provider, address).await.map_err(|e| {
+                IndexerError::Config(format!("Cannot read decimals() of {}: {}; set decimals for it in [[tokens]]", config.address, e))
+            })?,
+        };
+        let symbol = match &config.symbol {
+            Some(symbol) => symbol.clone(),
+            None => read_symbol(provider, address).await.map_err(|e| {
+                IndexerError::Config(format!("Cannot read symbol() of {}: {}; set symbol for it in [[tokens]]", config.address, e))
+            })?,
+        };
+
+        info!("🪙 Indexing {} ({}, {} decimals) on chain {}", symbol, config.address, decimals, chain_id);
+        resolved.push(Token {
+            chain_id,
+            address: config.address.clone(),
+            symbol,
+            decimals: i32::from(decimals),
+            updated_at: current_timestamp(),
+        });
+    }
+
+    let mut conn = pool.get()?;
+    for token in &resolved {
+        diesel::replace_into(tokens::table).values(token).execute(&mut conn)?;
+    }
+    Ok(resolved)
+}
+
+async fn call(provider: &Provider<Ws>, to: Address, selector: [u8; 4]) -> Result<Bytes> {
+    let request: TypedTransaction = TransactionRequest::new().to(to).data(selector.to_vec()).into();
+    observe_rpc("eth_call", provider.call(&request, None))
+        .await
+        .map_err(IndexerError::Ethereum)
+}
+
+async fn read_decimals(provider: &Provider<Ws>, token: Address) -> Result<u8> {
+    let output = call(provider, token, DECIMALS_SELECTOR).await?;
+    if output.len() < 32 {
+        return Err(IndexerError::Web3("empty response; is this an ERC-20 contract?".to_string()));
+    }
+    let decimals = U256::from_big_endian(&output[..32]);
+    if decimals > U256::from(u8::MAX) {
+        return Err(IndexerError::Web3(format!("unexpected decimals {}", decimals)));
+    }
+    Ok(decimals.low_u32() as u8)
+}
+
+/// Reads `symbol()`, which most tokens return as a string and some older ones as `bytes32`
+async fn read_symbol(provider: &Provider<Ws>, token: Address) -> Result<String> {
+    let output = call(provider, token, SYMBOL_SELECTOR).await?;
+    let symbol = match abi::decode(&[ParamType::String], &output) {
+        Ok(values) => values.into_iter().next().and_then(|value| value.into_string()).unwrap_or_default(),
+        Err(_) if output.len() == 32 => String::from_utf8_lossy(&output).trim_end_matches('\0').to_string(),
+        Err(e) => return Err(IndexerError::Web3(e.to_string())),
+    };
+    if symbol.trim().is_empty() {
+        return Err(IndexerError::Web3("empty symbol".to_string()));
+    }
+    Ok(symbol.trim().to_string())
+}
+
+/// Tokens the indexer has recorded, by symbol
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    tag = "tokens",
+    params(ChainQuery),
+    responses(
+        (status = 200, description = "Known tokens", body = Vec<Token>),
+    )
+)]
+pub async fn list_tokens(State(state): State<ServerState>, ApiQuery(query): ApiQuery<ChainQuery>) -> Json<Vec<Token>> {
+    Json(state.tokens.all(query.chain_id))
+}
@@ -1,7 +1,11 @@
-﻿use bigdecimal::BigDecimal;
+﻿use async_graphql::SimpleObject;
+use bigdecimal::BigDecimal;
 use chrono::{DateTime, Utc};
 use ethers::types::{Address, H256, U256};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{labels::Labels, models::NetFlow, utils::string_to_bigdecimal};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transfer {
@@ -15,21 +19,48 @@ pub struct Transfer {
     pub is_binance_related: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct NetFlowData {
+    pub chain_id: i64,
     pub address: String,
+    /// Token contract address
+    pub token: String,
+    /// Exchange or entity owning the address; `None` for watched wallets
+    pub entity: Option<String>,
+    #[schema(value_type = String)]
     pub net_flow: BigDecimal,
+    #[schema(value_type = String)]
     pub inflow: BigDecimal,
+    #[schema(value_type = String)]
     pub outflow: BigDecimal,
     pub transfer_count: i64,
     pub last_updated: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl NetFlowData {
+    /// A stored row with the entity its address is labelled with on its chain
+    pub fn new(row: NetFlow, labels: &Labels) -> Self {
+        Self {
+            entity: labels.entity_of(row.chain_id, &row.address),
+            chain_id: row.chain_id,
+            address: row.address,
+            token: row.token,
+            net_flow: string_to_bigdecimal(&row.net_flow),
+            inflow: string_to_bigdecimal(&row.inflow),
+            outflow: string_to_bigdecimal(&row.outflow),
+            transfer_count: row.transfer_count,
+            last_updated: DateTime::from_naive_utc_and_offset(row.last_updated, Utc),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SystemStats {
     pub total_transfers: i64,
     pub binance_transfers: i64,
+    #[schema(value_type = String)]
     pub total_volume: BigDecimal,
+    /// Last block indexed on the requested chain, or on the first configured chain
     pub current_block: i64,
     pub uptime_seconds: u64,
 }
@@ -41,6 +72,15 @@ pub struct BlockInfo {
     pub timestamp: u64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct BlockSummary {
+    pub chain_id: i64,
+    pub number: i64,
+    pub hash: String,
+    pub timestamp: DateTime<Utc>,
+    pub transfer_count: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransferEvent {
     pub from: Address,
@@ -3,7 +3,7 @@ use ethers::types::{Address, U256};
 use bigdecimal::BigDecimal;
 use std::str::FromStr;
 
-/// Known Binance addresses on Polygon
+/// Known Binance addresses on Polygon, the default labels of the Polygon chain
 pub const BINANCE_ADDRESSES: &[&str] = &[
     "0xf977814e90da44bfa03b6295a0616a897441acec", // Binance 8
     "0x3c783c21a0383057d128bae431894a5c19f9cf06", // Binance 9
@@ -17,11 +17,6 @@ pub const BINANCE_ADDRESSES: &[&str] = &[
     "0x4e9ce36e442e55ecd9025b9a6e0d88485d628a67", // Binance 19
 ];
 
-pub fn is_binance_address(address: &str) -> bool {
-    let normalized = address.to_lowercase();
-    BINANCE_ADDRESSES.iter().any(|&addr| addr == normalized)
-}
-
 pub fn format_address(address: &Address) -> String {
     format!("{:#x}", address).to_lowercase()
 }
@@ -30,14 +25,14 @@ pub fn u256_to_bigdecimal(value: U256) -> BigDecimal {
     BigDecimal::from_str(&value.to_string()).unwrap_or_default()
 }
 
-pub fn wei_to_ether(wei: U256) -> BigDecimal {
-    let wei_bd = u256_to_bigdecimal(wei);
-    let divisor = BigDecimal::from(10u64.pow(18));
-    wei_bd / divisor
+/// Scales a raw on-chain amount by the token's decimals, e.g. 1500000 with 6 decimals is 1.5
+pub fn to_token_units(raw: U256, decimals: u32) -> BigDecimal {
+    let (digits, scale) = u256_to_bigdecimal(raw).into_bigint_and_exponent();
+    BigDecimal::new(digits, scale + i64::from(decimals)).normalized()
 }
 
-pub fn generate_transfer_id(tx_hash: &str, log_index: usize) -> String {
-    format!("{}_{}", tx_hash, log_index)
+pub fn generate_transfer_id(chain_id: i64, tx_hash: &str, log_index: usize) -> String {
+    format!("{}_{}_{}", chain_id, tx_hash, log_index)
 }
 
 pub fn current_timestamp() -> NaiveDateTime {
@@ -0,0 +1,314 @@
+use bigdecimal::BigDecimal;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::{
+    database::DbPool,
+    labels::Labels,
+    models::NetFlow,
+    schema::{net_flows, transfers},
+    utils::{bigdecimal_to_string, current_timestamp, string_to_bigdecimal},
+    watchlists::WatchSet,
+    Result,
+};
+
+/// Addresses per `IN (...)` query, well under SQLite's parameter limit
+const ADDRESS_CHUNK_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FlowTotals {
+    pub inflow: BigDecimal,
+    pub outflow: BigDecimal,
+    pub net_flow: BigDecimal,
+    pub transfer_count: i64,
+}
+
+/// An address whose stored net flow in a token differs from the one recomputed from transfers
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowMismatch {
+    pub chain_id: i64,
+    pub address: String,
+    pub token: String,
+    /// `None` when the address has no `net_flows` row
+    pub stored: Option<FlowTotals>,
+    pub computed: FlowTotals,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub addresses_checked: usize,
+    pub mismatches: Vec<FlowMismatch>,
+}
+
+impl From<&NetFlow> for FlowTotals {
+    fn from(flow: &NetFlow) -> Self {
+        Self {
+            inflow: string_to_bigdecimal(&flow.inflow),
+            outflow: string_to_bigdecimal(&flow.outflow),
+            net_flow: string_to_bigdecimal(&flow.net_flow),
+            transfer_count: flow.transfer_count,
+        }
+    }
+}
+
+/// Key of a net flow: chain id, address and token
+type FlowKey = (i64, String, String);
+
+/// Recomputes the net flow of every tracked address on `chain_ids`, per chain and token,
+/// from the stored transfers and compares it with the `net_flows` table. Read-only.
+pub fn verify_net_flows(pool: &DbPool, chain_ids: &[i64], labels: &Labels, watchlists: &WatchSet) -> Result<VerifyReport> {
+    let mut conn = pool.get()?;
+
+    let stored: BTreeMap<FlowKey, NetFlow> = net_flows::table
+        .filter(net_flows::chain_id.eq_any(chain_ids))
+        .load::<NetFlow>(&mut conn)?
+        .into_iter()
+        .map(|flow| ((flow.chain_id, flow.address.clone(), flow.token.clone()), flow))
+        .collect();
+
+    let watched: HashSet<String> = watchlists.addresses().into_iter().collect();
+    let tracked: BTreeSet<String> = chain_ids
+        .iter()
+        .flat_map(|&chain_id| labels.addresses(Some(chain_id)))
+        .chain(watched.iter().cloned())
+        .chain(stored.keys().map(|(_, address, _)| address.clone()))
+        .collect();
+    let tracked: Vec<String> = tracked.into_iter().collect();
+
+    let mut computed: BTreeMap<FlowKey, FlowTotals> = BTreeMap::new();
+    for chunk in tracked.chunks(ADDRESS_CHUNK_SIZE) {
+        // Inflows and outflows are summed separately so a transfer between two
+        // tracked addresses counts once for each side, as the indexer does
+        let inflows: Vec<(i64, String, String, String)> = transfers::table
+            .filter(transfers::chain_id.eq_any(chain_ids))
+            .filter(transfers::to_address.eq_any(chunk))
+            .select((transfers::chain_id, transfers::to_address, transfers::token, transfers::value))
+            .load(&mut conn)?;
+        for (chain_id, address, token, value) in inflows {
+            let totals = computed.entry((chain_id, address, token)).or_default();
+            let value = string_to_bigdecimal(&value);
+            totals.net_flow += &value;
+            totals.inflow += value;
+            totals.transfer_count += 1;
+        }
+
+        let outflows: Vec<(i64, String, String, String)> = transfers::table
+            .filter(transfers::chain_id.eq_any(chain_ids))
+            .filter(transfers::from_address.eq_any(chunk))
+            .select((transfers::chain_id, transfers::from_address, transfers::token, transfers::value))
+            .load(&mut conn)?;
+        for (chain_id, address, token, value) in outflows {
+            let totals = computed.entry((chain_id, address, token)).or_default();
+            let value = string_to_bigdecimal(&value);
+            totals.net_flow -= &value;
+            totals.outflow += value;
+            totals.transfer_count += 1;
+        }
+    }
+
+    // A label only makes an address tracked on its own chain, so drop totals the indexer never kept
+    computed.retain(|key, _| {
+        let (chain_id, address, _) = key;
+        labels.entity_of(*chain_id, address).is_some() || watched.contains(address) || stored.contains_key(key)
+    });
+
+    // Every (chain, address, token) key with either a stored row or transfers
+    let pairs: BTreeSet<FlowKey> = stored.keys().chain(computed.keys()).cloned().collect();
+    let mut mismatches = Vec::new();
+    for key in pairs {
+        let stored = stored.get(&key).map(FlowTotals::from);
+        let computed = computed.remove(&key).unwrap_or_default();
+        if stored.as_ref() != Some(&computed) {
+            let (chain_id, address, token) = key;
+            mismatches.push(FlowMismatch {
+                chain_id,
+                address,
+                token,
+                stored,
+                computed,
+            });
+        }
+    }
+
+    Ok(VerifyReport {
+        addresses_checked: tracked.len(),
+        mismatches,
+    })
+}
+
+/// Recomputes an address's net flow in every token it moved on a chain from the transfers
+pub fn address_totals(conn: &mut SqliteConnection, chain_id: i64, address: &str) -> Result<BTreeMap<String, FlowTotals>> {
+    let inflows: Vec<(String, String)> = transfers::table
+        .filter(transfers::chain_id.eq(chain_id))
+        .filter(transfers::to_address.eq(address))
+        .select((transfers::token, transfers::value))
+        .load(conn)?;
+    let outflows: Vec<(String, String)> = transfers::table
+        .filter(transfers::chain_id.eq(chain_id))
+        .filter(transfers::from_address.eq(address))
+        .select((transfers::token, transfers::value))
+        .load(conn)?;
+
+    let mut totals: BTreeMap<String, FlowTotals> = BTreeMap::new();
+    for (token, value) in inflows {
+        let totals = totals.entry(token).or_default();
+        let value = string_to_bigdecimal(&value);
+        totals.net_flow += &value;
+        totals.inflow += value;
+        totals.transfer_count += 1;
+    }
+    for (token, value) in outflows {
+        let totals = totals.entry(token).or_default();
+        let value = string_to_bigdecimal(&value);
+        totals.net_flow -= &value;
+        totals.outflow += value;
+        totals.transfer_count += 1;
+    }
+    Ok(totals)
+}
+
+/// Writes a recomputed net flow over the stored one, or deletes the row when there are no transfers.
+/// Returns the rows changed.
+pub fn store_totals(conn: &mut SqliteConnection, chain_id: i64, address: &str, token: &str, totals: &FlowTotals) -> Result<usize> {
+    if totals.transfer_count == 0 {
+        return Ok(diesel::delete(net_flows::table.find((chain_id, address, token))).execute(conn)?);
+    }
+    Ok(diesel::replace_into(net_flows::table)
+        .values(NetFlow {
+            chain_id,
+            address: address.to_string(),
+            token: token.to_string(),
+            net_flow: bigdecimal_to_string(&totals.net_flow),
+            inflow: bigdecimal_to_string(&totals.inflow),
+            outflow: bigdecimal_to_string(&totals.outflow),
+            transfer_count: totals.transfer_count,
+            last_updated: current_timestamp(),
+        })
+        .execute(conn)?)
+}
+
+/// Rewrites the mismatched net flows on `chain_ids` from the transfers, deleting rows left
+/// without any; mismatches on other chains are left alone.
+///
+/// Each one is recomputed again inside a write transaction, so a block the indexer stores
+/// after `verify_net_flows` ran is neither lost nor counted twice. Returns the rows changed.
+pub fn repair_net_flows(pool: &DbPool, chain_ids: &[i64], mismatches: &[FlowMismatch]) -> Result<usize> {
+    let mut conn = pool.get()?;
+    conn.immediate_transaction(|conn| {
+        let mut repaired = 0;
+        for mismatch in mismatches.iter().filter(|mismatch| chain_ids.contains(&mismatch.chain_id)) {
+            let totals = address_totals(conn, mismatch.chain_id, &mismatch.address)?
+                .remove(&mismatch.token)
+                .unwrap_or_default();
+            repaired += store_totals(conn, mismatch.chain_id, &mismatch.address, &mismatch.token, &totals)?;
+        }
+        Ok(repaired)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ChainConfig, database::test_pool, models::Transfer, tokens::POL_ADDRESS};
+
+    const EXCHANGE: &str = "0x00000000000000000000000000000000000000e0";
+    const SENDER: &str = "0x000000000000000000000000000000000000000a";
+
+    /// `EXCHANGE` is labelled on Polygon and Ethereum
+    fn labels() -> Labels {
+        let chains = [ChainConfig::new("polygon", 137), ChainConfig::new("ethereum", 1)].map(|mut chain| {
+            chain.labels.insert(EXCHANGE.to_string(), "binance".to_string());
+            chain
+        });
+        Labels::from_chains(&chains)
+    }
+
+    fn store_transfer(pool: &DbPool, chain_id: i64, block: i64, from: &str, to: &str, value: &str) {
+        let transfer = Transfer {
+            id: format!("{}_0x{:02x}_0", chain_id, block),
+            chain_id,
+            block_number: block,
+            transaction_hash: format!("0x{:02x}", block),
+            from_address: from.to_string(),
+            to_address: to.to_string(),
+            token: POL_ADDRESS.to_string(),
+            value: value.to_string(),
+            timestamp: current_timestamp(),
+            is_binance_related: true,
+            is_watched: false,
+            log_index: Some(0),
+            transaction_index: Some(0),
+        };
+        diesel::insert_into(transfers::table).values(&transfer).execute(&mut pool.get().unwrap()).unwrap();
+    }
+
+    /// Stores `EXCHANGE`'s net flow on `chain_id` as `inflow` over `count` transfers
+    fn store_flow(pool: &DbPool, chain_id: i64, inflow: i64, count: i64) {
+        let totals = FlowTotals {
+            inflow: BigDecimal::from(inflow),
+            outflow: BigDecimal::from(0),
+            net_flow: BigDecimal::from(inflow),
+            transfer_count: count,
+        };
+        store_totals(&mut pool.get().unwrap(), chain_id, EXCHANGE, POL_ADDRESS, &totals).unwrap();
+    }
+
+    fn stored_inflow(pool: &DbPool, chain_id: i64) -> Option<BigDecimal> {
+        net_flows::table
+            .find((chain_id, EXCHANGE, POL_ADDRESS))
+            .select(net_flows::inflow)
+            .first::<String>(&mut pool.get().unwrap())
+            .optional()
+            .unwrap()
+            .map(|inflow| string_to_bigdecimal(&inflow))
+    }
+
+    #[test]
+    fn repairs_drifted_net_flows_on_the_chains_asked_for_only() {
+        let (_dir, pool) = test_pool();
+        let (labels, watchlists) = (labels(), WatchSet::load(&pool).unwrap());
+        store_transfer(&pool, 137, 10, SENDER, EXCHANGE, "5");
+        store_transfer(&pool, 137, 11, SENDER, EXCHANGE, "2");
+        store_transfer(&pool, 1, 10, SENDER, EXCHANGE, "3");
+        // Both chains drifted: Polygon missed a transfer, Ethereum counted one twice
+        store_flow(&pool, 137, 5, 1);
+        store_flow(&pool, 1, 6, 2);
+
+        let report = verify_net_flows(&pool, &[137], &labels, &watchlists).unwrap();
+        let found: Vec<_> = report.mismatches.iter().map(|m| (m.chain_id, m.address.as_str())).collect();
+        assert_eq!(found, [(137, EXCHANGE)]);
+        assert_eq!(report.mismatches[0].computed.inflow, BigDecimal::from(7));
+        assert_eq!(report.mismatches[0].computed.transfer_count, 2);
+
+        // Mismatches found on other chains aren't touched either
+        let everywhere = verify_net_flows(&pool, &[137, 1], &labels, &watchlists).unwrap();
+        assert_eq!(everywhere.mismatches.len(), 2);
+        assert_eq!(repair_net_flows(&pool, &[137], &everywhere.mismatches).unwrap(), 1);
+        assert_eq!(stored_inflow(&pool, 137), Some(BigDecimal::from(7)));
+        assert_eq!(stored_inflow(&pool, 1), Some(BigDecimal::from(6)));
+
+        assert!(verify_net_flows(&pool, &[137], &labels, &watchlists).unwrap().mismatches.is_empty());
+        let ethereum = verify_net_flows(&pool, &[1], &labels, &watchlists).unwrap();
+        assert_eq!(repair_net_flows(&pool, &[1], &ethereum.mismatches).unwrap(), 1);
+        assert_eq!(stored_inflow(&pool, 1), Some(BigDecimal::from(3)));
+    }
+
+    #[test]
+    fn missing_and_orphaned_rows_are_mismatches() {
+        let (_dir, pool) = test_pool();
+        let (labels, watchlists) = (labels(), WatchSet::load(&pool).unwrap());
+        // Transfers without a row on Polygon, a row without transfers on Ethereum
+        store_transfer(&pool, 137, 10, SENDER, EXCHANGE, "5");
+        store_flow(&pool, 1, 4, 1);
+
+        let report = verify_net_flows(&pool, &[137, 1], &labels, &watchlists).unwrap();
+        let found: Vec<_> = report.mismatches.iter().map(|m| (m.chain_id, m.stored.is_some())).collect();
+        assert_eq!(found, [(1, true), (137, false)]);
+
+        repair_net_flows(&pool, &[137, 1], &report.mismatches).unwrap();
+        assert_eq!(stored_inflow(&pool, 137), Some(BigDecimal::from(5)));
+        assert_eq!(stored_inflow(&pool, 1), None);
+        assert!(verify_net_flows(&pool, &[137, 1], &labels, &watchlists).unwrap().mismatches.is_empty());
+    }
+}
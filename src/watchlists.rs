@@ -0,0 +1,459 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use diesel::prelude::*;
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use tokio::time::{interval, Duration};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    database::DbPool,
+    models::{NetFlow, Transfer, Watchlist, WatchlistAddress},
+    schema::{net_flows, watchlist_addresses, watchlists},
+    server::{ApiJson, ApiQuery, ServerState, TokenQuery},
+    types::NetFlowData,
+    utils::{current_timestamp, format_address},
+    IndexerError, Result,
+};
+
+/// In-memory index of watched addresses, shared by the indexer and the API.
+///
+/// The API reloads it after every change, so the indexer picks up new addresses
+/// from the next transfer on.
+#[derive(Clone, Default)]
+pub struct WatchSet {
+    /// Address -> names of the watchlists containing it
+    addresses: Arc<RwLock<HashMap<String, Vec<String>>>>,
+}
+
+impl WatchSet {
+    pub fn load(pool: &DbPool) -> Result<Self> {
+        let set = Self::default();
+        set.reload(pool)?;
+        Ok(set)
+    }
+
+    pub fn reload(&self, pool: &DbPool) -> Result<()> {
+        let mut conn = pool.get()?;
+        let rows: Vec<(String, String)> = watchlist_addresses::table
+            .inner_join(watchlists::table)
+            .select((watchlist_addresses::address, watchlists::name))
+            .order(watchlists::name.asc())
+            .load(&mut conn)?;
+
+        let mut addresses: HashMap<String, Vec<String>> = HashMap::new();
+        for (address, name) in rows {
+            addresses.entry(address).or_default().push(name);
+        }
+        *self.addresses.write().unwrap() = addresses;
+        Ok(())
+    }
+
+    /// Reloads on a timer, for nodes whose watchlists are edited through an API in another process
+    pub async fn refresh_every(self, pool: DbPool, period: Duration) {
+        let mut ticker = interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.reload(&pool) {
+                warn!("⚠️ Failed to reload watchlists: {}", e);
+            }
+        }
+    }
+
+    pub fn is_watched(&self, address: &str) -> bool {
+        self.addresses.read().unwrap().contains_key(address)
+    }
+
+    /// Every watched address, in no particular order
+    pub fn addresses(&self) -> Vec<String> {
+        self.addresses.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Names of the watchlists containing either address, without duplicates
+    pub fn lists_for(&self, addresses: &[&str]) -> Vec<String> {
+        let index = self.addresses.read().unwrap();
+        let mut names: Vec<String> = addresses
+            .iter()
+            .filter_map(|address| index.get(*address))
+            .flatten()
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+/// A transfer touching at least one watched address, published on the `watchlists` channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedTransfer {
+    pub transfer: Transfer,
+    /// Names of the watchlists containing the sender or receiver
+    pub watchlists: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateWatchlist {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub addresses: Vec<NewWatchedAddress>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateWatchlist {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NewWatchedAddress {
+    pub address: String,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WatchlistView {
+    pub watchlist: Watchlist,
+    pub addresses: Vec<WatchlistAddress>,
+}
+
+/// All watchlists with their addresses
+#[utoipa::path(
+    get,
+    path = "/api/watchlists",
+    tag = "watchlists",
+    responses(
+        (status = 200, description = "Watchlists, by name", body = Vec<WatchlistView>),
+        (status = 503, description = "Database unavailable", body = ErrorResponse),
+    )
+)]
+pub async fn list_watchlists(State(state): State<ServerState>) -> Result<Json<Vec<WatchlistView>>> {
+    let mut conn = state.pool.get()?;
+    let lists: Vec<Watchlist> = watchlists::table.order(watchlists::name.asc()).load(&mut conn)?;
+
+    let mut members: HashMap<String, Vec<WatchlistAddress>> = HashMap::new();
+    for row in watchlist_addresses::table
+        .order(watchlist_addresses::added_at.asc())
+        .load::<WatchlistAddress>(&mut conn)?
+    {
+        members.entry(row.watchlist_id.clone()).or_default().push(row);
+    }
+
+    Ok(Json(
+        lists
+            .into_iter()
+            .map(|watchlist| WatchlistView {
+                addresses: members.remove(&watchlist.id).unwrap_or_default(),
+                watchlist,
+            })
+            .collect(),
+    ))
+}
+
+/// Creates a watchlist, optionally with its initial addresses
+#[utoipa::path(
+    post,
+    path = "/api/watchlists",
+    tag = "watchlists",
+    request_body = CreateWatchlist,
+    responses(
+        (status = 201, description = "Created", body = WatchlistView),
+        (status = 400, description = "Invalid name or address", body = ErrorResponse),
+        (status = 409, description = "Name already taken", body = ErrorResponse),
+    )
+)]
+pub async fn create_watchlist(
+    State(state): State<ServerState>,
+    ApiJson(request): ApiJson<CreateWatchlist>,
+) -> Result<(StatusCode, Json<WatchlistView>)> {
+    let name = validate_name(&request.name)?;
+    let addresses = request
+        .addresses
+        .iter()
+        .map(|entry| Ok((normalize_address(&entry.address)?, entry.label.clone())))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut conn = state.pool.get()?;
+    ensure_name_free(&mut conn, &name, None)?;
+
+    let now = current_timestamp();
+    let watchlist = Watchlist {
+        id: uuid::Uuid::new_v4().to_string(),
+        name,
+        description: request.description,
+        created_at: now,
+        updated_at: now,
+    };
+    let mut members: Vec<WatchlistAddress> = Vec::new();
+    for (address, label) in addresses {
+        if members.iter().all(|member| member.address != address) {
+            members.push(WatchlistAddress {
+                watchlist_id: watchlist.id.clone(),
+                address,
+                label,
+                added_at: now,
+            });
+        }
+    }
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::insert_into(watchlists::table).values(&watchlist).execute(conn)?;
+        diesel::insert_into(watchlist_addresses::table).values(&members).execute(conn)?;
+        Ok(())
+    })?;
+    state.watchlists.reload(&state.pool)?;
+
+    Ok((StatusCode::CREATED, Json(WatchlistView { watchlist, addresses: members })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/watchlists/{id}",
+    tag = "watchlists",
+    params(("id" = String, Path, description = "Watchlist id")),
+    responses(
+        (status = 200, description = "Watchlist with its addresses", body = WatchlistView),
+        (status = 404, description = "Unknown watchlist", body = ErrorResponse),
+    )
+)]
+pub async fn get_watchlist(State(state): State<ServerState>, Path(id): Path<String>) -> Result<Json<WatchlistView>> {
+    let mut conn = state.pool.get()?;
+    Ok(Json(load_view(&mut conn, &id)?))
+}
+
+/// Renames a watchlist or changes its description
+#[utoipa::path(
+    put,
+    path = "/api/watchlists/{id}",
+    tag = "watchlists",
+    params(("id" = String, Path, description = "Watchlist id")),
+    request_body = UpdateWatchlist,
+    responses(
+        (status = 200, description = "Updated", body = WatchlistView),
+        (status = 404, description = "Unknown watchlist", body = ErrorResponse),
+        (status = 409, description = "Name already taken", body = ErrorResponse),
+    )
+)]
+pub async fn update_watchlist(
+    State(state): State<ServerState>,
+    Path(id): Path<String>,
+    ApiJson(request): ApiJson<UpdateWatchlist>,
+) -> Result<Json<WatchlistView>> {
+    let mut conn = state.pool.get()?;
+    let mut view = load_view(&mut conn, &id)?;
+
+    if let Some(name) = &request.name {
+        let name = validate_name(name)?;
+        ensure_name_free(&mut conn, &name, Some(&id))?;
+        view.watchlist.name = name;
+    }
+    if request.description.is_some() {
+        view.watchlist.description = request.description;
+    }
+    view.watchlist.updated_at = current_timestamp();
+
+    diesel::update(watchlists::table.find(&id))
+        .set((
+            watchlists::name.eq(&view.watchlist.name),
+            watchlists::description.eq(&view.watchlist.description),
+            watchlists::updated_at.eq(view.watchlist.updated_at),
+        ))
+        .execute(&mut conn)?;
+    state.watchlists.reload(&state.pool)?;
+
+    Ok(Json(view))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/watchlists/{id}",
+    tag = "watchlists",
+    params(("id" = String, Path, description = "Watchlist id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "Unknown watchlist", body = ErrorResponse),
+    )
+)]
+pub async fn delete_watchlist(State(state): State<ServerState>, Path(id): Path<String>) -> Result<StatusCode> {
+    let mut conn = state.pool.get()?;
+    let deleted = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::delete(watchlist_addresses::table.filter(watchlist_addresses::watchlist_id.eq(&id))).execute(conn)?;
+        diesel::delete(watchlists::table.find(&id)).execute(conn)
+    })?;
+    if deleted == 0 {
+        return Err(IndexerError::NotFound(format!("Watchlist {}", id)));
+    }
+    state.watchlists.reload(&state.pool)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Adds an address to a watchlist, or updates its label if it is already there
+#[utoipa::path(
+    post,
+    path = "/api/watchlists/{id}/addresses",
+    tag = "watchlists",
+    params(("id" = String, Path, description = "Watchlist id")),
+    request_body = NewWatchedAddress,
+    responses(
+        (status = 201, description = "Added", body = WatchlistAddress),
+        (status = 400, description = "Invalid address", body = ErrorResponse),
+        (status = 404, description = "Unknown watchlist", body = ErrorResponse),
+    )
+)]
+pub async fn add_watched_address(
+    State(state): State<ServerState>,
+    Path(id): Path<String>,
+    ApiJson(request): ApiJson<NewWatchedAddress>,
+) -> Result<(StatusCode, Json<WatchlistAddress>)> {
+    let address = normalize_address(&request.address)?;
+    let mut conn = state.pool.get()?;
+    find_watchlist(&mut conn, &id)?;
+
+    let member = WatchlistAddress {
+        watchlist_id: id.clone(),
+        address: address.clone(),
+        label: request.label,
+        added_at: current_timestamp(),
+    };
+    diesel::insert_into(watchlist_addresses::table)
+        .values(&member)
+        .on_conflict((watchlist_addresses::watchlist_id, watchlist_addresses::address))
+        .do_update()
+        .set(watchlist_addresses::label.eq(&member.label))
+        .execute(&mut conn)?;
+    let member: WatchlistAddress = watchlist_addresses::table.find((&id, &address)).first(&mut conn)?;
+    diesel::update(watchlists::table.find(&id))
+        .set(watchlists::updated_at.eq(current_timestamp()))
+        .execute(&mut conn)?;
+    state.watchlists.reload(&state.pool)?;
+
+    Ok((StatusCode::CREATED, Json(member)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/watchlists/{id}/addresses/{address}",
+    tag = "watchlists",
+    params(
+        ("id" = String, Path, description = "Watchlist id"),
+        ("address" = String, Path, description = "Watched address"),
+    ),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 404, description = "Unknown watchlist or address not on it", body = ErrorResponse),
+    )
+)]
+pub async fn remove_watched_address(
+    State(state): State<ServerState>,
+    Path((id, address)): Path<(String, String)>,
+) -> Result<StatusCode> {
+    let address = normalize_address(&address)?;
+    let mut conn = state.pool.get()?;
+
+    let removed = diesel::delete(watchlist_addresses::table.find((&id, &address))).execute(&mut conn)?;
+    if removed == 0 {
+        return Err(IndexerError::NotFound(format!("{} on watchlist {}", address, id)));
+    }
+    diesel::update(watchlists::table.find(&id))
+        .set(watchlists::updated_at.eq(current_timestamp()))
+        .execute(&mut conn)?;
+    state.watchlists.reload(&state.pool)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Cumulative net flow, per chain and token, of each address on the watchlist that has moved tokens since it was added
+#[utoipa::path(
+    get,
+    path = "/api/watchlists/{id}/flows",
+    tag = "watchlists",
+    params(("id" = String, Path, description = "Watchlist id"), TokenQuery),
+    responses(
+        (status = 200, description = "Net flow per watched address, chain and token", body = Vec<NetFlowData>),
+        (status = 400, description = "Unknown token", body = ErrorResponse),
+        (status = 404, description = "Unknown watchlist", body = ErrorResponse),
+    )
+)]
+pub async fn watchlist_flows(
+    State(state): State<ServerState>,
+    Path(id): Path<String>,
+    ApiQuery(query): ApiQuery<TokenQuery>,
+) -> Result<Json<Vec<NetFlowData>>> {
+    let token_addresses = state.tokens.resolve_opt(query.token.as_deref(), query.chain_id)?;
+    let mut conn = state.pool.get()?;
+    find_watchlist(&mut conn, &id)?;
+
+    let members = watchlist_addresses::table
+        .filter(watchlist_addresses::watchlist_id.eq(&id))
+        .select(watchlist_addresses::address);
+    let mut rows = net_flows::table.filter(net_flows::address.eq_any(members)).into_boxed();
+    if let Some(chain_id) = query.chain_id {
+        rows = rows.filter(net_flows::chain_id.eq(chain_id));
+    }
+    if let Some(addrs) = token_addresses {
+        rows = rows.filter(net_flows::token.eq_any(addrs));
+    }
+    let rows: Vec<NetFlow> = rows
+        .order(net_flows::last_updated.desc())
+        .load(&mut conn)?;
+
+    Ok(Json(rows.into_iter().map(|row| NetFlowData::new(row, &state.labels)).collect()))
+}
+
+fn find_watchlist(conn: &mut SqliteConnection, id: &str) -> Result<Watchlist> {
+    watchlists::table
+        .find(id)
+        .first(conn)
+        .optional()?
+        .ok_or_else(|| IndexerError::NotFound(format!("Watchlist {}", id)))
+}
+
+fn load_view(conn: &mut SqliteConnection, id: &str) -> Result<WatchlistView> {
+    let watchlist = find_watchlist(conn, id)?;
+    let addresses = watchlist_addresses::table
+        .filter(watchlist_addresses::watchlist_id.eq(id))
+        .order(watchlist_addresses::added_at.asc())
+        .load(conn)?;
+    Ok(WatchlistView { watchlist, addresses })
+}
+
+fn ensure_name_free(conn: &mut SqliteConnection, name: &str, except_id: Option<&str>) -> Result<()> {
+    let existing: Option<String> = watchlists::table
+        .filter(watchlists::name.eq(name))
+        .select(watchlists::id)
+        .first(conn)
+        .optional()?;
+    match existing {
+        Some(existing) if Some(existing.as_str()) != except_id => {
+            Err(IndexerError::Conflict(format!("A watchlist named '{}' already exists", name)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(IndexerError::BadRequest("Watchlist name must not be empty".to_string()));
+    }
+    Ok(name.to_string())
+}
+
+fn normalize_address(address: &str) -> Result<String> {
+    Address::from_str(address.trim())
+        .map(|address| format_address(&address))
+        .map_err(|_| IndexerError::BadRequest(format!("Invalid address '{}'", address)))
+}
@@ -0,0 +1,483 @@
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use sha2::Sha256;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinSet;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use crate::{
+    alerts::Alert,
+    database::DbPool,
+    metrics::metrics,
+    models::WebhookDelivery,
+    shutdown::Shutdown,
+    utils::current_timestamp,
+    Config, IndexerError, Result,
+};
+
+/// Delay before the second attempt; doubles on every retry up to `MAX_RETRY_DELAY`
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_DELIVERED: &str = "delivered";
+pub const STATUS_FAILED: &str = "failed";
+
+/// Signed webhook delivery with retries, logged to the `webhook_deliveries` table.
+///
+/// Each POST carries `X-Webhook-Id`, `X-Webhook-Timestamp` and
+/// `X-Webhook-Signature: sha256=<hex>`, the HMAC-SHA256 of `"{timestamp}.{body}"`.
+/// A row stays `pending` until it is delivered or given up on, so deliveries cut
+/// short by a shutdown or crash are picked up again by `resume`.
+#[derive(Clone)]
+pub struct WebhookSender {
+    client: reqwest::Client,
+    pool: DbPool,
+    max_attempts: u32,
+    retry_delay: Duration,
+    /// A pending row not updated for this long has no live sender
+    stale_after: Duration,
+    tasks: Arc<Mutex<JoinSet<()>>>,
+    stopping: Shutdown,
+}
+
+/// Outcome of a single POST
+struct Attempt {
+    response_status: Option<i32>,
+    error: Option<String>,
+    retryable: bool,
+}
+
+impl WebhookSender {
+    pub fn new(config: &Config, pool: DbPool) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.webhook_timeout_secs))
+            .user_agent(concat!("polygon-indexer/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| IndexerError::Config(format!("Failed to build webhook client: {}", e)))?;
+
+        // A live delivery updates its row after every request and retry wait
+        let timeout = Duration::from_secs(config.webhook_timeout_secs);
+        Ok(Self {
+            client,
+            pool,
+            max_attempts: config.webhook_max_attempts.max(1),
+            retry_delay: BASE_RETRY_DELAY,
+            stale_after: MAX_RETRY_DELAY + timeout * 2,
+            tasks: Arc::new(Mutex::new(JoinSet::new())),
+            stopping: Shutdown::new(),
+        })
+    }
+
+    /// Records the alert as a pending delivery and sends it in the background
+    pub fn dispatch(&self, url: &str, secret: &str, alert: &Alert) -> Result<()> {
+        let now = current_timestamp();
+        let delivery = WebhookDelivery {
+            id: alert.id.clone(),
+            rule_name: alert.rule.clone(),
+            url: url.to_string(),
+            payload: serde_json::to_string(alert)?,
+            status: STATUS_PENDING.to_string(),
+            attempts: 0,
+            response_status: None,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        };
+
+        let mut conn = self.pool.get()?;
+        diesel::insert_into(crate::schema::webhook_deliveries::table)
+            .values(&delivery)
+            .execute(&mut conn)?;
+
+        self.spawn(delivery, secret.to_string());
+        Ok(())
+    }
+
+    /// Takes over pending deliveries whose sender went away, e.g. in a crash or
+    /// shutdown, and returns how many were restarted.
+    ///
+    /// Rows are claimed with a conditional update, so processes sharing the
+    /// database never send the same delivery twice at once.
+    pub fn resume(&self, secret_for: impl Fn(&str) -> Option<String>) -> Result<usize> {
+        use crate::schema::webhook_deliveries::dsl::*;
+        let mut conn = self.pool.get()?;
+        let stale_before = current_timestamp() - chrono::Duration::from_std(self.stale_after).unwrap_or_default();
+        let stale: Vec<WebhookDelivery> = webhook_deliveries
+            .filter(status.eq(STATUS_PENDING))
+            .filter(updated_at.lt(stale_before))
+            .order(created_at.asc())
+            .load(&mut conn)?;
+
+        let mut resumed = 0;
+        for delivery in stale {
+            let claimed = diesel::update(
+                webhook_deliveries
+                    .find(&delivery.id)
+                    .filter(status.eq(STATUS_PENDING))
+                    .filter(updated_at.eq(delivery.updated_at)),
+            )
+            .set(updated_at.eq(current_timestamp()))
+            .execute(&mut conn)?;
+            if claimed == 0 {
+                continue;
+            }
+
+            match secret_for(&delivery.rule_name) {
+                Some(secret) => {
+                    info!("📨 Resuming delivery of alert '{}' to {} after {} attempts",
+                          delivery.rule_name, delivery.url, delivery.attempts);
+                    self.spawn(delivery, secret);
+                    resumed += 1;
+                }
+                None => {
+                    diesel::update(webhook_deliveries.find(&delivery.id))
+                        .set((
+                            status.eq(STATUS_FAILED),
+                            last_error.eq(format!("Alert rule '{}' is no longer configured", delivery.rule_name)),
+                            updated_at.eq(current_timestamp()),
+                        ))
+                        .execute(&mut conn)?;
+                    metrics().webhook_deliveries.with_label_values(&[STATUS_FAILED]).inc();
+                }
+            }
+        }
+        Ok(resumed)
+    }
+
+    /// Stops waiting between retries and waits for requests in flight.
+    ///
+    /// Deliveries that still need another attempt are left `pending` for `resume`.
+    pub async fn drain(&self) {
+        self.stopping.trigger();
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        while tasks.join_next().await.is_some() {}
+    }
+
+    fn spawn(&self, delivery: WebhookDelivery, secret: String) {
+        if self.stopping.is_triggered() {
+            return;
+        }
+        let sender = self.clone();
+        let mut tasks = self.tasks.lock().unwrap();
+        // Finished deliveries are reaped here so the set only holds live ones
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(async move { sender.deliver(delivery, &secret).await });
+    }
+
+    async fn deliver(&self, delivery: WebhookDelivery, secret: &str) {
+        // Resumed deliveries carry on from the attempts already made
+        let mut attempt = delivery.attempts.max(0) as u32;
+
+        loop {
+            attempt += 1;
+            let outcome = self.post(&delivery, secret).await;
+            let delivered = outcome.error.is_none();
+            let finished = delivered || !outcome.retryable || attempt >= self.max_attempts;
+            let status = match (delivered, finished) {
+                (true, _) => STATUS_DELIVERED,
+                (false, true) => STATUS_FAILED,
+                (false, false) => STATUS_PENDING,
+            };
+
+            if let Err(e) = self.record_attempt(&delivery.id, attempt, status, &outcome) {
+                warn!("⚠️ Failed to record webhook delivery {}: {}", delivery.id, e);
+            }
+
+            if delivered {
+                metrics().webhook_deliveries.with_label_values(&[STATUS_DELIVERED]).inc();
+                info!("📨 Delivered alert '{}' to {} (attempt {})", delivery.rule_name, delivery.url, attempt);
+                return;
+            }
+
+            let error = outcome.error.unwrap_or_default();
+            if finished {
+                metrics().webhook_deliveries.with_label_values(&[STATUS_FAILED]).inc();
+                warn!("❌ Giving up on alert '{}' to {} after {} attempts: {}",
+                      delivery.rule_name, delivery.url, attempt, error);
+                return;
+            }
+
+            let delay = self.backoff(attempt);
+            warn!("⚠️ Webhook attempt {} for alert '{}' failed, retrying in {:?}: {}",
+                  attempt, delivery.rule_name, delay, error);
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.stopping.wait() => return,
+            }
+        }
+    }
+
+    /// Wait after the given failed attempt: `retry_delay`, doubling up to `MAX_RETRY_DELAY`
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.retry_delay.saturating_mul(factor).min(MAX_RETRY_DELAY)
+    }
+
+    async fn post(&self, delivery: &WebhookDelivery, secret: &str) -> Attempt {
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = sign(secret, timestamp, &delivery.payload);
+
+        let response = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Webhook-Id", &delivery.id)
+            .header("X-Webhook-Timestamp", timestamp.to_string())
+            .header("X-Webhook-Signature", format!("sha256={}", signature))
+            .body(delivery.payload.clone())
+            .send()
+            .await;
+
+        match response {
+            Ok(response) if response.status().is_success() => Attempt {
+                response_status: Some(i32::from(response.status().as_u16())),
+                error: None,
+                retryable: false,
+            },
+            Ok(response) => {
+                let status = response.status();
+                Attempt {
+                    response_status: Some(i32::from(status.as_u16())),
+                    error: Some(format!("Endpoint responded with {}", status)),
+                    // Other client errors won't change on retry
+                    retryable: status.is_server_error()
+                        || status == StatusCode::REQUEST_TIMEOUT
+                        || status == StatusCode::TOO_MANY_REQUESTS,
+                }
+            }
+            Err(e) => Attempt {
+                response_status: None,
+                error: Some(e.to_string()),
+                retryable: true,
+            },
+        }
+    }
+
+    fn record_attempt(&self, delivery_id: &str, attempt: u32, new_status: &str, outcome: &Attempt) -> Result<()> {
+        use crate::schema::webhook_deliveries::dsl::*;
+        let mut conn = self.pool.get()?;
+        diesel::update(webhook_deliveries.find(delivery_id))
+            .set((
+                status.eq(new_status),
+                attempts.eq(attempt as i32),
+                response_status.eq(outcome.response_status),
+                last_error.eq(outcome.error.as_deref()),
+                updated_at.eq(current_timestamp()),
+            ))
+            .execute(&mut conn)?;
+        Ok(())
+    }
+}
+
+/// Hex HMAC-SHA256 of `"{timestamp}.{body}"`, as sent in `X-Webhook-Signature`
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_pool;
+    use axum::{extract::State, http::HeaderMap, routing::post, Router};
+    use std::collections::VecDeque;
+
+    const SECRET: &str = "whsec_test";
+
+    /// Local endpoint answering with the queued statuses, repeating the last one
+    #[derive(Clone)]
+    struct StandIn {
+        statuses: Arc<Mutex<VecDeque<u16>>>,
+        received: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+    }
+
+    impl StandIn {
+        async fn start(statuses: &[u16]) -> (Self, String) {
+            let stand_in = Self {
+                statuses: Arc::new(Mutex::new(statuses.iter().copied().collect())),
+                received: Arc::new(Mutex::new(Vec::new())),
+            };
+            let app = Router::new().route("/hook", post(receive)).with_state(stand_in.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            (stand_in, url)
+        }
+
+        fn received(&self) -> Vec<(HeaderMap, String)> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    async fn receive(State(stand_in): State<StandIn>, headers: HeaderMap, body: String) -> axum::http::StatusCode {
+        stand_in.received.lock().unwrap().push((headers, body));
+        let mut statuses = stand_in.statuses.lock().unwrap();
+        let status = if statuses.len() > 1 { statuses.pop_front() } else { statuses.front().copied() };
+        axum::http::StatusCode::from_u16(status.unwrap_or(200)).unwrap()
+    }
+
+    fn sender(pool: &DbPool) -> WebhookSender {
+        let mut sender = WebhookSender::new(&Config::default(), pool.clone()).unwrap();
+        sender.retry_delay = Duration::from_millis(10);
+        sender
+    }
+
+    fn insert(pool: &DbPool, id: &str, rule: &str, url: &str, attempts: i32, updated_at: chrono::NaiveDateTime) -> WebhookDelivery {
+        let delivery = WebhookDelivery {
+            id: id.to_string(),
+            rule_name: rule.to_string(),
+            url: url.to_string(),
+            payload: format!(r#"{{"id":"{}","rule":"{}"}}"#, id, rule),
+            status: STATUS_PENDING.to_string(),
+            attempts,
+            response_status: None,
+            last_error: None,
+            created_at: updated_at,
+            updated_at,
+        };
+        diesel::insert_into(crate::schema::webhook_deliveries::table)
+            .values(&delivery)
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        delivery
+    }
+
+    fn load(pool: &DbPool, id: &str) -> WebhookDelivery {
+        crate::schema::webhook_deliveries::table
+            .find(id)
+            .first(&mut pool.get().unwrap())
+            .unwrap()
+    }
+
+    /// Waits for every spawned delivery to finish without cutting retries short
+    async fn settle(sender: &WebhookSender) {
+        let mut tasks = std::mem::take(&mut *sender.tasks.lock().unwrap());
+        while tasks.join_next().await.is_some() {}
+    }
+
+    #[test]
+    fn signature_matches_known_vector() {
+        // echo -n '1700000000.{"rule":"large"}' | openssl dgst -sha256 -hmac whsec_test
+        assert_eq!(
+            sign(SECRET, 1_700_000_000, r#"{"rule":"large"}"#),
+            "803e338bf4ec7ce0cd175655b9d078e5b6635ca2272f5e20aba40b417ff146b0"
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_until_delivered() {
+        let (_dir, pool) = test_pool();
+        let (stand_in, url) = StandIn::start(&[503, 429, 200]).await;
+        let sender = sender(&pool);
+
+        let delivery = insert(&pool, "a1", "large", &url, 0, current_timestamp());
+        sender.spawn(delivery.clone(), SECRET.to_string());
+        settle(&sender).await;
+
+        let received = stand_in.received();
+        assert_eq!(received.len(), 3);
+        for (headers, body) in &received {
+            assert_eq!(body, &delivery.payload);
+            assert_eq!(headers["x-webhook-id"], "a1");
+            let timestamp: i64 = headers["x-webhook-timestamp"].to_str().unwrap().parse().unwrap();
+            let expected = format!("sha256={}", sign(SECRET, timestamp, body));
+            assert_eq!(headers["x-webhook-signature"].to_str().unwrap(), expected);
+        }
+
+        let row = load(&pool, "a1");
+        assert_eq!(row.status, STATUS_DELIVERED);
+        assert_eq!(row.attempts, 3);
+        assert_eq!(row.response_status, Some(200));
+        assert_eq!(row.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (_dir, pool) = test_pool();
+        let (stand_in, url) = StandIn::start(&[404]).await;
+        let sender = sender(&pool);
+
+        let delivery = insert(&pool, "a1", "large", &url, 0, current_timestamp());
+        sender.spawn(delivery, SECRET.to_string());
+        settle(&sender).await;
+
+        assert_eq!(stand_in.received().len(), 1);
+        let row = load(&pool, "a1");
+        assert_eq!(row.status, STATUS_FAILED);
+        assert_eq!(row.attempts, 1);
+        assert_eq!(row.response_status, Some(404));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let (_dir, pool) = test_pool();
+        let sender = WebhookSender::new(&Config::default(), pool).unwrap();
+        assert_eq!(sender.backoff(1), BASE_RETRY_DELAY);
+        assert_eq!(sender.backoff(2), BASE_RETRY_DELAY * 2);
+        assert_eq!(sender.backoff(3), BASE_RETRY_DELAY * 4);
+        assert_eq!(sender.backoff(40), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn resume_takes_over_abandoned_deliveries() {
+        let (_dir, pool) = test_pool();
+        let (stand_in, url) = StandIn::start(&[200]).await;
+        let sender = sender(&pool);
+        let long_ago = current_timestamp() - chrono::Duration::hours(1);
+
+        insert(&pool, "abandoned", "large", &url, 2, long_ago);
+        insert(&pool, "in-flight", "large", &url, 1, current_timestamp());
+        insert(&pool, "removed-rule", "gone", &url, 1, long_ago);
+
+        let secret_for = |rule: &str| (rule == "large").then(|| SECRET.to_string());
+        assert_eq!(sender.resume(secret_for).unwrap(), 1);
+        settle(&sender).await;
+
+        let received = stand_in.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0["x-webhook-id"], "abandoned");
+
+        let abandoned = load(&pool, "abandoned");
+        assert_eq!(abandoned.status, STATUS_DELIVERED);
+        assert_eq!(abandoned.attempts, 3);
+
+        let in_flight = load(&pool, "in-flight");
+        assert_eq!(in_flight.status, STATUS_PENDING);
+        assert_eq!(in_flight.attempts, 1);
+
+        let removed = load(&pool, "removed-rule");
+        assert_eq!(removed.status, STATUS_FAILED);
+        assert!(removed.last_error.unwrap().contains("no longer configured"));
+
+        // Nothing left to claim
+        assert_eq!(sender.resume(secret_for).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn drain_leaves_due_retries_pending() {
+        let (_dir, pool) = test_pool();
+        let (stand_in, url) = StandIn::start(&[503]).await;
+        let mut sender = sender(&pool);
+        sender.retry_delay = Duration::from_secs(3600);
+
+        let delivery = insert(&pool, "a1", "large", &url, 0, current_timestamp());
+        sender.spawn(delivery.clone(), SECRET.to_string());
+        tokio::time::timeout(Duration::from_secs(10), sender.drain()).await.expect("drain cuts the retry wait short");
+
+        assert_eq!(stand_in.received().len(), 1);
+        let row = load(&pool, "a1");
+        assert_eq!(row.status, STATUS_PENDING);
+        assert_eq!(row.attempts, 1);
+
+        // Nothing new is sent once draining has started
+        sender.spawn(delivery, SECRET.to_string());
+        settle(&sender).await;
+        assert_eq!(stand_in.received().len(), 1);
+    }
+}
@@ -1,13 +1,24 @@
 // Polygon POL Indexer - Frontend JavaScript
+// The net flow totals and latest block are Polygon POL; other tokens and chains only show up in the transfer list
+const POLYGON_CHAIN_ID = 137;
+const POL_ADDRESS = '0x0000000000000000000000000000000000001010';
+
 class PolygonIndexer {
     constructor() {
-        this.isConnected = true;
+        this.isConnected = false;
+        this.socket = null;
+        this.lastSeq = null;
+        this.netFlows = new Map();
+        // Keyed by `${chain_id}:${address}`; the same address can be a different token on each chain
+        this.symbols = new Map([[`${POLYGON_CHAIN_ID}:${POL_ADDRESS}`, 'POL']]);
         this.init();
     }
 
     init() {
         this.updateConnectionStatus();
-        this.startSimulation();
+        this.loadTokens();
+        this.connect();
+        this.startClock();
         this.setupEventListeners();
     }
 
@@ -20,54 +31,125 @@ class PolygonIndexer {
         statusDot.className = this.isConnected ? 'status-dot connected' : 'status-dot';
     }
 
-    startSimulation() {
-        // Simulate real-time updates every 30 seconds
-        setInterval(() => {
-            this.updateMetrics();
-            this.addRandomTransfer();
-        }, 30000);
+    async loadTokens() {
+        try {
+            const response = await fetch('/api/tokens');
+            if (!response.ok) return;
+            for (const token of await response.json()) {
+                this.symbols.set(`${token.chain_id}:${token.address}`, token.symbol);
+            }
+        } catch (err) {
+            console.warn('Could not load token symbols', err);
+        }
+    }
+
+    connect() {
+        const protocol = window.location.protocol === 'https:' ? 'wss' : 'ws';
+        // Pick up where we left off after a reconnect
+        const resume = this.lastSeq === null ? '' : `?resume_from=${this.lastSeq}`;
+        this.socket = new WebSocket(`${protocol}://${window.location.host}/ws${resume}`);
+
+        this.socket.addEventListener('open', () => {
+            this.isConnected = true;
+            this.updateConnectionStatus();
+        });
+
+        this.socket.addEventListener('message', (message) => {
+            try {
+                this.handleEvent(JSON.parse(message.data));
+            } catch (err) {
+                console.warn('Ignoring malformed event', err);
+            }
+        });
+
+        this.socket.addEventListener('close', () => {
+            this.isConnected = false;
+            this.updateConnectionStatus();
+            // Reconnect after a short pause
+            setTimeout(() => this.connect(), 3000);
+        });
+    }
+
+    handleEvent(event) {
+        if (event.seq !== undefined) {
+            this.lastSeq = event.seq;
+        }
+
+        switch (event.type) {
+            case 'transfer':
+                this.addTransfer(event.data);
+                break;
+            case 'net_flow_update':
+                this.updateNetFlow(event.data);
+                break;
+            case 'new_block':
+                if (event.data.chain_id === POLYGON_CHAIN_ID) {
+                    document.getElementById('latestBlock').textContent = event.data.number;
+                }
+                break;
+            case 'stats_update':
+                this.updateStats(event.data);
+                break;
+            case 'gap':
+                console.warn(`Missed events ${event.data.from}-${event.data.to}`);
+                break;
+            default:
+                break;
+        }
+    }
 
+    startClock() {
         // Update timestamp every second
         setInterval(() => {
             this.updateTimestamp();
         }, 1000);
     }
 
-    updateMetrics() {
-        // Simulate small changes in net flow
-        const netFlowElement = document.getElementById('totalNetFlow');
-        const currentValue = parseFloat(netFlowElement.textContent.replace(/[^0-9.-]/g, ''));
-        const change = (Math.random() - 0.5) * 100000; // Random change ±50K
-        const newValue = currentValue + change;
+    updateNetFlow(flow) {
+        // Watched wallets have no entity; the totals are exchange flows only
+        if (flow.entity !== 'binance' || flow.chain_id !== POLYGON_CHAIN_ID || flow.token !== POL_ADDRESS) {
+            return;
+        }
+        this.netFlows.set(flow.address, flow);
+
+        let net = 0;
+        let inflow = 0;
+        let outflow = 0;
+        for (const entry of this.netFlows.values()) {
+            net += parseFloat(entry.net_flow);
+            inflow += parseFloat(entry.inflow);
+            outflow += parseFloat(entry.outflow);
+        }
+
+        document.getElementById('totalNetFlow').textContent = this.formatTokenAmount(net);
+        document.getElementById('totalInflow').textContent = this.formatTokenAmount(inflow).slice(1);
+        document.getElementById('totalOutflow').textContent = this.formatTokenAmount(outflow).slice(1);
+        document.getElementById('netFlowTrend').textContent = net >= 0 ? '📈' : '📉';
+    }
 
-        netFlowElement.textContent = this.formatTokenAmount(newValue);
+    updateStats(stats) {
+        document.getElementById('totalTransfers').textContent = stats.total_transfers.toLocaleString('en-US');
+        document.getElementById('latestBlock').textContent = stats.current_block;
     }
 
-    addRandomTransfer() {
+    addTransfer(transfer) {
         const transferList = document.getElementById('transferList');
-        const addresses = [
-            '0xF977814e90dA44bFA03b6295A0616a897441aceC',
-            '0xe7804c37c13166fF0b37F5aE0BB07A3aEbb6e245',
-            '0x505e71695E9bc45943c58adEC1650577BcA68fD9'
-        ];
-
-        const randomFrom = '0x' + Math.random().toString(16).substr(2, 40);
-        const randomTo = addresses[Math.floor(Math.random() * addresses.length)];
-        const amount = Math.floor(Math.random() * 100000) + 1000;
+        const amount = parseFloat(transfer.value);
+        const symbol = this.symbols.get(`${transfer.chain_id}:${transfer.token}`) || this.shortenAddress(transfer.token);
 
         const transferItem = document.createElement('div');
         transferItem.className = 'transfer-item fade-in';
         transferItem.innerHTML = `
-            <div class="transfer-icon">⬇️</div>
+            <div class="transfer-icon">${transfer.is_binance_related ? '🔶' : '⬇️'}</div>
             <div class="transfer-details">
-                <div class="transfer-hash">${this.shortenHash('0x' + Math.random().toString(16).substr(2, 64))}</div>
+                <div class="transfer-hash">${this.shortenHash(transfer.transaction_hash)}</div>
                 <div class="transfer-addresses">
-                    <span class="from">${this.shortenAddress(randomFrom)}</span>
+                    <span class="from">${this.shortenAddress(transfer.from_address)}</span>
                     ➡️
-                    <span class="to">${this.shortenAddress(randomTo)}</span>
+                    <span class="to">${this.shortenAddress(transfer.to_address)}</span>
                 </div>
             </div>
-            <div class="transfer-value">${this.formatTokenAmount(amount)}</div>
+            <div class="transfer-value">${this.formatTokenAmount(amount, symbol).slice(1)}</div>
             <div class="transfer-time">just now</div>
         `;
 
@@ -97,19 +179,19 @@ class PolygonIndexer {
         // Add any interactive features here
         document.addEventListener('visibilitychange', () => {
             if (!document.hidden) {
-                this.updateMetrics();
+                this.updateTimestamp();
             }
         });
     }
 
-    formatTokenAmount(amount) {
+    formatTokenAmount(amount, symbol = 'POL') {
         const value = Math.abs(amount);
         const sign = amount >= 0 ? '+' : '-';
 
-        if (value < 1000) return sign + value.toFixed(0) + ' POL';
-        if (value < 1000000) return sign + (value / 1000).toFixed(1) + 'K POL';
-        if (value < 1000000000) return sign + (value / 1000000).toFixed(2) + 'M POL';
-        return sign + (value / 1000000000).toFixed(2) + 'B POL';
+        if (value < 1000) return sign + value.toFixed(0) + ' ' + symbol;
+        if (value < 1000000) return sign + (value / 1000).toFixed(1) + 'K ' + symbol;
+        if (value < 1000000000) return sign + (value / 1000000).toFixed(2) + 'M ' + symbol;
+        return sign + (value / 1000000000).toFixed(2) + 'B ' + symbol;
     }
 
     shortenAddress(address) {